@@ -0,0 +1,89 @@
+//! Throughput of reads and writes through a client session and a server
+//! over an in-memory stream, so that only the crate itself is measured.
+//!
+//! Run with `cargo bench --bench throughput`
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use russh_sftp::{
+    client::SftpSession,
+    protocol::types::{FileAttributes, OpenFlags},
+    server::{
+        self,
+        vfs::{MemoryFs, VfsHandler},
+    },
+};
+use tokio::task::JoinSet;
+
+const CHUNK: usize = 255 * 1024;
+const CHUNKS: usize = 2048;
+const IN_FLIGHT: usize = 16;
+
+fn report(name: &str, elapsed: Duration) {
+    let mib = (CHUNK * CHUNKS) as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:<6} {:>8.0} MiB in {:>6.2?}: {:>8.1} MiB/s",
+        name,
+        mib,
+        elapsed,
+        mib / elapsed.as_secs_f64()
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let (client, server) = tokio::io::duplex(1024 * 1024);
+    let handler = VfsHandler::new(MemoryFs::default());
+    tokio::spawn(server::run_concurrent(server, handler, Default::default()));
+
+    let sftp = Arc::new(SftpSession::new(client).await.unwrap());
+    let handle = sftp
+        .open(
+            "/bench",
+            OpenFlags::READ | OpenFlags::WRITE | OpenFlags::CREATE,
+            FileAttributes::default(),
+        )
+        .await
+        .unwrap();
+
+    let chunk = vec![0x5a; CHUNK];
+    let start = Instant::now();
+    let mut tasks = JoinSet::new();
+    for i in 0..CHUNKS {
+        if tasks.len() == IN_FLIGHT {
+            tasks.join_next().await.unwrap().unwrap();
+        }
+
+        let (sftp, handle, chunk) = (sftp.clone(), handle.clone(), chunk.clone());
+        tasks.spawn(async move {
+            sftp.write(handle, (i * CHUNK) as u64, chunk).await.unwrap();
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        result.unwrap();
+    }
+    report("write", start.elapsed());
+
+    let start = Instant::now();
+    for i in 0..CHUNKS {
+        if tasks.len() == IN_FLIGHT {
+            tasks.join_next().await.unwrap().unwrap();
+        }
+
+        let (sftp, handle) = (sftp.clone(), handle.clone());
+        tasks.spawn(async move {
+            let data = sftp
+                .read(handle, (i * CHUNK) as u64, CHUNK as u32)
+                .await
+                .unwrap();
+            assert_eq!(data.len(), CHUNK);
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        result.unwrap();
+    }
+    report("read", start.elapsed());
+}
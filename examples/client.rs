@@ -1,4 +1,46 @@
+use async_trait::async_trait;
+use log::{info, LevelFilter};
+use russh::client;
+use russh_keys::key::PublicKey;
+use russh_sftp::client::SftpSession;
+use std::sync::Arc;
 
-fn main() {
-    
-}
\ No newline at end of file
+struct Client;
+
+#[async_trait]
+impl client::Handler for Client {
+    type Error = anyhow::Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &PublicKey,
+    ) -> Result<bool, Self::Error> {
+        info!("server key: {:?}", server_public_key.fingerprint());
+        Ok(true)
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::builder()
+        .filter_level(LevelFilter::Debug)
+        .init();
+
+    let config = Arc::new(client::Config::default());
+    let port = std::env::var("PORT").unwrap_or("22".to_string()).parse()?;
+    let mut session = client::connect(config, ("localhost", port), Client).await?;
+    if !session.authenticate_password("root", "password").await? {
+        anyhow::bail!("authentication failed");
+    }
+
+    let sftp = SftpSession::open_channel(&session, Default::default()).await?;
+    info!("current path: {}", sftp.realpath(".").await?);
+
+    let dir = sftp.opendir("/").await?;
+    for file in sftp.readdir(dir.clone()).await? {
+        info!("file in directory: {}", file.filename);
+    }
+    sftp.close(dir).await?;
+
+    Ok(())
+}
@@ -1,13 +1,15 @@
 use async_trait::async_trait;
 use log::{error, info, LevelFilter};
 use russh::{
-    server::{Auth, Msg, Session},
+    server::{Auth, Msg, Server as _, Session},
     Channel, ChannelId,
 };
 use russh_keys::key::KeyPair;
-use russh_sftp::protocol::{types::*, StatusCode, Status};
-use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
-use tokio::sync::Mutex;
+use russh_sftp::{
+    protocol::{types::*, Status, StatusCode},
+    server::SftpChannels,
+};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 #[derive(Clone)]
 struct Server;
@@ -20,90 +22,70 @@ impl russh::server::Server for Server {
     }
 }
 
+#[derive(Default)]
 struct SshSession {
-    clients: Arc<Mutex<HashMap<ChannelId, Channel<Msg>>>>,
-}
-
-impl Default for SshSession {
-    fn default() -> Self {
-        Self {
-            clients: Arc::new(Mutex::new(HashMap::new())),
-        }
-    }
-}
-
-impl SshSession {
-    pub async fn get_channel(&mut self, channel_id: ChannelId) -> Channel<Msg> {
-        let mut clients = self.clients.lock().await;
-        clients.remove(&channel_id).unwrap()
-    }
+    channels: SftpChannels,
 }
 
 #[async_trait]
 impl russh::server::Handler for SshSession {
     type Error = anyhow::Error;
 
-    async fn auth_password(self, user: &str, password: &str) -> Result<(Self, Auth), Self::Error> {
+    async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
         info!("credentials: {}, {}", user, password);
-        Ok((self, Auth::Accept))
+        Ok(Auth::Accept)
     }
 
     async fn auth_publickey(
-        self,
+        &mut self,
         user: &str,
         public_key: &russh_keys::key::PublicKey,
-    ) -> Result<(Self, Auth), Self::Error> {
+    ) -> Result<Auth, Self::Error> {
         info!("credentials: {}, {:?}", user, public_key);
-        Ok((self, Auth::Accept))
+        Ok(Auth::Accept)
     }
 
     async fn channel_open_session(
-        mut self,
+        &mut self,
         channel: Channel<Msg>,
-        session: Session,
-    ) -> Result<(Self, bool, Session), Self::Error> {
-        {
-            let mut clients = self.clients.lock().await;
-            clients.insert(channel.id(), channel);
-        }
-        Ok((self, true, session))
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        self.channels.insert(channel);
+        Ok(true)
+    }
+
+    async fn channel_close(
+        &mut self,
+        channel_id: ChannelId,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.channels.remove(channel_id);
+        Ok(())
     }
 
     async fn subsystem_request(
-        mut self,
+        &mut self,
         channel_id: ChannelId,
         name: &str,
-        mut session: Session,
-    ) -> Result<(Self, Session), Self::Error> {
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
         info!("subsystem: {}", name);
 
-        if name == "sftp" {
-            let channel = self.get_channel(channel_id).await;
-            let sftp = SftpSession::default();
-            session.channel_success(channel_id);
-            russh_sftp::server::run(channel.into_stream(), sftp).await;
-        } else {
-            session.channel_failure(channel_id);
-        }
+        let sftp = SftpSession::default();
+        self.channels
+            .subsystem_request(channel_id, name, session, sftp)
+            .await;
 
-        Ok((self, session))
+        Ok(())
     }
 }
 
+#[derive(Default)]
 struct SftpSession {
     version: Option<u32>,
     root_dir_read_done: bool,
 }
 
-impl Default for SftpSession {
-    fn default() -> Self {
-        Self {
-            version: None,
-            root_dir_read_done: false,
-        }
-    }
-}
-
 #[async_trait]
 impl russh_sftp::server::Handler for SftpSession {
     type Error = StatusCode;
@@ -184,23 +166,23 @@ async fn main() {
     let config = russh::server::Config {
         auth_rejection_time: Duration::from_secs(3),
         auth_rejection_time_initial: Some(Duration::from_secs(0)),
-        keys: vec![KeyPair::generate_ed25519().unwrap()],
+        keys: vec![KeyPair::generate_ed25519()],
         ..Default::default()
     };
 
-    let server = Server;
-
-    russh::server::run(
-        Arc::new(config),
-        (
-            "0.0.0.0",
-            std::env::var("PORT")
-                .unwrap_or("22".to_string())
-                .parse()
-                .unwrap(),
-        ),
-        server,
-    )
-    .await
-    .unwrap();
+    let mut server = Server;
+
+    server
+        .run_on_address(
+            Arc::new(config),
+            (
+                "0.0.0.0",
+                std::env::var("PORT")
+                    .unwrap_or("22".to_string())
+                    .parse()
+                    .unwrap(),
+            ),
+        )
+        .await
+        .unwrap();
 }
@@ -0,0 +1,235 @@
+//! Serves the local file system over the standard input and output, as a
+//! replacement of OpenSSH's `sftp-server`:
+//!
+//! ```text
+//! Subsystem sftp /usr/local/bin/russh-sftp-server -l INFO
+//! ```
+
+use std::{env, process};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use russh_sftp::server::{
+    self, implementation::SftpServerHandleImpl, vfs::LocalFs, Dispatch, Logging,
+};
+
+const USAGE: &str = "usage: russh-sftp-server [-ehR] [-d start_directory] [-f log_facility] \
+                     [-l log_level] [-u umask]";
+
+/// Settings taken from the command line, named like those of `sftp-server`
+#[derive(Debug, PartialEq)]
+struct Options {
+    start_dir: Option<String>,
+    read_only: bool,
+    umask: Option<u32>,
+    log_level: LevelFilter,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            start_dir: None,
+            read_only: false,
+            umask: None,
+            log_level: LevelFilter::Error,
+        }
+    }
+}
+
+impl Options {
+    /// Parses the arguments like getopt, so `-Rl DEBUG` and `-lDEBUG` work too.
+    /// `-e` and `-f` are accepted for compatibility, logs always go to stderr
+    fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+                return Err(format!("unexpected argument: {}", arg));
+            };
+
+            for (i, flag) in flags.char_indices() {
+                match flag {
+                    'R' => options.read_only = true,
+                    'e' => (),
+                    'h' => return Err(String::new()),
+                    'd' | 'f' | 'l' | 'u' => {
+                        let rest = &flags[i + 1..];
+                        let value = match rest.is_empty() {
+                            true => args
+                                .next()
+                                .ok_or_else(|| format!("option requires an argument: -{}", flag))?,
+                            false => rest.to_string(),
+                        };
+
+                        match flag {
+                            'd' => options.start_dir = Some(value),
+                            'l' => options.log_level = log_level(&value)?,
+                            'u' => options.umask = Some(umask(&value)?),
+                            _ => (),
+                        }
+                        break;
+                    }
+                    flag => return Err(format!("unknown option: -{}", flag)),
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Maps the log levels of OpenSSH
+fn log_level(name: &str) -> Result<LevelFilter, String> {
+    Ok(match name.to_ascii_uppercase().as_str() {
+        "QUIET" => LevelFilter::Off,
+        "FATAL" | "ERROR" => LevelFilter::Error,
+        "INFO" | "VERBOSE" => LevelFilter::Info,
+        "DEBUG" | "DEBUG1" => LevelFilter::Debug,
+        "DEBUG2" | "DEBUG3" => LevelFilter::Trace,
+        _ => return Err(format!("invalid log level: {}", name)),
+    })
+}
+
+fn umask(value: &str) -> Result<u32, String> {
+    match u32::from_str_radix(value, 8) {
+        Ok(mask) if mask <= 0o777 => Ok(mask),
+        _ => Err(format!("invalid umask: {}", value)),
+    }
+}
+
+/// Expands `%d` to the home directory and `%u` to the user name, like
+/// the `-d` option of `sftp-server`
+fn expand_dir(dir: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut chars = dir.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+
+        let var = match chars.next() {
+            Some('%') => {
+                expanded.push('%');
+                continue;
+            }
+            Some('d') => "HOME",
+            Some('u') => "USER",
+            _ => return Err(format!("invalid start directory: {}", dir)),
+        };
+        let value = env::var(var).map_err(|_| format!("{} is not set", var))?;
+        expanded.push_str(&value);
+    }
+
+    Ok(expanded)
+}
+
+/// Logs to stderr, as stdout carries the protocol
+struct Stderr;
+
+impl Log for Stderr {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("russh-sftp-server: {}: {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn setup(options: &Options) -> Result<(), String> {
+    log::set_logger(&Stderr).map_err(|err| err.to_string())?;
+    log::set_max_level(options.log_level);
+
+    #[cfg(unix)]
+    if let Some(mask) = options.umask {
+        // SAFETY: umask only replaces the mask of the process
+        unsafe { libc::umask(mask as libc::mode_t) };
+    }
+    #[cfg(not(unix))]
+    if options.umask.is_some() {
+        log::warn!("umask is not supported on this platform");
+    }
+
+    if let Some(dir) = &options.start_dir {
+        let dir = expand_dir(dir)?;
+        env::set_current_dir(&dir).map_err(|err| format!("{}: {}", dir, err))?;
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            if !err.is_empty() {
+                eprintln!("russh-sftp-server: {}", err);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    if let Err(err) = setup(&options) {
+        eprintln!("russh-sftp-server: {}", err);
+        process::exit(1);
+    }
+
+    let handler = SftpServerHandleImpl::new(LocalFs::new())
+        .with_read_only(options.read_only)
+        .layer(Logging::new().with_level(Level::Info));
+    let code = match server::run_stdio(handler).await.await {
+        Ok(()) => 0,
+        Err(err) => {
+            log::error!("{}", err);
+            1
+        }
+    };
+
+    //the runtime would wait for the blocking read of stdin
+    process::exit(code);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_options() {
+        assert_eq!(parse(&[]).unwrap(), Options::default());
+
+        let options = parse(&["-R", "-d", "/srv", "-u", "022", "-l", "debug"]).unwrap();
+        assert_eq!(
+            options,
+            Options {
+                start_dir: Some("/srv".to_string()),
+                read_only: true,
+                umask: Some(0o022),
+                log_level: LevelFilter::Debug,
+            }
+        );
+
+        //grouped flags and attached values, like getopt
+        let options = parse(&["-eRlVERBOSE", "-d%d/files", "-f", "AUTH"]).unwrap();
+        assert!(options.read_only);
+        assert_eq!(options.log_level, LevelFilter::Info);
+        assert_eq!(options.start_dir.as_deref(), Some("%d/files"));
+
+        assert!(parse(&["-x"]).is_err());
+        assert!(parse(&["-u", "999"]).is_err());
+        assert!(parse(&["-l", "LOUD"]).is_err());
+        assert!(parse(&["-d"]).is_err());
+        assert!(parse(&["dir"]).is_err());
+    }
+}
@@ -1,49 +1,26 @@
-use std::mem::size_of;
-
-use bytes::{Buf, BufMut};
+use bytes::{Buf, Bytes};
 
 use crate::error::Error;
 
 pub trait TryBuf: Buf {
-    fn try_get_u8(&mut self) -> Result<u8, Error>;
-    fn try_get_u32(&mut self) -> Result<u32, Error>;
-    fn try_get_u64(&mut self) -> Result<u64, Error>;
     fn try_get_bytes(&mut self) -> Result<Vec<u8>, Error>;
+    /// Same as [`TryBuf::try_get_bytes`] without copying out of [`Bytes`]
+    fn try_get_shared(&mut self) -> Result<Bytes, Error>;
     fn try_get_string(&mut self) -> Result<String, Error>;
 }
 
 impl<T: Buf> TryBuf for T {
-    fn try_get_u8(&mut self) -> Result<u8, Error> {
-        if self.remaining() < size_of::<u8>() {
-            return Err(Error::BadMessage);
-        }
-
-        Ok(self.get_u8())
-    }
-
-    fn try_get_u32(&mut self) -> Result<u32, Error> {
-        if self.remaining() < size_of::<u32>() {
-            return Err(Error::BadMessage);
-        }
-
-        Ok(self.get_u32())
-    }
-
-    fn try_get_u64(&mut self) -> Result<u64, Error> {
-        if self.remaining() < size_of::<u64>() {
-            return Err(Error::BadMessage);
-        }
-
-        Ok(self.get_u64())
+    fn try_get_bytes(&mut self) -> Result<Vec<u8>, Error> {
+        Ok(self.try_get_shared()?.to_vec())
     }
 
-    fn try_get_bytes(&mut self) -> Result<Vec<u8>, Error> {
+    fn try_get_shared(&mut self) -> Result<Bytes, Error> {
         let len = self.try_get_u32()? as usize;
         if self.remaining() < len {
             return Err(Error::BadMessage);
         }
 
-        Ok(self.copy_to_bytes(len).to_vec())
+        Ok(self.copy_to_bytes(len))
     }
 
     fn try_get_string(&mut self) -> Result<String, Error> {
@@ -51,16 +28,3 @@ impl<T: Buf> TryBuf for T {
         String::from_utf8(bytes).map_err(|_| Error::BadMessage)
     }
 }
-
-pub trait PutBuf: BufMut {
-    fn put_str(&mut self, str: &str);
-}
-
-impl<T: BufMut> PutBuf for T {
-    fn put_str(&mut self, str: &str) {
-        let bytes = str.as_bytes();
-
-        self.put_u32(bytes.len() as u32);
-        self.put_slice(bytes);
-    }
-}
@@ -0,0 +1,53 @@
+use russh::{
+    client::{self, Msg},
+    Channel, ChannelMsg,
+};
+
+use super::{Config, SftpSession};
+use crate::{protocol::StatusCode, server::SUBSYSTEM};
+
+impl SftpSession {
+    /// Opens a session channel on an authenticated connection and
+    /// starts the session on it, see [`SftpSession::from_channel`]
+    pub async fn open_channel<H>(
+        handle: &client::Handle<H>,
+        config: Config,
+    ) -> Result<Self, StatusCode>
+    where
+        H: client::Handler,
+    {
+        let channel = handle.channel_open_session().await.map_err(|err| {
+            warn!("{}", err);
+            StatusCode::NoConnection
+        })?;
+
+        Self::from_channel(channel, config).await
+    }
+
+    /// Requests the sftp subsystem on a session channel and starts the
+    /// session once the server accepted it. A refusal is reported as
+    /// [`StatusCode::OpUnsupported`].
+    ///
+    /// Dropping every clone of the session sends EOF, after which the
+    /// server closes the channel
+    pub async fn from_channel(
+        mut channel: Channel<Msg>,
+        config: Config,
+    ) -> Result<Self, StatusCode> {
+        channel
+            .request_subsystem(true, SUBSYSTEM)
+            .await
+            .map_err(|_| StatusCode::ConnectionLost)?;
+
+        loop {
+            match channel.wait().await {
+                Some(ChannelMsg::Success) => break,
+                Some(ChannelMsg::Failure) => return Err(StatusCode::OpUnsupported),
+                Some(_) => continue,
+                None => return Err(StatusCode::ConnectionLost),
+            }
+        }
+
+        Self::new_with_config(channel.into_stream(), config).await
+    }
+}
@@ -0,0 +1,482 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io::{self, SeekFrom},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::{Buf, Bytes};
+use tokio::{
+    io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf},
+    runtime,
+    task::{JoinError, JoinHandle},
+};
+
+use crate::protocol::{types::FileAttributes, StatusCode};
+
+use super::{Config, SftpSession};
+
+type Request<T> = JoinHandle<Result<T, StatusCode>>;
+
+fn joined<T>(result: Result<Result<T, StatusCode>, JoinError>) -> Result<T, StatusCode> {
+    result.unwrap_or(Err(StatusCode::Failure))
+}
+
+enum Seek {
+    To(u64),
+    End(i64, Option<Request<FileAttributes>>),
+}
+
+/// A file opened with [`SftpSession::open_file`], implementing
+/// [`AsyncRead`], [`AsyncWrite`] and [`AsyncSeek`] at its own offset.
+///
+/// Reads are requested ahead of the reader and writes are acknowledged
+/// before the server answers them, with as many requests in flight as
+/// set in [`Config`]. A failed write is reported by a later write,
+/// flush or shutdown. Shutting down closes the handle, and so does
+/// dropping the file, once the pending writes are done
+pub struct RemoteFile {
+    session: SftpSession,
+    handle: Option<String>,
+    pos: u64,
+    chunk_len: u32,
+    read_ahead: usize,
+    write_behind: usize,
+    /// Data already read at `pos`
+    buffer: Bytes,
+    /// Reads following `buffer`, with the length asked for
+    reads: VecDeque<(u32, Request<Bytes>)>,
+    /// Offset of the next read to request
+    read_offset: u64,
+    eof: bool,
+    writes: VecDeque<Request<()>>,
+    seek: Option<Seek>,
+    closing: Option<Request<()>>,
+}
+
+impl RemoteFile {
+    pub(crate) fn new(session: SftpSession, handle: String, config: &Config) -> Self {
+        Self {
+            session,
+            handle: Some(handle),
+            pos: 0,
+            chunk_len: config.file_chunk_len.max(1),
+            read_ahead: config.read_ahead.max(1),
+            write_behind: config.write_behind.max(1),
+            buffer: Bytes::new(),
+            reads: VecDeque::new(),
+            read_offset: 0,
+            eof: false,
+            writes: VecDeque::new(),
+            seek: None,
+            closing: None,
+        }
+    }
+
+    /// Handle given by the server, `None` once closed
+    pub fn handle(&self) -> Option<&str> {
+        self.handle.as_deref()
+    }
+
+    /// Sends SSH_FXP_FSTAT for the file
+    pub async fn metadata(&self) -> Result<FileAttributes, StatusCode> {
+        self.session.fstat(self.open_handle()?).await
+    }
+
+    /// Waits for the pending writes and closes the handle
+    pub async fn close(mut self) -> Result<(), StatusCode> {
+        while let Some(write) = self.writes.pop_front() {
+            joined(write.await)?;
+        }
+
+        match self.handle.take() {
+            Some(handle) => self.session.close(handle).await,
+            None => Ok(()),
+        }
+    }
+
+    fn open_handle(&self) -> Result<String, StatusCode> {
+        self.handle.clone().ok_or(StatusCode::InvalidHandle)
+    }
+
+    /// Drops the data read ahead, after a seek or a write
+    fn discard_reads(&mut self) {
+        self.buffer.clear();
+        self.reads.clear();
+        self.read_offset = self.pos;
+        self.eof = false;
+    }
+
+    fn set_pos(&mut self, pos: u64) {
+        match pos.checked_sub(self.pos) {
+            Some(skip) if skip < self.buffer.len() as u64 => {
+                self.buffer.advance(skip as usize);
+                self.pos = pos;
+            }
+            _ => {
+                self.pos = pos;
+                self.discard_reads();
+            }
+        }
+    }
+
+    fn poll_writes(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), StatusCode>> {
+        while let Some(write) = self.writes.front_mut() {
+            let result = ready!(Pin::new(write).poll(cx));
+            self.writes.pop_front();
+            joined(result)?;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for RemoteFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_writes(cx))?;
+
+        loop {
+            if buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            if !this.buffer.is_empty() {
+                let len = buf.remaining().min(this.buffer.len());
+                buf.put_slice(&this.buffer[..len]);
+                this.buffer.advance(len);
+                this.pos += len as u64;
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.eof {
+                return Poll::Ready(Ok(()));
+            }
+
+            while this.reads.len() < this.read_ahead {
+                let session = this.session.clone();
+                let handle = this.open_handle()?;
+                let (offset, len) = (this.read_offset, this.chunk_len);
+                let read = tokio::spawn(async move { session.read(handle, offset, len).await });
+                this.reads.push_back((len, read));
+                this.read_offset += len as u64;
+            }
+
+            let (len, read) = this.reads.front_mut().expect("reads requested above");
+            let len = *len as usize;
+            let result = ready!(Pin::new(read).poll(cx));
+            this.reads.pop_front();
+
+            match joined(result) {
+                Ok(data) if data.is_empty() => this.eof = true,
+                Ok(data) => {
+                    // the reads following a short one start past the data
+                    if data.len() < len {
+                        this.reads.clear();
+                        this.read_offset = this.pos + data.len() as u64;
+                    }
+                    this.buffer = data;
+                }
+                Err(StatusCode::Eof) => this.eof = true,
+                Err(err) => {
+                    this.discard_reads();
+                    return Poll::Ready(Err(err.into()));
+                }
+            }
+
+            if this.eof {
+                this.reads.clear();
+                this.read_offset = this.pos;
+            }
+        }
+    }
+}
+
+impl AsyncWrite for RemoteFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let handle = this.open_handle()?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        while this.writes.len() >= this.write_behind {
+            let write = this.writes.front_mut().expect("writes are pending");
+            let result = ready!(Pin::new(write).poll(cx));
+            this.writes.pop_front();
+            joined(result)?;
+        }
+
+        this.discard_reads();
+
+        let len = buf.len().min(this.chunk_len as usize);
+        let data = Bytes::copy_from_slice(&buf[..len]);
+        let session = this.session.clone();
+        let offset = this.pos;
+        this.writes.push_back(tokio::spawn(async move {
+            session.write(handle, offset, data).await
+        }));
+
+        this.pos += len as u64;
+        this.read_offset = this.pos;
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(ready!(self.get_mut().poll_writes(cx))?))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_writes(cx))?;
+
+        if this.closing.is_none() {
+            match this.handle.take() {
+                Some(handle) => {
+                    let session = this.session.clone();
+                    this.closing = Some(tokio::spawn(async move { session.close(handle).await }));
+                }
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+
+        let closing = this.closing.as_mut().expect("close requested above");
+        let result = ready!(Pin::new(closing).poll(cx));
+        this.closing = None;
+        Poll::Ready(Ok(joined(result)?))
+    }
+}
+
+impl AsyncSeek for RemoteFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let target = match position {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(delta) => this.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => {
+                this.seek = Some(Seek::End(delta, None));
+                return Ok(());
+            }
+        };
+
+        match target {
+            Some(pos) => {
+                this.seek = Some(Seek::To(pos));
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        // the size has to include the pending writes
+        if let Some(Seek::End(delta, None)) = this.seek {
+            ready!(this.poll_writes(cx))?;
+            let session = this.session.clone();
+            let handle = this.open_handle()?;
+            let fstat = tokio::spawn(async move { session.fstat(handle).await });
+            this.seek = Some(Seek::End(delta, Some(fstat)));
+        }
+
+        let pos = match this.seek.as_mut() {
+            None => return Poll::Ready(Ok(this.pos)),
+            Some(Seek::To(pos)) => Some(*pos),
+            Some(Seek::End(delta, fstat)) => {
+                let delta = *delta;
+                let fstat = fstat.as_mut().expect("fstat requested above");
+                let result = ready!(Pin::new(fstat).poll(cx));
+                this.seek = None;
+                let size = joined(result)?.size.ok_or(StatusCode::BadMessage)?;
+                size.checked_add_signed(delta)
+            }
+        };
+
+        this.seek = None;
+        match pos {
+            Some(pos) => {
+                this.set_pos(pos);
+                Poll::Ready(Ok(pos))
+            }
+            None => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            ))),
+        }
+    }
+}
+
+impl Drop for RemoteFile {
+    fn drop(&mut self) {
+        let handle = match self.handle.take() {
+            Some(handle) => handle,
+            None => return,
+        };
+
+        let writes = std::mem::take(&mut self.writes);
+        let session = self.session.clone();
+        match runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    for write in writes {
+                        if let Err(err) = joined(write.await) {
+                            warn!("write to dropped file failed: {}", err);
+                        }
+                    }
+
+                    if let Err(err) = session.close(handle).await {
+                        warn!("closing dropped file failed: {}", err);
+                    }
+                });
+            }
+            Err(_) => warn!("file dropped outside of a runtime, {} stays open", handle),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "impls"))]
+mod test {
+    use super::*;
+    use crate::{
+        protocol::types::OpenFlags,
+        server::{
+            self,
+            vfs::{MemoryFs, VfsHandler},
+        },
+    };
+    use tokio::io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    async fn session(config: Config) -> SftpSession {
+        let (client, server) = io::duplex(64 * 1024);
+        let handler = VfsHandler::new(MemoryFs::default());
+        tokio::spawn(server::run_concurrent(server, handler, Default::default()));
+        SftpSession::new_with_config(client, config).await.unwrap()
+    }
+
+    fn small_chunks() -> Config {
+        Config {
+            file_chunk_len: 1000,
+            read_ahead: 4,
+            write_behind: 4,
+            ..Default::default()
+        }
+    }
+
+    fn create() -> OpenFlags {
+        OpenFlags::READ | OpenFlags::WRITE | OpenFlags::CREATE
+    }
+
+    #[tokio::test]
+    async fn test_copy() {
+        let session = session(small_chunks()).await;
+        let data = (0..100_000u32).map(|i| i as u8).collect::<Vec<_>>();
+
+        let mut file = session
+            .open_file("a", create(), FileAttributes::default())
+            .await
+            .unwrap();
+        io::copy(&mut &data[..], &mut file).await.unwrap();
+        file.shutdown().await.unwrap();
+        assert_eq!(file.handle(), None);
+
+        let mut source = session
+            .open_file("a", OpenFlags::READ, FileAttributes::default())
+            .await
+            .unwrap();
+        let mut target = session
+            .open_file("b", create(), FileAttributes::default())
+            .await
+            .unwrap();
+        io::copy(&mut source, &mut target).await.unwrap();
+        source.close().await.unwrap();
+        target.close().await.unwrap();
+
+        let mut copied = Vec::new();
+        let mut file = session
+            .open_file("b", OpenFlags::READ, FileAttributes::default())
+            .await
+            .unwrap();
+        file.read_to_end(&mut copied).await.unwrap();
+        assert_eq!(copied, data);
+        assert_eq!(file.metadata().await.unwrap().size, Some(100_000));
+    }
+
+    #[tokio::test]
+    async fn test_seek() {
+        let session = session(small_chunks()).await;
+        let mut file = session
+            .open_file("a", create(), FileAttributes::default())
+            .await
+            .unwrap();
+        file.write_all(&[1; 5000]).await.unwrap();
+
+        assert_eq!(file.seek(SeekFrom::End(-10)).await.unwrap(), 4990);
+        file.write_all(&[2; 20]).await.unwrap();
+        assert_eq!(file.stream_position().await.unwrap(), 5010);
+
+        file.seek(SeekFrom::Start(4980)).await.unwrap();
+        let mut buf = [0; 30];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf[..10], &[1; 10]);
+        assert_eq!(&buf[10..], &[2; 20]);
+        assert_eq!(file.read(&mut buf).await.unwrap(), 0);
+
+        // within the data read ahead
+        file.seek(SeekFrom::Start(10)).await.unwrap();
+        file.read_exact(&mut buf[..10]).await.unwrap();
+        file.seek(SeekFrom::Current(500)).await.unwrap();
+        assert_eq!(file.stream_position().await.unwrap(), 520);
+        file.read_exact(&mut buf[..10]).await.unwrap();
+        assert_eq!(&buf[..10], &[1; 10]);
+
+        assert!(file.seek(SeekFrom::Current(-1000)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_drop_finishes_writes() {
+        let session = session(small_chunks()).await;
+        let mut file = session
+            .open_file("a", create(), FileAttributes::default())
+            .await
+            .unwrap();
+        file.write_all(&[7; 3500]).await.unwrap();
+        drop(file);
+
+        for _ in 0..100 {
+            if session.stat("a").await.unwrap().size == Some(3500) {
+                return;
+            }
+            tokio::task::yield_now().await;
+        }
+        panic!("writes of the dropped file were lost");
+    }
+
+    #[tokio::test]
+    async fn test_write_error() {
+        let session = session(small_chunks()).await;
+        session
+            .open("a", create(), FileAttributes::default())
+            .await
+            .unwrap();
+
+        let mut file = session
+            .open_file("a", OpenFlags::READ, FileAttributes::default())
+            .await
+            .unwrap();
+        file.write_all(&[0; 10]).await.unwrap();
+        let err = file.flush().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+}
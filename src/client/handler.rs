@@ -1,25 +1,24 @@
-use crate::protocol::{
-    types::*,
-    VERSION, StatusCode, Status
-};
+use crate::protocol::{types::*, Status, StatusError, VERSION, VERSION_MAX};
 
 /// Client handler for each client. This is [`async_trait::async_trait`]
 #[async_trait]
 pub trait Handler: Sized {
-    /// The type must have an Into<StatusCode>
+    /// The type must have an Into<StatusError>
     /// implementation because a response must be sent
     /// to any request, even if completed by error.
-    type Error: Into<StatusCode>;
+    /// [`StatusCode`](crate::protocol::StatusCode) and [`std::io::Error`] have one,
+    /// use [`StatusError`] to choose the message
+    type Error: Into<StatusError>;
 
     /// Called by the handler when the packet is not implemented
     fn unimplemented(&self) -> Self::Error;
 
-    /// The default is to send an SSH_FXP_VERSION response with
-    /// the protocol version and ignore any extensions.
-    #[allow(unused_variables)]
+    /// The default is to accept any version from [`VERSION`]
+    /// to [`VERSION_MAX`], refuse others with [`Handler::unimplemented`]
+    /// and ignore any extensions.
     async fn version(&mut self, arg: Version) -> Result<(), Self::Error> {
-        if arg.version != VERSION {
-            panic!("version mismatch: {} != {}", arg.version, VERSION);
+        if !(VERSION..=VERSION_MAX).contains(&arg.version) {
+            return Err(self.unimplemented());
         }
         Ok(())
     }
@@ -54,4 +53,3 @@ pub trait Handler: Sized {
         Err(self.unimplemented())
     }
 }
-
@@ -1,28 +1,55 @@
-use bytes::Bytes;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncWrite};
 
 use crate::{
     error::Error,
-    protocol::{Packet, RequestId, StatusCode}, handler_call,
+    framing::{read_packet, write_frame},
+    handler_call,
+    protocol::{Packet, RequestId, StatusCode, MAX_PACKET_LEN, VERSION, VERSION_MAX},
 };
 
-
+mod channel;
+mod file;
 mod handler;
-pub use self::handler::Handler;
+mod process;
+mod session;
+mod transfer;
+pub use self::{
+    file::RemoteFile,
+    handler::Handler,
+    session::SftpSession,
+    transfer::{
+        FileTransfer, Progress, ProgressFn, Resume, Symlinks, TransferOptions, TransferSummary,
+    },
+};
 #[cfg(feature = "impls")]
 pub mod implementation;
 
+/// Settings of [`SftpSession`]
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Longest packet accepted from the server. The session
+    /// is closed on a longer one. Default: [`MAX_PACKET_LEN`]
+    pub max_packet_len: u32,
+    /// Bytes asked for by each SSH_FXP_READ and sent by each
+    /// SSH_FXP_WRITE of a [`RemoteFile`]. Default: 32 KiB
+    pub file_chunk_len: u32,
+    /// SSH_FXP_READ requests a [`RemoteFile`] keeps in flight
+    /// ahead of the reader. Default: 16
+    pub read_ahead: usize,
+    /// SSH_FXP_WRITE requests a [`RemoteFile`] keeps in flight
+    /// before a write waits for the server. Default: 16
+    pub write_behind: usize,
+}
 
-async fn read_buf<S>(stream: &mut S) -> Result<Bytes, Error>
-where
-    S: AsyncRead + AsyncWrite + Unpin,
-{
-    let length = stream.read_u32().await?;
-
-    let mut buf = vec![0; length as usize];
-    stream.read_exact(&mut buf).await?;
-
-    Ok(Bytes::from(buf))
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_packet_len: MAX_PACKET_LEN,
+            file_chunk_len: 32 * 1024,
+            read_ahead: 16,
+            write_behind: 16,
+        }
+    }
 }
 
 async fn exec_response<H>(packet: Packet, processor: &mut H) -> Option<Packet>
@@ -33,7 +60,7 @@ where
         Packet::Version(version) => {
             let id = RequestId::get_request_id(&version);
             match processor.version(version).await {
-                Err(err) => Packet::error(id, err.into()),
+                Err(err) => Packet::error(id, err),
                 Ok(_) => return None,
             }
         }
@@ -46,15 +73,25 @@ where
     })
 }
 
-async fn packet_processor<H, S>(stream: &mut S, handler: &mut H) -> Result<(), Error>
+async fn packet_processor<H, S>(
+    stream: &mut S,
+    handler: &mut H,
+    version: &mut u32,
+    max_packet_len: u32,
+) -> Result<(), Error>
 where
     H: Handler + Send,
     S: AsyncRead + AsyncWrite + Unpin,
 {
-    let mut bytes = read_buf(stream).await?;
+    let mut bytes = read_packet(stream, max_packet_len).await?;
 
-    let request = match Packet::try_from(&mut bytes) {
-        Ok(response) => exec_response(response, handler).await,
+    let request = match Packet::decode(&mut bytes, *version) {
+        Ok(response) => {
+            if let Packet::Version(reply) = &response {
+                *version = reply.version.clamp(VERSION, VERSION_MAX);
+            }
+            exec_response(response, handler).await
+        }
         Err(e) => {
             warn!("error: {:?}", e);
             Some(Packet::error(0, StatusCode::BadMessage))
@@ -65,27 +102,42 @@ where
         return Ok(());
     }
 
-    let packet = Bytes::try_from(request.unwrap())?;
-    stream.write_all(&packet).await?;
+    write_frame(stream, request.unwrap().encode_frame(*version)?).await?;
 
     Ok(())
 }
 
 /// Run processing stream as SFTP
-pub async fn run<S, H>(mut stream: S, mut handler: H)
+pub async fn run<S, H>(stream: S, handler: H)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    H: Handler + Send + 'static,
+{
+    run_with_config(stream, handler, Config::default()).await
+}
+
+/// Same as [`run`] with custom settings, of which only
+/// [`Config::max_packet_len`] is used
+pub async fn run_with_config<S, H>(mut stream: S, mut handler: H, config: Config)
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
     H: Handler + Send + 'static,
 {
     tokio::spawn(async move {
+        let mut version = VERSION;
         loop {
-            match packet_processor(&mut stream, &mut handler).await {
+            let max_packet_len = config.max_packet_len;
+            match packet_processor(&mut stream, &mut handler, &mut version, max_packet_len).await {
                 Err(Error::UnexpectedEof) => break,
-                    Err(err) => warn!("{}", err),
-                    Ok(_) => (),
+                Err(err @ Error::PacketTooLarge(_)) => {
+                    warn!("{}", err);
+                    break;
+                }
+                Err(err) => warn!("{}", err),
+                Ok(_) => (),
             }
         }
 
         debug!("sftp stream ended");
     });
-}
\ No newline at end of file
+}
@@ -0,0 +1,82 @@
+use std::{
+    io,
+    pin::Pin,
+    process::Stdio,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    process::{Child, ChildStdin, ChildStdout, Command},
+};
+
+use super::{Config, SftpSession};
+
+/// Standard output and input of a child process as one stream.
+/// Shutting it down closes the input, which is EOF for the child
+struct ChildStream {
+    stdout: ChildStdout,
+    stdin: Option<ChildStdin>,
+}
+
+impl ChildStream {
+    fn stdin(&mut self) -> io::Result<Pin<&mut ChildStdin>> {
+        match &mut self.stdin {
+            Some(stdin) => Ok(Pin::new(stdin)),
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+}
+
+impl AsyncRead for ChildStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ChildStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.stdin()?.poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.stdin()?.poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.stdin.take();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl SftpSession {
+    /// Starts `command`, for example OpenSSH's `sftp-server` or
+    /// `russh-sftp-server`, and speaks SFTP over its standard input and
+    /// output. Its standard error is left as configured in `command`.
+    ///
+    /// Dropping every clone of the session closes the input of the child,
+    /// which can then be waited for. The child is killed if dropped first
+    pub async fn spawn(command: &mut Command, config: Config) -> io::Result<(Self, Child)> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let stream = ChildStream {
+            stdout: child.stdout.take().ok_or(io::ErrorKind::BrokenPipe)?,
+            stdin: child.stdin.take(),
+        };
+
+        let session = Self::new_with_config(stream, config).await?;
+        Ok((session, child))
+    }
+}
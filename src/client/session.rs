@@ -0,0 +1,1074 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use bytes::Bytes;
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, oneshot, Mutex},
+};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+
+use crate::{
+    error::Error,
+    framing::{read_packet, write_frame, Frame},
+    protocol::{types::*, Packet, StatusCode, VERSION, VERSION_MAX},
+};
+
+use super::{Config, RemoteFile};
+
+/// Entries of [`SftpSession::read_dir`] received ahead of the reader
+const READ_DIR_BUFFER: usize = 128;
+
+/// Requests waiting for a response, by request id.
+/// Becomes `None` once the stream has ended
+type Pending = Arc<Mutex<Option<HashMap<u32, oneshot::Sender<Packet>>>>>;
+
+/// Request id of a response that could not be decoded, read from
+/// the header shared by every response but SSH_FXP_VERSION
+fn raw_request_id(bytes: &[u8]) -> Option<u32> {
+    let id = bytes.get(1..5)?;
+    Some(u32::from_be_bytes(id.try_into().ok()?))
+}
+
+macro_rules! expect_packet {
+    ($packet:expr, $variant:ident) => {
+        match $packet {
+            Packet::$variant(inner) => Ok(inner),
+            Packet::Status(status) if status.status_code != StatusCode::Ok => {
+                Err(status.status_code)
+            }
+            _ => Err(StatusCode::BadMessage),
+        }
+    };
+}
+
+/// High-level client session over any stream carrying the SFTP subsystem,
+/// for example a russh [`ChannelStream`](russh::ChannelStream).
+///
+/// Every request gets its own id and is matched with the response
+/// by that id, so the session can be shared between tasks and several
+/// requests may be in flight at the same time. Errors reported by the
+/// server are returned as [`StatusCode`], while a closed stream is
+/// reported as [`StatusCode::ConnectionLost`].
+///
+/// Clones share the same session.
+#[derive(Clone)]
+pub struct SftpSession {
+    sender: mpsc::UnboundedSender<Frame>,
+    pending: Pending,
+    next_id: Arc<AtomicU32>,
+    version: Arc<Version>,
+    config: Config,
+}
+
+impl SftpSession {
+    /// Sends SSH_FXP_INIT with [`VERSION_MAX`], waits for SSH_FXP_VERSION
+    /// and starts processing responses in the background. Any version
+    /// from [`VERSION`] to [`VERSION_MAX`] offered by the server is used
+    pub async fn new<S>(stream: S) -> Result<Self, StatusCode>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        Self::new_with_config(stream, Config::default()).await
+    }
+
+    /// Same as [`SftpSession::new`] with custom settings
+    pub async fn new_with_config<S>(mut stream: S, config: Config) -> Result<Self, StatusCode>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let max_packet_len = config.max_packet_len;
+        let init = Bytes::try_from(Packet::Init(Init::new()))?;
+        stream
+            .write_all(&init)
+            .await
+            .map_err(|_| StatusCode::ConnectionLost)?;
+
+        let mut bytes = read_packet(&mut stream, max_packet_len).await?;
+        let version = match Packet::try_from(&mut bytes)? {
+            Packet::Version(version) => version,
+            _ => return Err(StatusCode::BadMessage),
+        };
+
+        if !(VERSION..=VERSION_MAX).contains(&version.version) {
+            return Err(StatusCode::OpUnsupported);
+        }
+
+        let negotiated = version.version;
+
+        let (mut reader, mut writer) = io::split(stream);
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Frame>();
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+
+        tokio::spawn(async move {
+            while let Some(packet) = receiver.recv().await {
+                if let Err(err) = write_frame(&mut writer, packet).await {
+                    warn!("{}", err);
+                    break;
+                }
+            }
+
+            let _ = writer.shutdown().await;
+        });
+
+        let responses = pending.clone();
+        tokio::spawn(async move {
+            loop {
+                let bytes = match read_packet(&mut reader, max_packet_len).await {
+                    Ok(bytes) => bytes,
+                    Err(Error::UnexpectedEof) => break,
+                    Err(err) => {
+                        warn!("{}", err);
+                        break;
+                    }
+                };
+
+                let packet = match Packet::decode(&mut bytes.clone(), negotiated) {
+                    Ok(packet) => packet,
+                    //the request still gets an answer when its id can be read
+                    Err(err) => match raw_request_id(&bytes) {
+                        Some(id) => {
+                            warn!("malformed response to request {}: {:?}", id, err);
+                            Packet::error(id, StatusCode::BadMessage)
+                        }
+                        None => {
+                            warn!("malformed response: {:?}", err);
+                            continue;
+                        }
+                    },
+                };
+
+                let id = packet.get_request_id();
+                let sender = responses
+                    .lock()
+                    .await
+                    .as_mut()
+                    .and_then(|pending| pending.remove(&id));
+
+                match sender {
+                    Some(sender) => {
+                        let _ = sender.send(packet);
+                    }
+                    None => warn!("response to unknown request {}", id),
+                }
+            }
+
+            // dropping the senders wakes up every waiting request
+            responses.lock().await.take();
+            debug!("sftp session ended");
+        });
+
+        Ok(Self {
+            sender,
+            pending,
+            next_id: Arc::new(AtomicU32::new(1)),
+            version: Arc::new(version),
+            config,
+        })
+    }
+
+    /// Protocol version used by the session, as sent by the server
+    pub fn version(&self) -> u32 {
+        self.version.version
+    }
+
+    /// Extensions announced by the server in SSH_FXP_VERSION
+    pub fn extensions(&self) -> &HashMap<String, String> {
+        &self.version.extensions
+    }
+
+    /// Sends a typed extension, if announced by the server
+    async fn request_extension<T: Extension>(&self, request: T) -> Result<Packet, StatusCode> {
+        if !self.extensions().contains_key(T::NAME) {
+            return Err(StatusCode::OpUnsupported);
+        }
+
+        self.request(Packet::Extended(Extended::new(&request)?))
+            .await
+    }
+
+    async fn request_extension_status<T: Extension>(&self, request: T) -> Result<(), StatusCode> {
+        match self.request_extension(request).await? {
+            Packet::Status(status) if status.status_code == StatusCode::Ok => Ok(()),
+            Packet::Status(status) => Err(status.status_code),
+            _ => Err(StatusCode::BadMessage),
+        }
+    }
+
+    fn require_version(&self, version: u32) -> Result<(), StatusCode> {
+        match self.version() >= version {
+            true => Ok(()),
+            false => Err(StatusCode::OpUnsupported),
+        }
+    }
+
+    fn next_id(&self) -> u32 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    async fn request(&self, packet: Packet) -> Result<Packet, StatusCode> {
+        let id = packet.get_request_id();
+        let bytes = packet.encode_frame(self.version())?;
+        let (sender, receiver) = oneshot::channel();
+
+        match self.pending.lock().await.as_mut() {
+            Some(pending) => pending.insert(id, sender),
+            None => return Err(StatusCode::ConnectionLost),
+        };
+
+        if self.sender.send(bytes).is_err() {
+            if let Some(pending) = self.pending.lock().await.as_mut() {
+                pending.remove(&id);
+            }
+            return Err(StatusCode::ConnectionLost);
+        }
+
+        receiver.await.map_err(|_| StatusCode::ConnectionLost)
+    }
+
+    async fn request_status(&self, packet: Packet) -> Result<(), StatusCode> {
+        match self.request(packet).await? {
+            Packet::Status(status) if status.status_code == StatusCode::Ok => Ok(()),
+            Packet::Status(status) => Err(status.status_code),
+            _ => Err(StatusCode::BadMessage),
+        }
+    }
+
+    /// Sends SSH_FXP_OPEN and returns the handle of the opened file
+    pub async fn open(
+        &self,
+        filename: impl Into<String>,
+        pflags: OpenFlags,
+        attrs: FileAttributes,
+    ) -> Result<String, StatusCode> {
+        let open = Open {
+            id: self.next_id(),
+            filename: filename.into(),
+            pflags,
+            attrs,
+            access: None,
+        };
+
+        expect_packet!(self.request(Packet::Open(open)).await?, Handle).map(|h| h.handle)
+    }
+
+    /// Opens a file as a [`RemoteFile`], which reads and writes
+    /// through the session
+    pub async fn open_file(
+        &self,
+        filename: impl Into<String>,
+        pflags: OpenFlags,
+        attrs: FileAttributes,
+    ) -> Result<RemoteFile, StatusCode> {
+        let handle = self.open(filename, pflags, attrs).await?;
+        Ok(RemoteFile::new(self.clone(), handle, &self.config))
+    }
+
+    /// Sends SSH_FXP_CLOSE for a file or directory handle
+    pub async fn close(&self, handle: impl Into<String>) -> Result<(), StatusCode> {
+        let close = Close {
+            id: self.next_id(),
+            handle: handle.into(),
+        };
+
+        self.request_status(Packet::Close(close)).await
+    }
+
+    /// Sends SSH_FXP_READ. [`StatusCode::Eof`] is returned
+    /// when there is no more data at `offset`
+    pub async fn read(
+        &self,
+        handle: impl Into<String>,
+        offset: u64,
+        len: u32,
+    ) -> Result<Bytes, StatusCode> {
+        let read = Read {
+            id: self.next_id(),
+            handle: handle.into(),
+            offset,
+            len,
+        };
+
+        expect_packet!(self.request(Packet::Read(read)).await?, Data).map(|d| d.data)
+    }
+
+    /// Sends SSH_FXP_WRITE
+    pub async fn write(
+        &self,
+        handle: impl Into<String>,
+        offset: u64,
+        data: impl Into<Bytes>,
+    ) -> Result<(), StatusCode> {
+        let write = Write {
+            id: self.next_id(),
+            handle: handle.into(),
+            offset,
+            data: data.into(),
+        };
+
+        self.request_status(Packet::Write(write)).await
+    }
+
+    /// Sends SSH_FXP_LSTAT, which does not follow symbolic links
+    pub async fn lstat(&self, path: impl Into<String>) -> Result<FileAttributes, StatusCode> {
+        let lstat = LStat {
+            id: self.next_id(),
+            path: path.into(),
+        };
+
+        expect_packet!(self.request(Packet::LStat(lstat)).await?, Attrs).map(|a| a.attrs)
+    }
+
+    /// Sends SSH_FXP_FSTAT
+    pub async fn fstat(&self, handle: impl Into<String>) -> Result<FileAttributes, StatusCode> {
+        let fstat = FStat {
+            id: self.next_id(),
+            handle: handle.into(),
+        };
+
+        expect_packet!(self.request(Packet::FStat(fstat)).await?, Attrs).map(|a| a.attrs)
+    }
+
+    /// Sends SSH_FXP_SETSTAT
+    pub async fn setstat(
+        &self,
+        path: impl Into<String>,
+        attrs: FileAttributes,
+    ) -> Result<(), StatusCode> {
+        let setstat = SetStat {
+            id: self.next_id(),
+            path: path.into(),
+            attrs,
+        };
+
+        self.request_status(Packet::SetStat(setstat)).await
+    }
+
+    /// Sends SSH_FXP_FSETSTAT
+    pub async fn fsetstat(
+        &self,
+        handle: impl Into<String>,
+        attrs: FileAttributes,
+    ) -> Result<(), StatusCode> {
+        let fsetstat = FSetStat {
+            id: self.next_id(),
+            handle: handle.into(),
+            attrs,
+        };
+
+        self.request_status(Packet::FSetStat(fsetstat)).await
+    }
+
+    /// Sends SSH_FXP_OPENDIR and returns the handle of the directory
+    pub async fn opendir(&self, path: impl Into<String>) -> Result<String, StatusCode> {
+        let opendir = OpenDir {
+            id: self.next_id(),
+            path: path.into(),
+        };
+
+        expect_packet!(self.request(Packet::OpenDir(opendir)).await?, Handle).map(|h| h.handle)
+    }
+
+    /// Sends SSH_FXP_READDIR. [`StatusCode::Eof`] is returned
+    /// when all entries have been read
+    pub async fn readdir(&self, handle: impl Into<String>) -> Result<Vec<File>, StatusCode> {
+        let readdir = ReadDir {
+            id: self.next_id(),
+            handle: handle.into(),
+        };
+
+        expect_packet!(self.request(Packet::ReadDir(readdir)).await?, Name).map(|n| n.files)
+    }
+
+    /// Lists a directory as a stream of entries. SSH_FXP_READDIR is sent
+    /// as the stream is consumed, and the handle is closed once all entries
+    /// are read or the stream is dropped. Failures end the stream
+    pub async fn read_dir(
+        &self,
+        path: impl Into<String>,
+    ) -> Result<impl Stream<Item = Result<File, StatusCode>> + Send + Unpin, StatusCode> {
+        let handle = self.opendir(path).await?;
+        let session = self.clone();
+        let (sender, receiver) = mpsc::channel(READ_DIR_BUFFER);
+
+        tokio::spawn(async move {
+            let error = 'listing: loop {
+                match session.readdir(handle.as_str()).await {
+                    Ok(files) if files.is_empty() => break None,
+                    Ok(files) => {
+                        for file in files {
+                            if sender.send(Ok(file)).await.is_err() {
+                                break 'listing None;
+                            }
+                        }
+                    }
+                    Err(StatusCode::Eof) => break None,
+                    Err(err) => break Some(err),
+                }
+            };
+
+            let closed = session.close(handle).await;
+            if let Some(err) = error.or(closed.err()) {
+                let _ = sender.send(Err(err)).await;
+            }
+        });
+
+        Ok(ReceiverStream::new(receiver))
+    }
+
+    /// Sends SSH_FXP_REMOVE
+    pub async fn remove(&self, filename: impl Into<String>) -> Result<(), StatusCode> {
+        let remove = Remove {
+            id: self.next_id(),
+            filename: filename.into(),
+        };
+
+        self.request_status(Packet::Remove(remove)).await
+    }
+
+    /// Sends SSH_FXP_MKDIR
+    pub async fn mkdir(
+        &self,
+        path: impl Into<String>,
+        attrs: FileAttributes,
+    ) -> Result<(), StatusCode> {
+        let mkdir = MkDir {
+            id: self.next_id(),
+            path: path.into(),
+            attrs,
+        };
+
+        self.request_status(Packet::MkDir(mkdir)).await
+    }
+
+    /// Sends SSH_FXP_RMDIR
+    pub async fn rmdir(&self, path: impl Into<String>) -> Result<(), StatusCode> {
+        let rmdir = RmDir {
+            id: self.next_id(),
+            path: path.into(),
+        };
+
+        self.request_status(Packet::RmDir(rmdir)).await
+    }
+
+    /// Sends SSH_FXP_REALPATH and returns the canonical path
+    pub async fn realpath(&self, path: impl Into<String>) -> Result<String, StatusCode> {
+        let realpath = RealPath {
+            id: self.next_id(),
+            path: path.into(),
+        };
+
+        let name = expect_packet!(self.request(Packet::RealPath(realpath)).await?, Name)?;
+        name.files
+            .into_iter()
+            .next()
+            .map(|f| f.filename)
+            .ok_or(StatusCode::BadMessage)
+    }
+
+    /// Sends SSH_FXP_STAT, which follows symbolic links
+    pub async fn stat(&self, path: impl Into<String>) -> Result<FileAttributes, StatusCode> {
+        let stat = Stat {
+            id: self.next_id(),
+            path: path.into(),
+        };
+
+        expect_packet!(self.request(Packet::Stat(stat)).await?, Attrs).map(|a| a.attrs)
+    }
+
+    /// Sends SSH_FXP_RENAME
+    pub async fn rename(
+        &self,
+        oldpath: impl Into<String>,
+        newpath: impl Into<String>,
+    ) -> Result<(), StatusCode> {
+        let rename = Rename {
+            id: self.next_id(),
+            oldpath: oldpath.into(),
+            newpath: newpath.into(),
+            flags: RenameFlags::empty(),
+        };
+
+        self.request_status(Packet::Rename(rename)).await
+    }
+
+    /// Sends SSH_FXP_READLINK and returns the target of the link
+    pub async fn readlink(&self, path: impl Into<String>) -> Result<String, StatusCode> {
+        let readlink = ReadLink {
+            id: self.next_id(),
+            path: path.into(),
+        };
+
+        let name = expect_packet!(self.request(Packet::ReadLink(readlink)).await?, Name)?;
+        name.files
+            .into_iter()
+            .next()
+            .map(|f| f.filename)
+            .ok_or(StatusCode::BadMessage)
+    }
+
+    /// Sends SSH_FXP_SYMLINK
+    pub async fn symlink(
+        &self,
+        linkpath: impl Into<String>,
+        targetpath: impl Into<String>,
+    ) -> Result<(), StatusCode> {
+        let symlink = Symlink {
+            id: self.next_id(),
+            linkpath: linkpath.into(),
+            targetpath: targetpath.into(),
+        };
+
+        self.request_status(Packet::Symlink(symlink)).await
+    }
+
+    /// Sends SSH_FXP_LINK, which needs version 6
+    pub async fn link(
+        &self,
+        new_link_path: impl Into<String>,
+        existing_path: impl Into<String>,
+        symlink: bool,
+    ) -> Result<(), StatusCode> {
+        self.require_version(6)?;
+        let link = Link {
+            id: self.next_id(),
+            new_link_path: new_link_path.into(),
+            existing_path: existing_path.into(),
+            symlink,
+        };
+
+        self.request_status(Packet::Link(link)).await
+    }
+
+    /// Sends SSH_FXP_BLOCK, which needs version 6
+    pub async fn block(
+        &self,
+        handle: impl Into<String>,
+        offset: u64,
+        length: u64,
+        lock_mask: OpenFlagsV5,
+    ) -> Result<(), StatusCode> {
+        self.require_version(6)?;
+        let block = Block {
+            id: self.next_id(),
+            handle: handle.into(),
+            offset,
+            length,
+            lock_mask,
+        };
+
+        self.request_status(Packet::Block(block)).await
+    }
+
+    /// Sends SSH_FXP_UNBLOCK, which needs version 6
+    pub async fn unblock(
+        &self,
+        handle: impl Into<String>,
+        offset: u64,
+        length: u64,
+    ) -> Result<(), StatusCode> {
+        self.require_version(6)?;
+        let unblock = Unblock {
+            id: self.next_id(),
+            handle: handle.into(),
+            offset,
+            length,
+        };
+
+        self.request_status(Packet::Unblock(unblock)).await
+    }
+
+    /// Sends SSH_FXP_EXTENDED and returns the data of SSH_FXP_EXTENDED_REPLY
+    pub async fn extended(
+        &self,
+        request: impl Into<String>,
+        data: Vec<u8>,
+    ) -> Result<Vec<u8>, StatusCode> {
+        let extended = Extended {
+            id: self.next_id(),
+            request: request.into(),
+            data,
+        };
+
+        expect_packet!(
+            self.request(Packet::Extended(extended)).await?,
+            ExtendedReply
+        )
+        .map(|r| r.data)
+    }
+
+    /// Sends `posix-rename@openssh.com`, replacing `newpath` if it exists
+    pub async fn posix_rename(
+        &self,
+        oldpath: impl Into<String>,
+        newpath: impl Into<String>,
+    ) -> Result<(), StatusCode> {
+        let rename = PosixRename {
+            id: self.next_id(),
+            oldpath: oldpath.into(),
+            newpath: newpath.into(),
+        };
+
+        self.request_extension_status(rename).await
+    }
+
+    /// Sends `statvfs@openssh.com`
+    pub async fn statvfs(&self, path: impl Into<String>) -> Result<StatvfsReply, StatusCode> {
+        let statvfs = Statvfs {
+            id: self.next_id(),
+            path: path.into(),
+        };
+
+        let reply = expect_packet!(self.request_extension(statvfs).await?, ExtendedReply)?;
+        Ok(reply.parse()?)
+    }
+
+    /// Sends `fstatvfs@openssh.com`
+    pub async fn fstatvfs(&self, handle: impl Into<String>) -> Result<StatvfsReply, StatusCode> {
+        let fstatvfs = Fstatvfs {
+            id: self.next_id(),
+            handle: handle.into(),
+        };
+
+        let reply = expect_packet!(self.request_extension(fstatvfs).await?, ExtendedReply)?;
+        Ok(reply.parse()?)
+    }
+
+    /// Sends `hardlink@openssh.com`, creating `newpath` as a link to `oldpath`
+    pub async fn hardlink(
+        &self,
+        oldpath: impl Into<String>,
+        newpath: impl Into<String>,
+    ) -> Result<(), StatusCode> {
+        let hardlink = Hardlink {
+            id: self.next_id(),
+            oldpath: oldpath.into(),
+            newpath: newpath.into(),
+        };
+
+        self.request_extension_status(hardlink).await
+    }
+
+    /// Sends `fsync@openssh.com`
+    pub async fn fsync(&self, handle: impl Into<String>) -> Result<(), StatusCode> {
+        let fsync = Fsync {
+            id: self.next_id(),
+            handle: handle.into(),
+        };
+
+        self.request_extension_status(fsync).await
+    }
+
+    /// Sends `lsetstat@openssh.com`
+    pub async fn lsetstat(
+        &self,
+        path: impl Into<String>,
+        attrs: FileAttributes,
+    ) -> Result<(), StatusCode> {
+        let lsetstat = LSetStat {
+            id: self.next_id(),
+            path: path.into(),
+            attrs,
+        };
+
+        self.request_extension_status(lsetstat).await
+    }
+
+    /// Sends `limits@openssh.com`
+    pub async fn limits(&self) -> Result<LimitsReply, StatusCode> {
+        let limits = Limits { id: self.next_id() };
+        let reply = expect_packet!(self.request_extension(limits).await?, ExtendedReply)?;
+        Ok(reply.parse()?)
+    }
+
+    /// Sends `expand-path@openssh.com` and returns the expanded path
+    pub async fn expand_path(&self, path: impl Into<String>) -> Result<String, StatusCode> {
+        let expand_path = ExpandPath {
+            id: self.next_id(),
+            path: path.into(),
+        };
+
+        expect_packet!(self.request_extension(expand_path).await?, Name)?
+            .files
+            .into_iter()
+            .next()
+            .map(|f| f.filename)
+            .ok_or(StatusCode::BadMessage)
+    }
+
+    /// Sends `copy-data`, copying `length` bytes between two open files on
+    /// the server, or until the end of the file when `length` is zero
+    pub async fn copy_data(
+        &self,
+        read_from_handle: impl Into<String>,
+        read_from_offset: u64,
+        length: u64,
+        write_to_handle: impl Into<String>,
+        write_to_offset: u64,
+    ) -> Result<(), StatusCode> {
+        let copy_data = CopyData {
+            id: self.next_id(),
+            read_from_handle: read_from_handle.into(),
+            read_from_offset,
+            read_data_length: length,
+            write_to_handle: write_to_handle.into(),
+            write_to_offset,
+        };
+
+        self.request_extension_status(copy_data).await
+    }
+
+    /// Sends `check-file-name`, hashing `length` bytes of a file from
+    /// `start_offset` with the first algorithm of the comma separated
+    /// `hash_algorithms` known to the server. A `length` of zero hashes until
+    /// the end of the file, a `block_size` of zero hashes the range at once
+    pub async fn check_file_name(
+        &self,
+        filename: impl Into<String>,
+        hash_algorithms: impl Into<String>,
+        start_offset: u64,
+        length: u64,
+        block_size: u32,
+    ) -> Result<CheckFileReply, StatusCode> {
+        let check_file = CheckFileName {
+            id: self.next_id(),
+            filename: filename.into(),
+            hash_algorithms: hash_algorithms.into(),
+            start_offset,
+            length,
+            block_size,
+        };
+
+        let reply = expect_packet!(self.request_extension(check_file).await?, ExtendedReply)?;
+        Ok(CheckFileReply::try_from(reply)?)
+    }
+
+    /// Sends `check-file-handle`, the same as
+    /// [`SftpSession::check_file_name`] for an open file
+    pub async fn check_file_handle(
+        &self,
+        handle: impl Into<String>,
+        hash_algorithms: impl Into<String>,
+        start_offset: u64,
+        length: u64,
+        block_size: u32,
+    ) -> Result<CheckFileReply, StatusCode> {
+        let check_file = CheckFileHandle {
+            id: self.next_id(),
+            handle: handle.into(),
+            hash_algorithms: hash_algorithms.into(),
+            start_offset,
+            length,
+            block_size,
+        };
+
+        let reply = expect_packet!(self.request_extension(check_file).await?, ExtendedReply)?;
+        Ok(CheckFileReply::try_from(reply)?)
+    }
+
+    /// Sends `md5-hash`, returning the MD5 of `length` bytes of a file from
+    /// `start_offset`, or until the end of the file when `length` is zero.
+    /// The hash is empty if a non-empty `quick_check_hash` is not the one
+    /// of the first 2048 bytes of the range
+    pub async fn md5_hash(
+        &self,
+        filename: impl Into<String>,
+        start_offset: u64,
+        length: u64,
+        quick_check_hash: Vec<u8>,
+    ) -> Result<Vec<u8>, StatusCode> {
+        let md5_hash = Md5Hash {
+            id: self.next_id(),
+            filename: filename.into(),
+            start_offset,
+            length,
+            quick_check_hash,
+        };
+
+        let reply = expect_packet!(self.request_extension(md5_hash).await?, ExtendedReply)?;
+        Ok(Md5HashReply::try_from(reply)?.hash)
+    }
+
+    /// Sends `md5-hash-handle`, the same as
+    /// [`SftpSession::md5_hash`] for an open file
+    pub async fn md5_hash_handle(
+        &self,
+        handle: impl Into<String>,
+        start_offset: u64,
+        length: u64,
+        quick_check_hash: Vec<u8>,
+    ) -> Result<Vec<u8>, StatusCode> {
+        let md5_hash = Md5HashHandle {
+            id: self.next_id(),
+            handle: handle.into(),
+            start_offset,
+            length,
+            quick_check_hash,
+        };
+
+        let reply = expect_packet!(self.request_extension(md5_hash).await?, ExtendedReply)?;
+        Ok(Md5HashReply::try_from(reply)?.hash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio_stream::StreamExt;
+
+    use super::*;
+    use crate::{
+        protocol::{Status, MAX_PACKET_LEN},
+        server,
+    };
+
+    #[derive(Default)]
+    struct MemoryServer {
+        files: HashMap<String, Vec<u8>>,
+        dir_read: bool,
+    }
+
+    #[async_trait]
+    impl server::Handler for MemoryServer {
+        type Error = StatusCode;
+
+        fn unimplemented(&self) -> Self::Error {
+            StatusCode::OpUnsupported
+        }
+
+        async fn open(&mut self, arg: Open) -> Result<Handle, Self::Error> {
+            self.files.entry(arg.filename.clone()).or_default();
+            Ok(Handle {
+                id: arg.id,
+                handle: arg.filename,
+            })
+        }
+
+        async fn close(&mut self, arg: Close) -> Result<Status, Self::Error> {
+            Ok(Status::new(arg.id, StatusCode::Ok, "Ok"))
+        }
+
+        async fn read(&mut self, arg: Read) -> Result<Data, Self::Error> {
+            let file = self.files.get(&arg.handle).ok_or(StatusCode::NoSuchFile)?;
+            let offset = (arg.offset as usize).min(file.len());
+            if offset == file.len() {
+                return Err(StatusCode::Eof);
+            }
+
+            let end = (offset + arg.len as usize).min(file.len());
+            Ok(Data {
+                id: arg.id,
+                data: Bytes::copy_from_slice(&file[offset..end]),
+            })
+        }
+
+        async fn write(&mut self, arg: Write) -> Result<Status, Self::Error> {
+            let file = self
+                .files
+                .get_mut(&arg.handle)
+                .ok_or(StatusCode::NoSuchFile)?;
+            let offset = arg.offset as usize;
+            if file.len() < offset + arg.data.len() {
+                file.resize(offset + arg.data.len(), 0);
+            }
+            file[offset..offset + arg.data.len()].copy_from_slice(&arg.data);
+            Ok(Status::new(arg.id, StatusCode::Ok, "Ok"))
+        }
+
+        async fn stat(&mut self, arg: Stat) -> Result<Attrs, Self::Error> {
+            let file = self.files.get(&arg.path).ok_or(StatusCode::NoSuchFile)?;
+            Ok(Attrs {
+                id: arg.id,
+                attrs: FileAttributes {
+                    size: Some(file.len() as u64),
+                    ..Default::default()
+                },
+            })
+        }
+
+        async fn opendir(&mut self, arg: OpenDir) -> Result<Handle, Self::Error> {
+            self.dir_read = false;
+            Ok(Handle {
+                id: arg.id,
+                handle: arg.path,
+            })
+        }
+
+        async fn readdir(&mut self, arg: ReadDir) -> Result<Name, Self::Error> {
+            if self.dir_read {
+                return Err(StatusCode::Eof);
+            }
+
+            self.dir_read = true;
+            let mut filenames = self.files.keys().cloned().collect::<Vec<_>>();
+            filenames.sort();
+            Ok(Name {
+                id: arg.id,
+                files: filenames
+                    .into_iter()
+                    .map(|filename| File {
+                        filename,
+                        longname: String::new(),
+                        attrs: FileAttributes::default(),
+                    })
+                    .collect(),
+            })
+        }
+
+        async fn remove(&mut self, arg: Remove) -> Result<Status, Self::Error> {
+            self.files
+                .remove(&arg.filename)
+                .ok_or(StatusCode::NoSuchFile)?;
+            Ok(Status::new(arg.id, StatusCode::Ok, "Ok"))
+        }
+
+        async fn rename(&mut self, arg: Rename) -> Result<Status, Self::Error> {
+            let file = self
+                .files
+                .remove(&arg.oldpath)
+                .ok_or(StatusCode::NoSuchFile)?;
+            self.files.insert(arg.newpath, file);
+            Ok(Status::new(arg.id, StatusCode::Ok, "Ok"))
+        }
+    }
+
+    async fn session() -> SftpSession {
+        let (client, server) = io::duplex(4096);
+        server::run(server, MemoryServer::default()).await;
+        SftpSession::new(client).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_session() {
+        let session = session().await;
+        assert_eq!(session.version(), VERSION_MAX);
+
+        let handle = session
+            .open("a", OpenFlags::WRITE, FileAttributes::default())
+            .await
+            .unwrap();
+        session
+            .write(handle.as_str(), 0, b"hello".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(
+            session.read(handle.as_str(), 1, 3).await.unwrap(),
+            &b"ell"[..]
+        );
+        assert_eq!(
+            session.read(handle.as_str(), 5, 3).await,
+            Err(StatusCode::Eof)
+        );
+        session.close(handle).await.unwrap();
+
+        assert_eq!(session.stat("a").await.unwrap().size, Some(5));
+        session.rename("a", "b").await.unwrap();
+        assert_eq!(session.stat("a").await.unwrap_err(), StatusCode::NoSuchFile);
+
+        let files = session
+            .read_dir("/")
+            .await
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .await
+            .unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].filename, "b");
+
+        session.remove("b").await.unwrap();
+        assert_eq!(session.rmdir("b").await, Err(StatusCode::OpUnsupported));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests() {
+        let session = Arc::new(session().await);
+        let handle = session
+            .open("a", OpenFlags::WRITE, FileAttributes::default())
+            .await
+            .unwrap();
+
+        let writes = (0..16u8).map(|i| {
+            let session = session.clone();
+            let handle = handle.clone();
+            tokio::spawn(async move { session.write(handle, i as u64, vec![i]).await })
+        });
+
+        for write in writes {
+            write.await.unwrap().unwrap();
+        }
+
+        let data = session.read(handle.as_str(), 0, 16).await.unwrap();
+        assert_eq!(data, (0..16).collect::<Vec<u8>>());
+    }
+
+    #[tokio::test]
+    async fn test_connection_lost() {
+        let (client, mut server) = io::duplex(4096);
+        let version = Bytes::try_from(Packet::Version(Version::new())).unwrap();
+        tokio::spawn(async move {
+            let _ = read_packet(&mut server, MAX_PACKET_LEN).await;
+            server.write_all(&version).await.unwrap();
+            let _ = read_packet(&mut server, MAX_PACKET_LEN).await;
+        });
+
+        let session = SftpSession::new(client).await.unwrap();
+        assert_eq!(
+            session.stat("a").await.unwrap_err(),
+            StatusCode::ConnectionLost
+        );
+        assert_eq!(
+            session.stat("a").await.unwrap_err(),
+            StatusCode::ConnectionLost
+        );
+    }
+
+    #[tokio::test]
+    async fn test_malformed_response() {
+        let (client, mut server) = io::duplex(4096);
+        let version = Bytes::try_from(Packet::Version(Version::new())).unwrap();
+        tokio::spawn(async move {
+            let _ = read_packet(&mut server, MAX_PACKET_LEN).await;
+            server.write_all(&version).await.unwrap();
+
+            //attributes announcing a size without sending it
+            let stat = read_packet(&mut server, MAX_PACKET_LEN).await.unwrap();
+            let mut attrs = vec![0, 0, 0, 10, 105];
+            attrs.extend_from_slice(&stat[1..5]);
+            attrs.extend_from_slice(&[0, 0, 0, 1, 0]);
+            server.write_all(&attrs).await.unwrap();
+
+            let stat = read_packet(&mut server, MAX_PACKET_LEN).await.unwrap();
+            let id = raw_request_id(&stat).unwrap();
+            let status = Bytes::try_from(Packet::error(id, StatusCode::NoSuchFile)).unwrap();
+            server.write_all(&status).await.unwrap();
+            let _ = read_packet(&mut server, MAX_PACKET_LEN).await;
+        });
+
+        let session = SftpSession::new(client).await.unwrap();
+        assert_eq!(session.stat("a").await.unwrap_err(), StatusCode::BadMessage);
+        //the session goes on with the next response
+        assert_eq!(session.stat("b").await.unwrap_err(), StatusCode::NoSuchFile);
+    }
+
+    #[tokio::test]
+    async fn test_version_fallback() {
+        let (client, mut server) = io::duplex(4096);
+        let version = Bytes::try_from(Packet::Version(Version::new())).unwrap();
+        tokio::spawn(async move {
+            let _ = read_packet(&mut server, MAX_PACKET_LEN).await;
+            server.write_all(&version).await.unwrap();
+            let _ = read_packet(&mut server, MAX_PACKET_LEN).await;
+        });
+
+        let session = SftpSession::new(client).await.unwrap();
+        assert_eq!(session.version(), VERSION);
+        assert_eq!(
+            session.link("link", "file", true).await.unwrap_err(),
+            StatusCode::OpUnsupported
+        );
+    }
+}
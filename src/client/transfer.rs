@@ -0,0 +1,1053 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    future::Future,
+    io,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+    sync::Semaphore,
+    task::{JoinError, JoinSet},
+};
+use tokio_stream::StreamExt;
+
+use super::SftpSession;
+use crate::{
+    hash::{HashAlgorithm, HASH_ALGORITHMS},
+    protocol::{
+        types::{CheckFileName, Extension, FileAttributes, FileType, Md5Hash, OpenFlags},
+        StatusCode,
+    },
+};
+
+/// Bytes copied at once by a file transfer
+const COPY_BUFFER_LEN: usize = 64 * 1024;
+
+/// How symbolic links met during a recursive transfer are handled
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Symlinks {
+    /// Recreated as links to the same target
+    #[default]
+    Preserve,
+    /// Replaced by what they point to. Links to a directory
+    /// already being transferred are reported as failures
+    Follow,
+    /// Left out
+    Skip,
+}
+
+/// How a file partly present at the destination is handled
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// Copied again from the start
+    #[default]
+    Restart,
+    /// Completed from its current size, unless it is longer than the source
+    Size,
+    /// Like [`Resume::Size`] once it is found to be a prefix of the source,
+    /// by hashing it on the server with `check-file-name` or `md5-hash`.
+    /// Only the sizes are compared if the server supports neither
+    Verify,
+}
+
+/// Progress of one file, with its path relative to the transferred directory
+#[derive(Debug, Clone, Copy)]
+pub enum Progress<'a> {
+    /// The file is opened, `size` bytes are expected in total
+    /// and the copy starts at `offset` when resuming
+    Start {
+        path: &'a Path,
+        size: u64,
+        offset: u64,
+    },
+    /// `len` more bytes of the file were copied
+    Bytes { path: &'a Path, len: u64 },
+    /// The file is copied and its attributes applied
+    Done { path: &'a Path },
+}
+
+/// Called with the [`Progress`] of every file, from several tasks at once
+pub type ProgressFn = Arc<dyn Fn(Progress<'_>) + Send + Sync>;
+
+/// Settings of the transfers of [`SftpSession`], such as [`SftpSession::upload_dir`]
+#[derive(Clone)]
+pub struct TransferOptions {
+    /// Default: [`Symlinks::Preserve`]
+    pub symlinks: Symlinks,
+    /// Files copied at the same time. Default: 4
+    pub concurrency: usize,
+    /// Default: [`Resume::Restart`]
+    pub resume: Resume,
+    /// Default: none
+    pub progress: Option<ProgressFn>,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        Self {
+            symlinks: Symlinks::default(),
+            concurrency: 4,
+            resume: Resume::default(),
+            progress: None,
+        }
+    }
+}
+
+impl fmt::Debug for TransferOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransferOptions")
+            .field("symlinks", &self.symlinks)
+            .field("concurrency", &self.concurrency)
+            .field("resume", &self.resume)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+/// Outcome of a recursive transfer. A failing entry does not stop
+/// the transfer but is recorded in `failures`
+#[derive(Debug, Default)]
+pub struct TransferSummary {
+    /// Files copied
+    pub files: usize,
+    /// Directories created, including the root
+    pub dirs: usize,
+    /// Symbolic links recreated
+    pub links: usize,
+    /// Bytes copied
+    pub bytes: u64,
+    /// Bytes already at the destination and kept when resuming
+    pub resumed: u64,
+    /// Entries that failed, by path relative to the transferred directory
+    pub failures: Vec<(PathBuf, io::Error)>,
+}
+
+/// Outcome of the transfer of one file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FileTransfer {
+    /// Bytes already at the destination and kept when resuming
+    pub resumed: u64,
+    /// Bytes copied
+    pub copied: u64,
+}
+
+impl TransferSummary {
+    /// Whether every entry was transferred
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// State shared by the walk of a tree and the file tasks it spawns
+struct Transfer {
+    options: TransferOptions,
+    permits: Arc<Semaphore>,
+    tasks: JoinSet<(PathBuf, io::Result<FileTransfer>)>,
+    summary: TransferSummary,
+}
+
+impl Transfer {
+    fn new(options: &TransferOptions) -> Self {
+        Self {
+            options: options.clone(),
+            permits: Arc::new(Semaphore::new(options.concurrency.max(1))),
+            tasks: JoinSet::new(),
+            summary: TransferSummary::default(),
+        }
+    }
+
+    fn fail(&mut self, relative: PathBuf, err: io::Error) {
+        self.summary.failures.push((relative, err));
+    }
+
+    /// Copies a file in a task once a permit is free
+    async fn spawn<F>(&mut self, relative: PathBuf, copy: F)
+    where
+        F: Future<Output = io::Result<FileTransfer>> + Send + 'static,
+    {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("permits are never closed");
+
+        self.tasks.spawn(async move {
+            let result = copy.await;
+            drop(permit);
+            (relative, result)
+        });
+
+        while let Some(joined) = self.tasks.try_join_next() {
+            self.joined(joined);
+        }
+    }
+
+    fn joined(&mut self, joined: Result<(PathBuf, io::Result<FileTransfer>), JoinError>) {
+        match joined {
+            Ok((_, Ok(file))) => {
+                self.summary.files += 1;
+                self.summary.bytes += file.copied;
+                self.summary.resumed += file.resumed;
+            }
+            Ok((relative, Err(err))) => self.fail(relative, err),
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(err) => panic!("file transfer cancelled: {err}"),
+        }
+    }
+
+    async fn finish(mut self) -> TransferSummary {
+        while let Some(joined) = self.tasks.join_next().await {
+            self.joined(joined);
+        }
+
+        self.summary
+    }
+}
+
+/// Copies `source` into `target` while reporting progress
+async fn copy<R, W>(
+    mut source: R,
+    mut target: W,
+    relative: &Path,
+    size: u64,
+    offset: u64,
+    progress: Option<&ProgressFn>,
+) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let report = |event| {
+        if let Some(progress) = progress {
+            progress(event);
+        }
+    };
+
+    report(Progress::Start {
+        path: relative,
+        size,
+        offset,
+    });
+
+    let mut buffer = vec![0; COPY_BUFFER_LEN];
+    let mut copied = 0;
+    loop {
+        let len = source.read(&mut buffer).await?;
+        if len == 0 {
+            break;
+        }
+
+        target.write_all(&buffer[..len]).await?;
+        copied += len as u64;
+        report(Progress::Bytes {
+            path: relative,
+            len: len as u64,
+        });
+    }
+
+    target.flush().await?;
+    Ok(copied)
+}
+
+/// Attributes kept by a transfer: permissions and times
+fn preserved(attrs: &FileAttributes) -> FileAttributes {
+    FileAttributes {
+        permissions: attrs.file_permissions().map(|p| p.bits()),
+        atime: attrs.atime,
+        atime_nseconds: attrs.atime_nseconds,
+        mtime: attrs.mtime,
+        mtime_nseconds: attrs.mtime_nseconds,
+        ..FileAttributes::empty()
+    }
+}
+
+/// Applies permissions and times to a local file or directory.
+/// Only unix keeps them, as directories cannot be opened elsewhere
+async fn set_local_attrs(path: PathBuf, attrs: FileAttributes) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        tokio::task::spawn_blocking(move || {
+            //times first, as the permissions may forbid opening the file
+            if let Some(times) = attrs.file_times() {
+                std::fs::File::open(&path)?.set_times(times)?;
+            }
+
+            if let Some(permissions) = attrs.file_permissions() {
+                std::fs::set_permissions(&path, permissions.into())?;
+            }
+
+            Ok(())
+        })
+        .await?
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, attrs);
+        Ok(())
+    }
+}
+
+/// Joins a name to a remote directory
+fn remote_join(dir: &str, name: &str) -> String {
+    match dir.ends_with('/') {
+        true => format!("{dir}{name}"),
+        false => format!("{dir}/{name}"),
+    }
+}
+
+fn symlink_loop() -> io::Error {
+    io::Error::other("symbolic link to a directory being transferred")
+}
+
+/// Hashes `len` bytes from the start of a local file
+async fn hash_local(path: &Path, algorithm: HashAlgorithm, len: u64) -> io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path).await?.take(len);
+    let mut hasher = algorithm.hasher();
+    let mut buffer = vec![0; COPY_BUFFER_LEN];
+    loop {
+        match file.read(&mut buffer).await? {
+            0 => break,
+            len => hasher.update(&buffer[..len]),
+        }
+    }
+
+    Ok(hasher.finalize().into_vec())
+}
+
+/// Whether the first `len` bytes of a remote and a local file are the same,
+/// hashed on the server with `check-file-name` or `md5-hash`.
+/// Without either extension only the sizes can be compared
+async fn same_prefix(
+    session: &SftpSession,
+    remote: &str,
+    local: &Path,
+    len: u64,
+) -> io::Result<bool> {
+    let extensions = session.extensions();
+    let (algorithm, hash) = if extensions.contains_key(CheckFileName::NAME) {
+        let reply = session
+            .check_file_name(remote, HASH_ALGORITHMS, 0, len, 0)
+            .await?;
+        let algorithm = HashAlgorithm::from_name(&reply.hash_algorithm)
+            .ok_or(io::Error::from(StatusCode::BadMessage))?;
+        (algorithm, reply.hashes)
+    } else if extensions.contains_key(Md5Hash::NAME) {
+        let hash = session.md5_hash(remote, 0, len, Vec::new()).await?;
+        (HashAlgorithm::Md5, hash)
+    } else {
+        return Ok(true);
+    };
+
+    Ok(hash_local(local, algorithm, len).await? == hash)
+}
+
+/// Offset to resume a transfer from, given the sizes of the source and
+/// of what is already at the destination
+async fn resume_offset(
+    session: &SftpSession,
+    resume: Resume,
+    remote: &str,
+    local: &Path,
+    size: u64,
+    existing: Option<u64>,
+) -> io::Result<u64> {
+    let existing = match (resume, existing) {
+        (Resume::Restart, _) | (_, None) => return Ok(0),
+        (_, Some(existing)) if existing == 0 || existing > size => return Ok(0),
+        (_, Some(existing)) => existing,
+    };
+
+    match resume == Resume::Verify && !same_prefix(session, remote, local, existing).await? {
+        true => Ok(0),
+        false => Ok(existing),
+    }
+}
+
+async fn upload(
+    session: SftpSession,
+    local: PathBuf,
+    remote: String,
+    relative: PathBuf,
+    attrs: FileAttributes,
+    options: TransferOptions,
+) -> io::Result<FileTransfer> {
+    let size = attrs.size.unwrap_or_default();
+    let existing = match session.stat(remote.as_str()).await {
+        Ok(existing) if existing.is_regular() => existing.size,
+        _ => None,
+    };
+    let offset = resume_offset(&session, options.resume, &remote, &local, size, existing).await?;
+
+    let mut source = fs::File::open(&local).await?;
+    let mut flags = OpenFlags::WRITE | OpenFlags::CREATE;
+    if offset == 0 {
+        flags |= OpenFlags::TRUNCATE;
+    }
+    let mut target = session
+        .open_file(remote.as_str(), flags, FileAttributes::empty())
+        .await?;
+    source.seek(SeekFrom::Start(offset)).await?;
+    target.seek(SeekFrom::Start(offset)).await?;
+
+    let progress = options.progress.as_ref();
+    let copied = copy(source, &mut target, &relative, size, offset, progress).await?;
+    target.close().await?;
+    session.setstat(remote, preserved(&attrs)).await?;
+
+    if let Some(progress) = progress {
+        progress(Progress::Done { path: &relative });
+    }
+
+    Ok(FileTransfer {
+        resumed: offset,
+        copied,
+    })
+}
+
+async fn download(
+    session: SftpSession,
+    remote: String,
+    local: PathBuf,
+    relative: PathBuf,
+    attrs: FileAttributes,
+    options: TransferOptions,
+) -> io::Result<FileTransfer> {
+    let size = attrs.size.unwrap_or_default();
+    let existing = match fs::metadata(&local).await {
+        Ok(existing) if existing.is_file() => Some(existing.len()),
+        _ => None,
+    };
+    let offset = resume_offset(&session, options.resume, &remote, &local, size, existing).await?;
+
+    let mut source = session
+        .open_file(remote, OpenFlags::READ, FileAttributes::empty())
+        .await?;
+    let mut target = fs::File::options()
+        .write(true)
+        .create(true)
+        .truncate(offset == 0)
+        .open(&local)
+        .await?;
+    source.seek(SeekFrom::Start(offset)).await?;
+    target.seek(SeekFrom::Start(offset)).await?;
+
+    let progress = options.progress.as_ref();
+    let copied = copy(&mut source, target, &relative, size, offset, progress).await?;
+    source.close().await?;
+    set_local_attrs(local, preserved(&attrs)).await?;
+
+    if let Some(progress) = progress {
+        progress(Progress::Done { path: &relative });
+    }
+
+    Ok(FileTransfer {
+        resumed: offset,
+        copied,
+    })
+}
+
+impl SftpSession {
+    /// Copies a local file to `remote`, keeping permissions and times.
+    /// A partial copy at `remote` is completed as set by [`TransferOptions::resume`]
+    pub async fn upload_file(
+        &self,
+        local: impl AsRef<Path>,
+        remote: impl Into<String>,
+        options: &TransferOptions,
+    ) -> io::Result<FileTransfer> {
+        let local = local.as_ref().to_path_buf();
+        let metadata = fs::metadata(&local).await?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "not a regular file",
+            ));
+        }
+
+        let relative = PathBuf::from(local.file_name().unwrap_or_default());
+        let attrs = FileAttributes::from(&metadata);
+        upload(
+            self.clone(),
+            local,
+            remote.into(),
+            relative,
+            attrs,
+            options.clone(),
+        )
+        .await
+    }
+
+    /// Copies a remote file to `local`. Permissions and times are kept on unix.
+    /// A partial copy at `local` is completed as set by [`TransferOptions::resume`]
+    pub async fn download_file(
+        &self,
+        remote: impl Into<String>,
+        local: impl AsRef<Path>,
+        options: &TransferOptions,
+    ) -> io::Result<FileTransfer> {
+        let remote = remote.into();
+        let local = local.as_ref().to_path_buf();
+        let attrs = self.stat(remote.as_str()).await?;
+        if !attrs.is_regular() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "not a regular file",
+            ));
+        }
+
+        let relative = PathBuf::from(local.file_name().unwrap_or_default());
+        download(
+            self.clone(),
+            remote,
+            local,
+            relative,
+            attrs,
+            options.clone(),
+        )
+        .await
+    }
+
+    /// Copies the local directory `local` into `remote` like `scp -r`,
+    /// creating `remote` if needed and keeping permissions and times.
+    ///
+    /// Up to [`TransferOptions::concurrency`] files are copied at the same
+    /// time. Only a failure on `local` or `remote` themselves is returned
+    /// as an error, other ones are listed in the summary
+    pub async fn upload_dir(
+        &self,
+        local: impl AsRef<Path>,
+        remote: impl Into<String>,
+        options: &TransferOptions,
+    ) -> io::Result<TransferSummary> {
+        let local = local.as_ref().to_path_buf();
+        let remote = remote.into();
+        let metadata = fs::metadata(&local).await?;
+        if !metadata.is_dir() {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+
+        self.create_remote_dir(&remote).await?;
+
+        let mut transfer = Transfer::new(options);
+        let mut visited = HashSet::from([fs::canonicalize(&local).await?]);
+        let mut dirs = vec![(local, remote, PathBuf::new(), metadata)];
+        let mut created = Vec::new();
+
+        while let Some((local, remote, relative, metadata)) = dirs.pop() {
+            transfer.summary.dirs += 1;
+            created.push((remote.clone(), relative.clone(), metadata));
+
+            let mut entries = match fs::read_dir(&local).await {
+                Ok(entries) => entries,
+                Err(err) => {
+                    transfer.fail(relative, err);
+                    continue;
+                }
+            };
+
+            loop {
+                let entry = match entries.next_entry().await {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => break,
+                    Err(err) => {
+                        transfer.fail(relative.clone(), err);
+                        break;
+                    }
+                };
+
+                let name = entry.file_name();
+                let local = entry.path();
+                let remote = remote_join(&remote, &name.to_string_lossy());
+                let relative = relative.join(&name);
+
+                let result = self
+                    .upload_entry(&mut transfer, &mut visited, local, remote, &relative)
+                    .await;
+                match result {
+                    Ok(Some(dir)) => dirs.push(dir),
+                    Ok(None) => (),
+                    Err(err) => transfer.fail(relative, err),
+                }
+            }
+        }
+
+        let mut summary = transfer.finish().await;
+
+        //deepest first, so that permissions cannot block the creation of children
+        for (remote, relative, metadata) in created.into_iter().rev() {
+            let attrs = preserved(&FileAttributes::from(&metadata));
+            if let Err(err) = self.setstat(remote, attrs).await {
+                summary.failures.push((relative, err.into()));
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Uploads one entry of a directory, returning it when it is
+    /// a directory to walk
+    async fn upload_entry(
+        &self,
+        transfer: &mut Transfer,
+        visited: &mut HashSet<PathBuf>,
+        local: PathBuf,
+        remote: String,
+        relative: &Path,
+    ) -> io::Result<Option<(PathBuf, String, PathBuf, std::fs::Metadata)>> {
+        let mut metadata = fs::symlink_metadata(&local).await?;
+
+        if metadata.file_type().is_symlink() {
+            match transfer.options.symlinks {
+                Symlinks::Skip => return Ok(None),
+                Symlinks::Preserve => {
+                    let target = fs::read_link(&local).await?;
+                    self.symlink(remote, target.to_string_lossy()).await?;
+                    transfer.summary.links += 1;
+                    return Ok(None);
+                }
+                Symlinks::Follow => metadata = fs::metadata(&local).await?,
+            }
+        }
+
+        if metadata.is_dir() {
+            if !visited.insert(fs::canonicalize(&local).await?) {
+                return Err(symlink_loop());
+            }
+
+            self.create_remote_dir(&remote).await?;
+            return Ok(Some((local, remote, relative.to_path_buf(), metadata)));
+        }
+
+        if !metadata.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "not a regular file",
+            ));
+        }
+
+        let copy = upload(
+            self.clone(),
+            local,
+            remote,
+            relative.to_path_buf(),
+            FileAttributes::from(&metadata),
+            transfer.options.clone(),
+        );
+        transfer.spawn(relative.to_path_buf(), copy).await;
+        Ok(None)
+    }
+
+    /// Creates a remote directory unless one already exists
+    async fn create_remote_dir(&self, remote: &str) -> io::Result<()> {
+        match self.stat(remote).await {
+            Ok(attrs) if attrs.is_dir() => Ok(()),
+            Ok(_) => Err(io::ErrorKind::NotADirectory.into()),
+            Err(StatusCode::NoSuchFile) => Ok(self.mkdir(remote, FileAttributes::empty()).await?),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Copies the remote directory `remote` into `local` like `scp -r`,
+    /// creating `local` if needed. Permissions and times are kept on unix.
+    ///
+    /// Up to [`TransferOptions::concurrency`] files are copied at the same
+    /// time. Only a failure on `remote` or `local` themselves is returned
+    /// as an error, other ones are listed in the summary
+    pub async fn download_dir(
+        &self,
+        remote: impl Into<String>,
+        local: impl AsRef<Path>,
+        options: &TransferOptions,
+    ) -> io::Result<TransferSummary> {
+        let remote = remote.into();
+        let local = local.as_ref().to_path_buf();
+        let attrs = self.stat(remote.as_str()).await?;
+        if !attrs.is_dir() {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+
+        create_local_dir(&local).await?;
+
+        let mut transfer = Transfer::new(options);
+        let mut visited = HashSet::from([self.realpath(remote.as_str()).await?]);
+        let mut dirs = vec![(remote, local, PathBuf::new(), attrs)];
+        let mut created = Vec::new();
+
+        while let Some((remote, local, relative, attrs)) = dirs.pop() {
+            transfer.summary.dirs += 1;
+            created.push((local.clone(), relative.clone(), attrs));
+
+            let mut entries = match self.read_dir(remote.as_str()).await {
+                Ok(entries) => entries,
+                Err(err) => {
+                    transfer.fail(relative, err.into());
+                    continue;
+                }
+            };
+
+            while let Some(entry) = entries.next().await {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        transfer.fail(relative.clone(), err.into());
+                        break;
+                    }
+                };
+
+                if entry.filename == "." || entry.filename == ".." {
+                    continue;
+                }
+
+                let remote = remote_join(&remote, &entry.filename);
+                let local = local.join(&entry.filename);
+                let relative = relative.join(&entry.filename);
+
+                let result = self
+                    .download_entry(
+                        &mut transfer,
+                        &mut visited,
+                        remote,
+                        local,
+                        &relative,
+                        entry.attrs,
+                    )
+                    .await;
+                match result {
+                    Ok(Some(dir)) => dirs.push(dir),
+                    Ok(None) => (),
+                    Err(err) => transfer.fail(relative, err),
+                }
+            }
+        }
+
+        let mut summary = transfer.finish().await;
+
+        //deepest first, so that permissions cannot block the creation of children
+        for (local, relative, attrs) in created.into_iter().rev() {
+            if let Err(err) = set_local_attrs(local, preserved(&attrs)).await {
+                summary.failures.push((relative, err));
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Downloads one entry of a directory, returning it when it is
+    /// a directory to walk
+    async fn download_entry(
+        &self,
+        transfer: &mut Transfer,
+        visited: &mut HashSet<String>,
+        remote: String,
+        local: PathBuf,
+        relative: &Path,
+        mut attrs: FileAttributes,
+    ) -> io::Result<Option<(String, PathBuf, PathBuf, FileAttributes)>> {
+        if attrs.file_type() == FileType::Unknown {
+            attrs = self.lstat(remote.as_str()).await?;
+        }
+
+        if attrs.is_symlink() {
+            match transfer.options.symlinks {
+                Symlinks::Skip => return Ok(None),
+                Symlinks::Preserve => {
+                    let target = self.readlink(remote).await?;
+                    create_local_symlink(&target, &local).await?;
+                    transfer.summary.links += 1;
+                    return Ok(None);
+                }
+                Symlinks::Follow => attrs = self.stat(remote.as_str()).await?,
+            }
+        }
+
+        match attrs.file_type() {
+            FileType::Dir => {
+                if !visited.insert(self.realpath(remote.as_str()).await?) {
+                    return Err(symlink_loop());
+                }
+
+                create_local_dir(&local).await?;
+                Ok(Some((remote, local, relative.to_path_buf(), attrs)))
+            }
+            FileType::Regular => {
+                let copy = download(
+                    self.clone(),
+                    remote,
+                    local,
+                    relative.to_path_buf(),
+                    attrs,
+                    transfer.options.clone(),
+                );
+                transfer.spawn(relative.to_path_buf(), copy).await;
+                Ok(None)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "not a regular file",
+            )),
+        }
+    }
+}
+
+/// Creates a local directory unless one already exists
+async fn create_local_dir(local: &Path) -> io::Result<()> {
+    match fs::create_dir(local).await {
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            match fs::metadata(local).await?.is_dir() {
+                true => Ok(()),
+                false => Err(err),
+            }
+        }
+        result => result,
+    }
+}
+
+async fn create_local_symlink(target: &str, local: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        fs::symlink(target, local).await
+    }
+    #[cfg(windows)]
+    {
+        //the target may not exist yet, so its kind is guessed from its name
+        match target.ends_with('/') {
+            true => fs::symlink_dir(target, local).await,
+            false => fs::symlink_file(target, local).await,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "impls"))]
+mod test {
+    use std::{
+        sync::atomic::{AtomicU64, Ordering},
+        time::{Duration, SystemTime},
+    };
+
+    use super::*;
+    use crate::{
+        server::{
+            self,
+            vfs::{MemoryFs, VfsHandler},
+        },
+        test_util::TempDir,
+    };
+
+    async fn session() -> SftpSession {
+        let (client, server) = tokio::io::duplex(4096);
+        let handler = VfsHandler::new(MemoryFs::default());
+        tokio::spawn(server::run_concurrent(server, handler, Default::default()));
+        SftpSession::new(client).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let source = TempDir::new("transfer-source");
+        std::fs::create_dir_all(source.join("dir/sub")).unwrap();
+        std::fs::write(source.join("dir/file"), vec![7; 100_000]).unwrap();
+        std::fs::write(source.join("dir/sub/empty"), "").unwrap();
+
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let file = std::fs::File::options()
+            .write(true)
+            .open(source.join("dir/file"))
+            .unwrap();
+        file.set_modified(mtime).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o640))
+                .unwrap();
+            std::os::unix::fs::symlink("dir/file", source.join("link")).unwrap();
+        }
+
+        let bytes = Arc::new(AtomicU64::new(0));
+        let counted = bytes.clone();
+        let options = TransferOptions {
+            concurrency: 2,
+            progress: Some(Arc::new(move |progress| {
+                if let Progress::Bytes { len, .. } = progress {
+                    counted.fetch_add(len, Ordering::Relaxed);
+                }
+            })),
+            ..Default::default()
+        };
+
+        let sftp = session().await;
+        let summary = sftp.upload_dir(&source, "/up", &options).await.unwrap();
+        assert!(summary.is_complete(), "{:?}", summary.failures);
+        assert_eq!(
+            (summary.files, summary.dirs, summary.bytes),
+            (2, 3, 100_000)
+        );
+        assert_eq!(bytes.load(Ordering::Relaxed), 100_000);
+
+        let attrs = sftp.stat("/up/dir/file").await.unwrap();
+        assert_eq!(attrs.size, Some(100_000));
+        assert_eq!(attrs.mtime, Some(1_000_000_000));
+
+        let target = TempDir::new("transfer-target");
+        let summary = sftp
+            .download_dir("/up", &target, &TransferOptions::default())
+            .await
+            .unwrap();
+        assert!(summary.is_complete(), "{:?}", summary.failures);
+        assert_eq!((summary.files, summary.dirs), (2, 3));
+
+        assert_eq!(
+            std::fs::read(target.join("dir/file")).unwrap(),
+            vec![7; 100_000]
+        );
+        assert_eq!(std::fs::read(target.join("dir/sub/empty")).unwrap(), b"");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(summary.links, 1);
+            assert_eq!(
+                std::fs::read_link(target.join("link")).unwrap(),
+                Path::new("dir/file")
+            );
+
+            let metadata = std::fs::metadata(target.join("dir/file")).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+            assert_eq!(metadata.modified().unwrap(), mtime);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume() {
+        let dir = TempDir::new("transfer-resume");
+        let data = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        std::fs::write(dir.join("source"), &data).unwrap();
+
+        let sftp = session().await;
+        let upload = |resume| {
+            let options = TransferOptions {
+                resume,
+                ..Default::default()
+            };
+            let sftp = sftp.clone();
+            let source = dir.join("source");
+            async move { sftp.upload_file(source, "/file", &options).await.unwrap() }
+        };
+        let truncate = |size| {
+            let attrs = FileAttributes {
+                size: Some(size),
+                ..FileAttributes::empty()
+            };
+            sftp.setstat("/file", attrs)
+        };
+
+        let copied = upload(Resume::Verify).await;
+        assert_eq!((copied.resumed, copied.copied), (0, 100_000));
+
+        truncate(40_000).await.unwrap();
+        let copied = upload(Resume::Verify).await;
+        assert_eq!((copied.resumed, copied.copied), (40_000, 60_000));
+        let hash = sftp.md5_hash("/file", 0, 0, Vec::new()).await.unwrap();
+        let source = dir.join("source");
+        let local = hash_local(&source, HashAlgorithm::Md5, u64::MAX).await;
+        assert_eq!(local.unwrap(), hash);
+
+        //a changed prefix is only noticed by hashing it
+        let change = || async {
+            truncate(50_000).await.unwrap();
+            let handle = sftp
+                .open("/file", OpenFlags::WRITE, FileAttributes::empty())
+                .await
+                .unwrap();
+            sftp.write(handle.as_str(), 0, &b"changed"[..])
+                .await
+                .unwrap();
+            sftp.close(handle).await.unwrap();
+        };
+        change().await;
+        assert_eq!(upload(Resume::Verify).await.resumed, 0);
+        change().await;
+        assert_eq!(upload(Resume::Size).await.resumed, 50_000);
+        truncate(50_000).await.unwrap();
+        assert_eq!(upload(Resume::Restart).await.resumed, 0);
+
+        std::fs::write(dir.join("target"), &data[..30_000]).unwrap();
+        let offsets = Arc::new(AtomicU64::new(0));
+        let started = offsets.clone();
+        let options = TransferOptions {
+            resume: Resume::Verify,
+            progress: Some(Arc::new(move |progress| {
+                if let Progress::Start { offset, .. } = progress {
+                    started.store(offset, Ordering::Relaxed);
+                }
+            })),
+            ..Default::default()
+        };
+        let copied = sftp
+            .download_file("/file", dir.join("target"), &options)
+            .await
+            .unwrap();
+        assert_eq!((copied.resumed, copied.copied), (30_000, 70_000));
+        assert_eq!(offsets.load(Ordering::Relaxed), 30_000);
+        assert_eq!(std::fs::read(dir.join("target")).unwrap(), data);
+
+        std::fs::write(dir.join("target"), b"changed").unwrap();
+        let copied = sftp
+            .download_file("/file", dir.join("target"), &options)
+            .await
+            .unwrap();
+        assert_eq!((copied.resumed, copied.copied), (0, 100_000));
+        assert_eq!(std::fs::read(dir.join("target")).unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn test_failures() {
+        let sftp = session().await;
+        sftp.mkdir("/src", FileAttributes::empty()).await.unwrap();
+        sftp.mkdir("/src/dir", FileAttributes::empty())
+            .await
+            .unwrap();
+        for path in ["/src/file", "/src/dir/file"] {
+            let mut file = sftp
+                .open_file(
+                    path,
+                    OpenFlags::WRITE | OpenFlags::CREATE,
+                    FileAttributes::empty(),
+                )
+                .await
+                .unwrap();
+            file.write_all(b"data").await.unwrap();
+            file.close().await.unwrap();
+        }
+        sftp.symlink("/src/loop", "/src").await.unwrap();
+
+        //a local file in the way of a directory fails only that directory
+        let target = TempDir::new("transfer-failures");
+        std::fs::write(target.join("dir"), "").unwrap();
+
+        let options = TransferOptions {
+            symlinks: Symlinks::Follow,
+            ..Default::default()
+        };
+        let summary = sftp.download_dir("/src", &target, &options).await.unwrap();
+        let mut failed = summary
+            .failures
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        failed.sort();
+        assert_eq!(failed, [Path::new("dir"), Path::new("loop")]);
+        assert_eq!(summary.files, 1);
+        assert_eq!(std::fs::read(target.join("file")).unwrap(), b"data");
+
+        let err = sftp
+            .download_dir("/src/file", &target, &options)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotADirectory);
+    }
+}
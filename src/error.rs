@@ -1,6 +1,9 @@
+use bytes::TryGetError;
 use std::{fmt, io};
 use thiserror::Error;
 
+use crate::protocol::StatusCode;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("I/O: {0}")]
@@ -9,6 +12,8 @@ pub enum Error {
     UnexpectedEof,
     #[error("Bad message")]
     BadMessage,
+    #[error("Packet of {0} bytes exceeds the maximum length")]
+    PacketTooLarge(u32),
 }
 
 impl From<io::Error> for Error {
@@ -23,6 +28,52 @@ impl From<io::Error> for Error {
     }
 }
 
+/// Lets client requests report transport failures as a status
+impl From<Error> for StatusCode {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::BadMessage => StatusCode::BadMessage,
+            _ => StatusCode::ConnectionLost,
+        }
+    }
+}
+
+/// Lets [`RemoteFile`](crate::client::RemoteFile) report statuses
+/// through the I/O traits
+impl From<StatusCode> for io::Error {
+    fn from(status: StatusCode) -> Self {
+        let kind = match status {
+            StatusCode::Eof => io::ErrorKind::UnexpectedEof,
+            StatusCode::NoSuchFile | StatusCode::NoSuchPath => io::ErrorKind::NotFound,
+            StatusCode::PermissionDenied | StatusCode::WriteProtect => {
+                io::ErrorKind::PermissionDenied
+            }
+            StatusCode::BadMessage => io::ErrorKind::InvalidData,
+            StatusCode::NoConnection => io::ErrorKind::NotConnected,
+            StatusCode::ConnectionLost => io::ErrorKind::ConnectionAborted,
+            StatusCode::OpUnsupported => io::ErrorKind::Unsupported,
+            StatusCode::InvalidHandle | StatusCode::InvalidParameter => {
+                io::ErrorKind::InvalidInput
+            }
+            StatusCode::FileAlreadyExists => io::ErrorKind::AlreadyExists,
+            StatusCode::DirNotEmpty => io::ErrorKind::DirectoryNotEmpty,
+            StatusCode::NotADirectory => io::ErrorKind::NotADirectory,
+            StatusCode::FileIsADirectory => io::ErrorKind::IsADirectory,
+            StatusCode::NoSpaceOnFilesystem => io::ErrorKind::StorageFull,
+            StatusCode::QuotaExceeded => io::ErrorKind::QuotaExceeded,
+            _ => io::ErrorKind::Other,
+        };
+
+        io::Error::new(kind, status)
+    }
+}
+
+impl From<TryGetError> for Error {
+    fn from(_: TryGetError) -> Self {
+        Self::BadMessage
+    }
+}
+
 impl serde::ser::Error for Error {
     fn custom<T>(_msg: T) -> Self
     where
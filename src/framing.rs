@@ -0,0 +1,68 @@
+use bytes::{buf::Chain, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::Error;
+
+/// Encoded packet: the length and headers, then the data of SSH_FXP_DATA
+/// or SSH_FXP_WRITE, which is not copied to join them
+pub(crate) type Frame = Chain<Bytes, Bytes>;
+
+/// Reads a packet without its length. Lengths above `max_len` are refused
+/// before allocating anything, the stream must not be read any further then
+pub(crate) async fn read_packet<S>(stream: &mut S, max_len: u32) -> Result<Bytes, Error>
+where
+    S: AsyncRead + Unpin,
+{
+    let length = stream.read_u32().await?;
+    if length > max_len {
+        return Err(Error::PacketTooLarge(length));
+    }
+
+    let mut buf = BytesMut::zeroed(length as usize);
+    stream.read_exact(&mut buf).await?;
+
+    Ok(buf.freeze())
+}
+
+/// Writes both parts of a frame, with a vectored write if the stream supports it
+pub(crate) async fn write_frame<S>(stream: &mut S, mut frame: Frame) -> Result<(), Error>
+where
+    S: AsyncWrite + Unpin,
+{
+    stream.write_all_buf(&mut frame).await?;
+    //buffered streams such as stdout would hold the response back
+    stream.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_packet() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(&[0, 0, 0, 3, 1, 2, 3]).await.unwrap();
+        assert_eq!(read_packet(&mut server, 3).await.unwrap(), &[1, 2, 3][..]);
+
+        client.write_all(&[0xff, 0xff, 0xff, 0xff]).await.unwrap();
+        assert!(matches!(
+            read_packet(&mut server, 256 * 1024).await,
+            Err(Error::PacketTooLarge(u32::MAX))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_truncated_frame() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(&[0, 0, 0, 8, 1, 2]).await.unwrap();
+        drop(client);
+
+        assert!(matches!(
+            read_packet(&mut server, 256 * 1024).await,
+            Err(Error::UnexpectedEof)
+        ));
+    }
+}
@@ -0,0 +1,25 @@
+use super::{impl_request_id, OpenFlagsV5, RequestId};
+
+/// Implementation for SSH_FXP_BLOCK, since version 6
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Block {
+    pub id: u32,
+    pub handle: String,
+    pub offset: u64,
+    pub length: u64,
+    /// Only the `BLOCK_*` flags are meaningful
+    pub lock_mask: OpenFlagsV5,
+}
+
+impl_request_id!(Block);
+
+/// Implementation for SSH_FXP_UNBLOCK, since version 6
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Unblock {
+    pub id: u32,
+    pub handle: String,
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl_request_id!(Unblock);
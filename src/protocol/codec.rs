@@ -0,0 +1,352 @@
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::{Packet, MAX_PACKET_LEN, VERSION};
+use crate::error::Error;
+
+/// Codec of SFTP packets, so that [`Framed`](tokio_util::codec::Framed) gives
+/// a stream and a sink of [`Packet`].
+///
+/// Packets are laid out as in [`VERSION`] until [`SftpCodec::set_version`]
+/// is called once SSH_FXP_VERSION is exchanged. Packets longer than the
+/// maximum length are refused, the stream must not be read any further then
+#[derive(Debug, Clone)]
+pub struct SftpCodec {
+    version: u32,
+    max_len: u32,
+}
+
+impl SftpCodec {
+    /// Codec of [`VERSION`] refusing packets longer than [`MAX_PACKET_LEN`]
+    pub fn new() -> Self {
+        Self {
+            version: VERSION,
+            max_len: MAX_PACKET_LEN,
+        }
+    }
+
+    /// Refuses packets longer than `max_len`, without their length field
+    pub fn with_max_len(mut self, max_len: u32) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    pub fn max_len(&self) -> u32 {
+        self.max_len
+    }
+
+    /// Protocol version packets are laid out in
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Sets the negotiated version, for example through
+    /// [`Framed::codec_mut`](tokio_util::codec::Framed::codec_mut)
+    pub fn set_version(&mut self, version: u32) {
+        self.version = version;
+    }
+}
+
+impl Default for SftpCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for SftpCodec {
+    type Item = Packet;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some(mut length) = src.get(..4) else {
+            return Ok(None);
+        };
+
+        let length = length.get_u32();
+        if length > self.max_len {
+            return Err(Error::PacketTooLarge(length));
+        }
+
+        let frame_len = 4 + length as usize;
+        if src.len() < frame_len {
+            src.reserve(frame_len - src.len());
+            return Ok(None);
+        }
+
+        src.advance(4);
+        let mut bytes = src.split_to(length as usize).freeze();
+        Packet::decode(&mut bytes, self.version).map(Some)
+    }
+}
+
+impl Encoder<Packet> for SftpCodec {
+    type Error = Error;
+
+    fn encode(&mut self, packet: Packet, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let frame = packet.encode_frame(self.version)?;
+        let length = (frame.remaining() - 4) as u32;
+        if length > self.max_len {
+            return Err(Error::PacketTooLarge(length));
+        }
+
+        dst.reserve(frame.remaining());
+        dst.put(frame);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use bytes::Bytes;
+    use futures::{SinkExt, StreamExt};
+    use tokio_util::codec::{Framed, FramedRead};
+
+    use super::*;
+    use crate::protocol::{types::*, Status, StatusCode, VERSION_MAX};
+
+    fn attrs() -> FileAttributes {
+        FileAttributes {
+            size: Some(1024),
+            uid: Some(1000),
+            gid: Some(100),
+            permissions: Some(0o100644),
+            atime: Some(1_000_000_000),
+            mtime: Some(1_000_000_001),
+            ..FileAttributes::empty()
+        }
+    }
+
+    /// One packet of every type, all of them existing in `version`
+    fn packets(version: u32) -> Vec<Packet> {
+        let handle = || Handle {
+            id: 1,
+            handle: "handle".to_string(),
+        };
+        let path = || Path {
+            id: 2,
+            path: "/path".to_string(),
+        };
+        let path_attrs = || PathAttrs {
+            id: 3,
+            path: "/path".to_string(),
+            attrs: attrs(),
+        };
+        let extensions = HashMap::from([("name@example.com".to_string(), "1".to_string())]);
+
+        let mut packets = vec![
+            Packet::Init(Init {
+                version,
+                extensions: extensions.clone(),
+            }),
+            Packet::Version(Version {
+                version,
+                extensions,
+            }),
+            Packet::Open(Open {
+                id: 4,
+                filename: "/file".to_string(),
+                pflags: OpenFlags::READ | OpenFlags::WRITE | OpenFlags::CREATE,
+                attrs: attrs(),
+                access: None,
+            }),
+            Packet::Close(handle()),
+            Packet::Read(Read {
+                id: 5,
+                handle: "handle".to_string(),
+                offset: u64::MAX - 1,
+                len: 32768,
+            }),
+            Packet::Write(Write {
+                id: 6,
+                handle: "handle".to_string(),
+                offset: 7,
+                data: Bytes::from_static(b"data"),
+            }),
+            Packet::LStat(path()),
+            Packet::FStat(handle()),
+            Packet::SetStat(path_attrs()),
+            Packet::FSetStat(FSetStat {
+                id: 8,
+                handle: "handle".to_string(),
+                attrs: attrs(),
+            }),
+            Packet::OpenDir(path()),
+            Packet::ReadDir(handle()),
+            Packet::Remove(Remove {
+                id: 9,
+                filename: "/file".to_string(),
+            }),
+            Packet::MkDir(path_attrs()),
+            Packet::RmDir(path()),
+            Packet::RealPath(path()),
+            Packet::Stat(path()),
+            Packet::Rename(Rename {
+                id: 10,
+                oldpath: "/old".to_string(),
+                newpath: "/new".to_string(),
+                flags: RenameFlags::empty(),
+            }),
+            Packet::ReadLink(path()),
+            Packet::Symlink(Symlink {
+                id: 11,
+                linkpath: "/link".to_string(),
+                targetpath: "/target".to_string(),
+            }),
+            Packet::Status(Status::new(12, StatusCode::NoSuchFile, "no such file")),
+            Packet::Handle(handle()),
+            Packet::Data(Data {
+                id: 13,
+                data: Bytes::from(vec![1; 1000]),
+            }),
+            Packet::Name(Name {
+                id: 14,
+                files: vec![File {
+                    filename: "file".to_string(),
+                    longname: match version {
+                        3 => "-rw-r--r--    1 1000     100          1024 Sep  9  2001 file",
+                        _ => "",
+                    }
+                    .to_string(),
+                    attrs: attrs(),
+                }],
+            }),
+            Packet::Attrs(Attrs {
+                id: 15,
+                attrs: attrs(),
+            }),
+            Packet::Extended(Extended {
+                id: 16,
+                request: "name@example.com".to_string(),
+                data: vec![1, 2, 3],
+            }),
+            StatvfsReply {
+                id: 17,
+                f_bsize: 4096,
+                ..Default::default()
+            }
+            .into(),
+            CheckFileReply {
+                id: 18,
+                hash_algorithm: "md5".to_string(),
+                hashes: vec![0; 32],
+            }
+            .into(),
+        ];
+
+        if version >= 6 {
+            packets.push(Packet::Link(Link {
+                id: 19,
+                new_link_path: "/link".to_string(),
+                existing_path: "/file".to_string(),
+                symlink: false,
+            }));
+            packets.push(Packet::Block(Block {
+                id: 20,
+                handle: "handle".to_string(),
+                offset: 0,
+                length: 100,
+                lock_mask: OpenFlagsV5::BLOCK_READ,
+            }));
+            packets.push(Packet::Unblock(Unblock {
+                id: 21,
+                handle: "handle".to_string(),
+                offset: 0,
+                length: 100,
+            }));
+        }
+
+        packets
+    }
+
+    fn encode(packet: Packet, version: u32) -> Bytes {
+        packet.encode(version).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for version in VERSION..=VERSION_MAX {
+            let mut codec = SftpCodec::new();
+            codec.set_version(version);
+
+            let mut stream = BytesMut::new();
+            for packet in packets(version) {
+                codec.encode(packet, &mut stream).unwrap();
+            }
+
+            //fed a few bytes at a time, so that most frames arrive in parts
+            let mut src = BytesMut::new();
+            let mut decoded = Vec::new();
+            for chunk in stream.chunks(7) {
+                src.extend_from_slice(chunk);
+                while let Some(packet) = codec.decode(&mut src).unwrap() {
+                    decoded.push(packet);
+                }
+            }
+            assert!(src.is_empty());
+
+            let expected = packets(version);
+            assert_eq!(decoded.len(), expected.len());
+            for (decoded, expected) in decoded.into_iter().zip(expected) {
+                let expected = encode(expected, version);
+                assert_eq!(encode(decoded, version), expected, "version {version}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_len() {
+        let mut codec = SftpCodec::new().with_max_len(16);
+
+        //refused as soon as the length is known
+        let mut src = BytesMut::from(&[0, 0, 0, 17][..]);
+        assert!(matches!(
+            codec.decode(&mut src),
+            Err(Error::PacketTooLarge(17))
+        ));
+
+        let mut src = BytesMut::from(&[0, 0, 0, 16, 1][..]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        assert!(src.capacity() >= 20);
+
+        let data = Packet::Data(Data {
+            id: 1,
+            data: Bytes::from(vec![0; 16]),
+        });
+        let mut dst = BytesMut::new();
+        assert!(matches!(
+            codec.encode(data, &mut dst),
+            Err(Error::PacketTooLarge(25))
+        ));
+        assert!(dst.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_framed() {
+        let (client, server) = tokio::io::duplex(4096);
+        let mut client = Framed::new(client, SftpCodec::new());
+        let mut server = FramedRead::new(server, SftpCodec::new());
+
+        client.send(Packet::Init(Init::new())).await.unwrap();
+        let data = Packet::Data(Data {
+            id: 2,
+            data: Bytes::from(vec![3; 1000]),
+        });
+        client.send(data).await.unwrap();
+        drop(client);
+
+        assert!(matches!(
+            server.next().await,
+            Some(Ok(Packet::Init(Init {
+                version: VERSION_MAX,
+                ..
+            })))
+        ));
+        match server.next().await {
+            Some(Ok(Packet::Data(data))) => assert_eq!(data.data, vec![3; 1000]),
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(server.next().await.is_none());
+    }
+}
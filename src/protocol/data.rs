@@ -1,19 +1,41 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
 use super::{impl_packet_for, impl_request_id, Packet, RequestId};
+use crate::{buf::TryBuf, error::Error};
 
 /// Implementation for [SSH_FXP_DATA](crate::protocol::SSH_FXP_DATA)
 /// The SSH_FXP_DATA response has the following format: <br>
 /// id: u32 <br>
-/// data: Vec<u8> <br>
+/// data: Bytes <br>
 /// Where [`Data::id`] is the request identifier, and [`Data::data`] is an arbitrary byte
 /// string containing the requested data.  The data string may be at most
 /// the number of bytes requested in a [SSH_FXP_READ](crate::protocol::SSH_FXP_READ) request,
 /// but may also be shorter if end of file is reached or if the read is from something
 /// other than a regular file.
-#[derive(Debug, Serialize, Deserialize)]
+///
+/// A received `data` is a slice of the packet, it is never copied
+#[derive(Debug)]
 pub struct Data {
     pub id: u32,
-    pub data: Vec<u8>,
+    pub data: Bytes,
 }
 
 impl_request_id!(Data);
 impl_packet_for!(Data);
+
+impl Data {
+    pub(crate) fn decode(bytes: &mut Bytes) -> Result<Self, Error> {
+        Ok(Self {
+            id: bytes.try_get_u32()?,
+            data: bytes.try_get_shared()?,
+        })
+    }
+
+    /// Header and payload, to be written without joining them
+    pub(crate) fn encode(self) -> (Bytes, Bytes) {
+        let mut header = BytesMut::with_capacity(8);
+        header.put_u32(self.id);
+        header.put_u32(self.data.len() as u32);
+        (header.freeze(), self.data)
+    }
+}
@@ -1,7 +1,12 @@
-use super::{impl_packet_for, impl_request_id, Packet, RequestId};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use serde::{de::DeserializeOwned, Serialize};
 
-/// Implementation for [SSH_FXP_EXTENDED](crate::protocol::SSH_FXP_EXTENDED)
-#[derive(Debug, Serialize, Deserialize)]
+use super::{impl_packet_for, impl_request_id, Extension, Packet, RequestId};
+use crate::{buf::TryBuf, de, error::Error, ser};
+
+/// Implementation for [SSH_FXP_EXTENDED](crate::protocol::SSH_FXP_EXTENDED).
+/// `data` is the rest of the packet after the request name
+#[derive(Debug)]
 pub struct Extended {
     pub id: u32,
     pub request: String,
@@ -10,8 +15,43 @@ pub struct Extended {
 
 impl_request_id!(Extended);
 
-/// Implementation for [SSH_FXP_EXTENDED_REPLY](crate::protocol::SSH_FXP_EXTENDED_REPLY)
-#[derive(Debug, Serialize, Deserialize)]
+impl Extended {
+    /// Wraps a typed request, named after [`Extension::NAME`]
+    pub(crate) fn new<T: Extension>(request: &T) -> Result<Self, Error> {
+        let mut data = ser::to_bytes(request)?;
+        Ok(Self {
+            id: data.try_get_u32()?,
+            request: T::NAME.to_string(),
+            data: data.to_vec(),
+        })
+    }
+
+    /// Reads `data` as a typed request
+    pub(crate) fn parse<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        with_id(self.id, &self.data)
+    }
+
+    pub(crate) fn decode(bytes: &mut Bytes) -> Result<Self, Error> {
+        Ok(Self {
+            id: bytes.try_get_u32()?,
+            request: bytes.try_get_string()?,
+            data: bytes.split_off(0).to_vec(),
+        })
+    }
+
+    pub(crate) fn encode(&self) -> Bytes {
+        let mut bytes = BytesMut::new();
+        bytes.put_u32(self.id);
+        bytes.put_u32(self.request.len() as u32);
+        bytes.put_slice(self.request.as_bytes());
+        bytes.put_slice(&self.data);
+        bytes.freeze()
+    }
+}
+
+/// Implementation for [SSH_FXP_EXTENDED_REPLY](crate::protocol::SSH_FXP_EXTENDED_REPLY).
+/// `data` is the rest of the packet after the id
+#[derive(Debug)]
 pub struct ExtendedReply {
     pub id: u32,
     pub data: Vec<u8>,
@@ -19,3 +59,41 @@ pub struct ExtendedReply {
 
 impl_request_id!(ExtendedReply);
 impl_packet_for!(ExtendedReply);
+
+impl ExtendedReply {
+    /// Wraps a typed reply, its first field being the id
+    pub(crate) fn new<T: Serialize>(reply: &T) -> Result<Self, Error> {
+        let mut data = ser::to_bytes(reply)?;
+        Ok(Self {
+            id: data.try_get_u32()?,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Reads `data` as a typed reply
+    pub(crate) fn parse<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        with_id(self.id, &self.data)
+    }
+
+    pub(crate) fn decode(bytes: &mut Bytes) -> Result<Self, Error> {
+        Ok(Self {
+            id: bytes.try_get_u32()?,
+            data: bytes.split_off(0).to_vec(),
+        })
+    }
+
+    pub(crate) fn encode(&self) -> Bytes {
+        let mut bytes = BytesMut::new();
+        bytes.put_u32(self.id);
+        bytes.put_slice(&self.data);
+        bytes.freeze()
+    }
+}
+
+/// Typed extensions start with the id, which is not part of `data`
+fn with_id<T: DeserializeOwned>(id: u32, data: &[u8]) -> Result<T, Error> {
+    let mut bytes = BytesMut::with_capacity(4 + data.len());
+    bytes.put_u32(id);
+    bytes.put_slice(data);
+    de::from_bytes(&mut bytes.freeze())
+}
@@ -0,0 +1,306 @@
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{impl_request_id, ExtendedReply, FileAttributes, Packet, RequestId, StatusCode};
+use crate::{buf::TryBuf, error::Error};
+
+/// Typed request carried by SSH_FXP_EXTENDED.
+/// The first field is the id, the following ones make up the data
+pub trait Extension: Serialize + DeserializeOwned {
+    /// Name of the request, also advertised in SSH_FXP_VERSION
+    const NAME: &'static str;
+}
+
+macro_rules! impl_extension {
+    ($name:ident, $request:expr) => {
+        impl_request_id!($name);
+
+        impl Extension for $name {
+            const NAME: &'static str = $request;
+        }
+    };
+}
+
+macro_rules! impl_extended_reply {
+    ($name:ident) => {
+        impl_request_id!($name);
+
+        impl From<$name> for Packet {
+            fn from(reply: $name) -> Self {
+                match ExtendedReply::new(&reply) {
+                    Ok(reply) => Self::ExtendedReply(reply),
+                    Err(_) => Self::error(reply.id, StatusCode::Failure),
+                }
+            }
+        }
+    };
+}
+
+/// Implementation for `posix-rename@openssh.com`.
+/// Unlike SSH_FXP_RENAME, an existing `newpath` is replaced
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PosixRename {
+    pub id: u32,
+    pub oldpath: String,
+    pub newpath: String,
+}
+
+impl_extension!(PosixRename, "posix-rename@openssh.com");
+
+/// Implementation for `statvfs@openssh.com`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Statvfs {
+    pub id: u32,
+    pub path: String,
+}
+
+impl_extension!(Statvfs, "statvfs@openssh.com");
+
+/// Implementation for `fstatvfs@openssh.com`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Fstatvfs {
+    pub id: u32,
+    pub handle: String,
+}
+
+impl_extension!(Fstatvfs, "fstatvfs@openssh.com");
+
+/// Reply to `statvfs@openssh.com` and `fstatvfs@openssh.com`,
+/// with the fields of `statvfs(3)`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct StatvfsReply {
+    pub id: u32,
+    pub f_bsize: u64,
+    pub f_frsize: u64,
+    pub f_blocks: u64,
+    pub f_bfree: u64,
+    pub f_bavail: u64,
+    pub f_files: u64,
+    pub f_ffree: u64,
+    pub f_favail: u64,
+    pub f_fsid: u64,
+    /// Made of [`StatvfsReply::ST_RDONLY`] and [`StatvfsReply::ST_NOSUID`]
+    pub f_flag: u64,
+    pub f_namemax: u64,
+}
+
+impl_extended_reply!(StatvfsReply);
+
+impl StatvfsReply {
+    pub const ST_RDONLY: u64 = 0x1;
+    pub const ST_NOSUID: u64 = 0x2;
+}
+
+/// Implementation for `hardlink@openssh.com`, creating `newpath` as a link to `oldpath`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Hardlink {
+    pub id: u32,
+    pub oldpath: String,
+    pub newpath: String,
+}
+
+impl_extension!(Hardlink, "hardlink@openssh.com");
+
+/// Implementation for `fsync@openssh.com`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Fsync {
+    pub id: u32,
+    pub handle: String,
+}
+
+impl_extension!(Fsync, "fsync@openssh.com");
+
+/// Implementation for `lsetstat@openssh.com`,
+/// a SSH_FXP_SETSTAT that does not follow symbolic links
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LSetStat {
+    pub id: u32,
+    pub path: String,
+    pub attrs: FileAttributes,
+}
+
+impl_extension!(LSetStat, "lsetstat@openssh.com");
+
+/// Implementation for `limits@openssh.com`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Limits {
+    pub id: u32,
+}
+
+impl_extension!(Limits, "limits@openssh.com");
+
+/// Reply to `limits@openssh.com`. Zero stands for no limit
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LimitsReply {
+    pub id: u32,
+    pub max_packet_length: u64,
+    pub max_read_length: u64,
+    pub max_write_length: u64,
+    pub max_open_handles: u64,
+}
+
+impl_extended_reply!(LimitsReply);
+
+/// Implementation for `expand-path@openssh.com`, a SSH_FXP_REALPATH
+/// where a leading `~` stands for the home directory.
+/// Answered with SSH_FXP_NAME
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExpandPath {
+    pub id: u32,
+    pub path: String,
+}
+
+impl_extension!(ExpandPath, "expand-path@openssh.com");
+
+/// Implementation for `copy-data`, copying between two open files on the
+/// server. A `read_data_length` of zero copies until the end of the file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CopyData {
+    pub id: u32,
+    pub read_from_handle: String,
+    pub read_from_offset: u64,
+    pub read_data_length: u64,
+    pub write_to_handle: String,
+    pub write_to_offset: u64,
+}
+
+impl_extension!(CopyData, "copy-data");
+
+/// Implementation for `check-file-name`, hashing `length` bytes of a file from
+/// `start_offset` with the first known algorithm of the comma separated
+/// `hash_algorithms`. A `length` of zero hashes until the end of the file,
+/// a `block_size` of zero hashes the whole range at once
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckFileName {
+    pub id: u32,
+    pub filename: String,
+    pub hash_algorithms: String,
+    pub start_offset: u64,
+    pub length: u64,
+    pub block_size: u32,
+}
+
+impl_extension!(CheckFileName, "check-file-name");
+
+/// Implementation for `check-file-handle`, the same as
+/// [`CheckFileName`] for an open file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckFileHandle {
+    pub id: u32,
+    pub handle: String,
+    pub hash_algorithms: String,
+    pub start_offset: u64,
+    pub length: u64,
+    pub block_size: u32,
+}
+
+impl_extension!(CheckFileHandle, "check-file-handle");
+
+/// Reply to `check-file-name` and `check-file-handle`,
+/// with the hashes of the blocks one after the other
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckFileReply {
+    pub id: u32,
+    pub hash_algorithm: String,
+    pub hashes: Vec<u8>,
+}
+
+impl_request_id!(CheckFileReply);
+
+impl CheckFileReply {
+    /// Name starting the reply
+    pub const NAME: &'static str = "check-file";
+}
+
+impl From<CheckFileReply> for Packet {
+    fn from(reply: CheckFileReply) -> Self {
+        //the hashes take the rest of the packet, without a length
+        match ExtendedReply::new(&(reply.id, CheckFileReply::NAME, &reply.hash_algorithm)) {
+            Ok(mut extended) => {
+                extended.data.extend_from_slice(&reply.hashes);
+                Self::ExtendedReply(extended)
+            }
+            Err(_) => Self::error(reply.id, StatusCode::Failure),
+        }
+    }
+}
+
+impl TryFrom<ExtendedReply> for CheckFileReply {
+    type Error = Error;
+
+    fn try_from(reply: ExtendedReply) -> Result<Self, Self::Error> {
+        let mut data = Bytes::from(reply.data);
+        if data.try_get_string()? != Self::NAME {
+            return Err(Error::BadMessage);
+        }
+
+        Ok(Self {
+            id: reply.id,
+            hash_algorithm: data.try_get_string()?,
+            hashes: data.to_vec(),
+        })
+    }
+}
+
+/// Implementation for `md5-hash`, hashing `length` bytes of a file from
+/// `start_offset`, or until the end of the file when `length` is zero.
+/// Unless `quick_check_hash` is empty, it must be the hash of the first
+/// 2048 bytes of the range for the range to be hashed
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Md5Hash {
+    pub id: u32,
+    pub filename: String,
+    pub start_offset: u64,
+    pub length: u64,
+    pub quick_check_hash: Vec<u8>,
+}
+
+impl_extension!(Md5Hash, "md5-hash");
+
+/// Implementation for `md5-hash-handle`, the same as [`Md5Hash`] for an open file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Md5HashHandle {
+    pub id: u32,
+    pub handle: String,
+    pub start_offset: u64,
+    pub length: u64,
+    pub quick_check_hash: Vec<u8>,
+}
+
+impl_extension!(Md5HashHandle, "md5-hash-handle");
+
+/// Reply to `md5-hash` and `md5-hash-handle`.
+/// The hash is empty if the quick check failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Md5HashReply {
+    pub id: u32,
+    pub hash: Vec<u8>,
+}
+
+impl_request_id!(Md5HashReply);
+
+impl Md5HashReply {
+    /// Name starting the reply
+    pub const NAME: &'static str = "md5-hash";
+}
+
+impl From<Md5HashReply> for Packet {
+    fn from(reply: Md5HashReply) -> Self {
+        match ExtendedReply::new(&(reply.id, Md5HashReply::NAME, &reply.hash)) {
+            Ok(extended) => Self::ExtendedReply(extended),
+            Err(_) => Self::error(reply.id, StatusCode::Failure),
+        }
+    }
+}
+
+impl TryFrom<ExtendedReply> for Md5HashReply {
+    type Error = Error;
+
+    fn try_from(reply: ExtendedReply) -> Result<Self, Self::Error> {
+        let (id, name, hash) = reply.parse::<(u32, String, Vec<u8>)>()?;
+        match name == Self::NAME {
+            true => Ok(Self { id, hash }),
+            false => Err(Error::BadMessage),
+        }
+    }
+}
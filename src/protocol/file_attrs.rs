@@ -1,40 +1,295 @@
-use bytes::{BufMut, Bytes, BytesMut};
-use serde::{Serialize, ser::SerializeStruct};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use serde::{
+    de::{Error as DeError, SeqAccess, Visitor},
+    ser::SerializeStruct,
+    Deserialize, Deserializer, Serialize,
+};
 #[cfg(unix)]
-use std::os::unix::fs::MetadataExt;
-use std::{fs::Metadata, time::UNIX_EPOCH};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::{
+    fmt,
+    fs::{self, FileTimes, Metadata, Permissions},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use crate::{buf::TryBuf, error, utils};
+use super::current_version;
+use crate::{error, utils};
 
-    /// Attributes flags according to the specification
+/// Attributes flags according to the specification
 #[derive(Default, Serialize, Deserialize)]
 pub struct FileAttr(u32);
 
-    /// Types according to mode unix
-#[derive(Default, Serialize, Deserialize)]
-pub struct FileType(u32);
+/// Permission bits of a unix mode, without the file type
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FilePermissions(u32);
 
 bitflags! {
     impl FileAttr: u32 {
         const SIZE = 0x00000001;
+        /// Only in version 3
         const UIDGID = 0x00000002;
         const PERMISSIONS = 0x00000004;
+        /// Access and modify time in version 3
         const ACMODTIME = 0x00000008;
+        /// Access time since version 4
+        const ACCESSTIME = 0x00000008;
+        const CREATETIME = 0x00000010;
+        const MODIFYTIME = 0x00000020;
+        const ACL = 0x00000040;
+        const OWNERGROUP = 0x00000080;
+        const SUBSECOND_TIMES = 0x00000100;
+        /// Since version 5
+        const BITS = 0x00000200;
+        /// Since version 6
+        const ALLOCATION_SIZE = 0x00000400;
+        const TEXT_HINT = 0x00000800;
+        const MIME_TYPE = 0x00001000;
+        const LINK_COUNT = 0x00002000;
+        const UNTRANSLATED_NAME = 0x00004000;
+        const CTIME = 0x00008000;
+        const EXTENDED = 0x80000000;
+    }
+
+    impl FilePermissions: u32 {
+        const SET_UID = 0o4000;
+        const SET_GID = 0o2000;
+        const STICKY = 0o1000;
+        const OWNER_READ = 0o400;
+        const OWNER_WRITE = 0o200;
+        const OWNER_EXEC = 0o100;
+        const GROUP_READ = 0o040;
+        const GROUP_WRITE = 0o020;
+        const GROUP_EXEC = 0o010;
+        const OTHER_READ = 0o004;
+        const OTHER_WRITE = 0o002;
+        const OTHER_EXEC = 0o001;
+    }
+}
+
+impl FileAttr {
+    /// Flags known in the protocol `version`, apart from EXTENDED
+    pub fn supported(version: u32) -> Self {
+        let v4 = Self::SIZE
+            | Self::PERMISSIONS
+            | Self::ACCESSTIME
+            | Self::CREATETIME
+            | Self::MODIFYTIME
+            | Self::ACL
+            | Self::OWNERGROUP
+            | Self::SUBSECOND_TIMES;
+
+        match version {
+            ..=3 => Self::SIZE | Self::UIDGID | Self::PERMISSIONS | Self::ACMODTIME,
+            4 => v4,
+            5 => v4 | Self::BITS,
+            _ => {
+                v4 | Self::BITS
+                    | Self::ALLOCATION_SIZE
+                    | Self::TEXT_HINT
+                    | Self::MIME_TYPE
+                    | Self::LINK_COUNT
+                    | Self::UNTRANSLATED_NAME
+                    | Self::CTIME
+            }
+        }
+    }
+}
+
+/// Mask of the file type in a unix mode
+const S_IFMT: u32 = 0o170000;
+
+/// Letters of `ls -l` for each permission bit, in order
+const RWX: [(FilePermissions, char); 9] = [
+    (FilePermissions::OWNER_READ, 'r'),
+    (FilePermissions::OWNER_WRITE, 'w'),
+    (FilePermissions::OWNER_EXEC, 'x'),
+    (FilePermissions::GROUP_READ, 'r'),
+    (FilePermissions::GROUP_WRITE, 'w'),
+    (FilePermissions::GROUP_EXEC, 'x'),
+    (FilePermissions::OTHER_READ, 'r'),
+    (FilePermissions::OTHER_WRITE, 'w'),
+    (FilePermissions::OTHER_EXEC, 'x'),
+];
+
+/// Special bits shown in place of the execute letter of owner, group and other
+const SPECIAL: [(FilePermissions, char); 3] = [
+    (FilePermissions::SET_UID, 's'),
+    (FilePermissions::SET_GID, 's'),
+    (FilePermissions::STICKY, 't'),
+];
+
+impl FilePermissions {
+    /// Permissions of a unix mode, leaving out its type bits
+    pub fn from_mode(mode: u32) -> Self {
+        Self::from_bits_truncate(mode)
+    }
+
+    /// Octal form such as `0755`
+    pub fn octal(&self) -> String {
+        format!("{:04o}", self.bits())
+    }
+
+    /// Form of `ls -l` such as `rwxr-sr-t`
+    pub fn rwx(&self) -> String {
+        RWX.iter()
+            .enumerate()
+            .map(|(i, &(flag, letter))| {
+                let (special, s) = SPECIAL[i / 3];
+                match (self.contains(flag), letter == 'x' && self.contains(special)) {
+                    (true, true) => s,
+                    (false, true) => s.to_ascii_uppercase(),
+                    (true, false) => letter,
+                    (false, false) => '-',
+                }
+            })
+            .collect()
+    }
+
+    /// Reads an octal form such as `755` or `0755`
+    pub fn from_octal(octal: &str) -> Option<Self> {
+        Self::from_bits(u32::from_str_radix(octal, 8).ok()?)
+    }
+
+    /// Reads the form of `ls -l` such as `rwxr-xr-x`
+    pub fn from_rwx(rwx: &str) -> Option<Self> {
+        if rwx.chars().count() != RWX.len() {
+            return None;
+        }
+
+        let mut permissions = Self::empty();
+        for (i, (&(flag, letter), found)) in RWX.iter().zip(rwx.chars()).enumerate() {
+            let (special, s) = SPECIAL[i / 3];
+            match found {
+                '-' => (),
+                _ if found == letter => permissions |= flag,
+                _ if letter == 'x' && found == s => permissions |= flag | special,
+                _ if letter == 'x' && found == s.to_ascii_uppercase() => permissions |= special,
+                _ => return None,
+            }
+        }
+
+        Some(permissions)
+    }
+}
+
+#[cfg(unix)]
+impl From<FilePermissions> for Permissions {
+    fn from(permissions: FilePermissions) -> Self {
+        Permissions::from_mode(permissions.bits())
+    }
+}
+
+/// Outside of unix only the read-only flag is known, giving `0555` or `0777`
+impl From<&Permissions> for FilePermissions {
+    fn from(permissions: &Permissions) -> Self {
+        #[cfg(unix)]
+        let mode = permissions.mode();
+        #[cfg(not(unix))]
+        let mode = match permissions.readonly() {
+            true => 0o555,
+            false => 0o777,
+        };
+
+        Self::from_mode(mode)
+    }
+}
+
+/// Type of a file from the type bits of a unix mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileType {
+    Regular,
+    Dir,
+    Symlink,
+    Fifo,
+    CharDevice,
+    BlockDevice,
+    Socket,
+    /// No type bits, or ones that are not known
+    Unknown,
+}
+
+impl FileType {
+    /// Type of a unix mode
+    pub fn from_mode(mode: u32) -> Self {
+        match mode & S_IFMT {
+            0o100000 => Self::Regular,
+            0o040000 => Self::Dir,
+            0o120000 => Self::Symlink,
+            0o010000 => Self::Fifo,
+            0o020000 => Self::CharDevice,
+            0o060000 => Self::BlockDevice,
+            0o140000 => Self::Socket,
+            _ => Self::Unknown,
+        }
     }
 
-    impl FileType: u32 {
-        const FIFO = 0x1000;
-        const CHR = 0x2000;
-        const DIR = 0x4000;
-        const BLK = 0x6000;
-        const REG = 0x8000;
-        const LNK = 0xA000;
-        const NAM = 0x5000;
+    /// Type bits of a unix mode, none for [`FileType::Unknown`]
+    pub fn mode(self) -> u32 {
+        match self {
+            Self::Regular => 0o100000,
+            Self::Dir => 0o040000,
+            Self::Symlink => 0o120000,
+            Self::Fifo => 0o010000,
+            Self::CharDevice => 0o020000,
+            Self::BlockDevice => 0o060000,
+            Self::Socket => 0o140000,
+            Self::Unknown => 0,
+        }
     }
 
-    // TODO: Add FilePermission
+    /// First letter of the mode shown by `ls -l`
+    pub fn ls_char(self) -> char {
+        match self {
+            Self::Regular => '-',
+            Self::Dir => 'd',
+            Self::Symlink => 'l',
+            Self::Fifo => 'p',
+            Self::CharDevice => 'c',
+            Self::BlockDevice => 'b',
+            Self::Socket => 's',
+            Self::Unknown => '?',
+        }
+    }
+}
+
+impl From<fs::FileType> for FileType {
+    fn from(file_type: fs::FileType) -> Self {
+        #[cfg(unix)]
+        {
+            if file_type.is_fifo() {
+                return Self::Fifo;
+            } else if file_type.is_socket() {
+                return Self::Socket;
+            } else if file_type.is_char_device() {
+                return Self::CharDevice;
+            } else if file_type.is_block_device() {
+                return Self::BlockDevice;
+            }
+        }
+
+        if file_type.is_dir() {
+            Self::Dir
+        } else if file_type.is_symlink() {
+            Self::Symlink
+        } else if file_type.is_file() {
+            Self::Regular
+        } else {
+            Self::Unknown
+        }
+    }
 }
 
+/// Values of the type byte since version 4
+const SSH_FILEXFER_TYPE_REGULAR: u8 = 1;
+const SSH_FILEXFER_TYPE_DIRECTORY: u8 = 2;
+const SSH_FILEXFER_TYPE_SYMLINK: u8 = 3;
+const SSH_FILEXFER_TYPE_SPECIAL: u8 = 4;
+const SSH_FILEXFER_TYPE_UNKNOWN: u8 = 5;
+const SSH_FILEXFER_TYPE_SOCKET: u8 = 6;
+const SSH_FILEXFER_TYPE_CHAR_DEVICE: u8 = 7;
+const SSH_FILEXFER_TYPE_BLOCK_DEVICE: u8 = 8;
+const SSH_FILEXFER_TYPE_FIFO: u8 = 9;
+
 /// Used in the implementation of other packages.
 ///
 /// The fields `user` and `group` are string names of users
@@ -43,7 +298,13 @@ bitflags! {
 ///
 /// The `flags` field is omitted because it
 /// is set by itself depending on the flags
-#[derive(Debug, Deserialize)]
+///
+/// Since version 4 `user` and `group` are sent instead of `uid` and `gid`,
+/// falling back to the numbers when not set. Numeric names received are
+/// also parsed into `uid` and `gid`. The file type of version 4 is kept
+/// in the type bits of `permissions`. Fields that the negotiated version
+/// does not know are not sent
+#[derive(Debug, Clone)]
 pub struct FileAttributes {
     pub size: Option<u64>,
     pub uid: Option<u32>,
@@ -51,50 +312,123 @@ pub struct FileAttributes {
     pub gid: Option<u32>,
     pub group: Option<String>,
     pub permissions: Option<u32>,
-    pub atime: Option<u32>,
-    pub mtime: Option<u32>,
+    pub atime: Option<u64>,
+    pub mtime: Option<u64>,
+    /// Nanoseconds of `atime`, since version 4
+    pub atime_nseconds: Option<u32>,
+    /// Nanoseconds of `mtime`, since version 4
+    pub mtime_nseconds: Option<u32>,
+    /// Since version 4
+    pub createtime: Option<u64>,
+    /// Nanoseconds of `createtime`, since version 4
+    pub createtime_nseconds: Option<u32>,
+    /// Time of the last change of attributes, since version 6
+    pub ctime: Option<u64>,
+    /// Nanoseconds of `ctime`, since version 6
+    pub ctime_nseconds: Option<u32>,
+    /// ACL as sent on the wire, since version 4
+    pub acl: Option<Vec<u8>>,
+    /// Since version 5
+    pub attrib_bits: Option<u32>,
+    /// Which of `attrib_bits` are known, since version 6.
+    /// All of them when not set
+    pub attrib_bits_valid: Option<u32>,
+    /// Since version 6
+    pub allocation_size: Option<u64>,
+    /// Since version 6
+    pub text_hint: Option<u8>,
+    /// Since version 6
+    pub mime_type: Option<String>,
+    /// Since version 6
+    pub link_count: Option<u32>,
+    /// Since version 6
+    pub untranslated_name: Option<String>,
 }
 
 macro_rules! impl_fn_type {
-    ($get_name:ident, $set_name:ident, $doc_name:expr, $flag:ident) => {
+    ($get_name:ident, $set_name:ident, $doc_name:expr, $type:ident) => {
         #[doc = "Returns `true` if is a "]
         #[doc = $doc_name]
         pub fn $get_name(&self) -> bool {
-            self.permissions.map_or(false, |b| {
-                FileType::from_bits_truncate(b).contains(FileType::$flag)
-            })
+            self.file_type() == FileType::$type
         }
 
-        #[doc = "Set flag if is a "]
+        #[doc = "Set type if is a "]
         #[doc = $doc_name]
         #[doc = " or not"]
         pub fn $set_name(&mut self, $get_name: bool) {
             match $get_name {
-                true => self.set_type(FileType::$flag),
-                false => self.remove_type(FileType::$flag),
+                true => self.set_type(FileType::$type),
+                false => self.remove_type(FileType::$type),
             }
         }
     };
 }
 
 impl FileAttributes {
-    impl_fn_type!(is_dir, set_dir, "dir", DIR);
-    impl_fn_type!(is_regular, set_regular, "regular", REG);
-    impl_fn_type!(is_symlink, set_symlink, "symlink", LNK);
-    impl_fn_type!(is_character, set_character, "character", CHR);
-    impl_fn_type!(is_block, set_block, "block", BLK);
-    impl_fn_type!(is_fifo, set_fifo, "fifo", FIFO);
-
-    /// Set type flag
+    impl_fn_type!(is_dir, set_dir, "dir", Dir);
+    impl_fn_type!(is_regular, set_regular, "regular", Regular);
+    impl_fn_type!(is_symlink, set_symlink, "symlink", Symlink);
+    impl_fn_type!(is_character, set_character, "character", CharDevice);
+    impl_fn_type!(is_block, set_block, "block", BlockDevice);
+    impl_fn_type!(is_fifo, set_fifo, "fifo", Fifo);
+    impl_fn_type!(is_socket, set_socket, "socket", Socket);
+
+    /// Type from the type bits of `permissions`
+    pub fn file_type(&self) -> FileType {
+        FileType::from_mode(self.permissions.unwrap_or(0))
+    }
+
+    /// Replace the type bits of `permissions`
     pub fn set_type(&mut self, r#type: FileType) {
         let perms = self.permissions.unwrap_or(0);
-        self.permissions = Some(perms | r#type.bits());
+        self.permissions = Some(perms & !S_IFMT | r#type.mode());
     }
 
-    /// Remove type flag
+    /// Clear the type bits of `permissions` if they are of `type`
     pub fn remove_type(&mut self, r#type: FileType) {
+        if self.file_type() == r#type {
+            self.set_type(FileType::Unknown);
+        }
+    }
+
+    /// Permission bits of `permissions`, without the type
+    pub fn file_permissions(&self) -> Option<FilePermissions> {
+        self.permissions.map(FilePermissions::from_mode)
+    }
+
+    /// Replace the permission bits of `permissions`, keeping the type
+    pub fn set_file_permissions(&mut self, permissions: FilePermissions) {
         let perms = self.permissions.unwrap_or(0);
-        self.permissions = Some(perms & !r#type.bits());
+        self.permissions = Some(perms & S_IFMT | permissions.bits());
+    }
+
+    /// Attributes without any field set
+    pub fn empty() -> Self {
+        Self {
+            size: None,
+            uid: None,
+            user: None,
+            gid: None,
+            group: None,
+            permissions: None,
+            atime: None,
+            mtime: None,
+            atime_nseconds: None,
+            mtime_nseconds: None,
+            createtime: None,
+            createtime_nseconds: None,
+            ctime: None,
+            ctime_nseconds: None,
+            acl: None,
+            attrib_bits: None,
+            attrib_bits_valid: None,
+            allocation_size: None,
+            text_hint: None,
+            mime_type: None,
+            link_count: None,
+            untranslated_name: None,
+        }
     }
 }
 
@@ -104,12 +438,11 @@ impl Default for FileAttributes {
         Self {
             size: Some(0),
             uid: Some(0),
-            user: None,
             gid: Some(0),
-            group: None,
-            permissions: Some(0o777 | FileType::DIR.bits()),
+            permissions: Some(0o777 | FileType::Dir.mode()),
             atime: Some(0),
             mtime: Some(0),
+            ..Self::empty()
         }
     }
 }
@@ -117,34 +450,107 @@ impl Default for FileAttributes {
 /// For simple conversion of `Metadata` into file attributes
 impl From<&Metadata> for FileAttributes {
     fn from(metadata: &Metadata) -> Self {
-        let mut attrs = Self {
+        let permissions = FilePermissions::from(&metadata.permissions()).bits()
+            | FileType::from(metadata.file_type()).mode();
+        let (atime, atime_nseconds) = utils::unix(metadata.accessed().unwrap_or(UNIX_EPOCH));
+        let (mtime, mtime_nseconds) = utils::unix(metadata.modified().unwrap_or(UNIX_EPOCH));
+        let (createtime, createtime_nseconds) = metadata.created().ok().map(utils::unix).unzip();
+
+        Self {
             size: Some(metadata.len()),
             #[cfg(unix)]
             uid: Some(metadata.uid()),
             #[cfg(unix)]
             gid: Some(metadata.gid()),
-            #[cfg(windows)]
-            permissions: Some(if metadata.permissions().readonly() {
-                0o555
-            } else {
-                0o777
-            }),
+            permissions: Some(permissions),
+            atime: Some(atime),
+            atime_nseconds: Some(atime_nseconds),
+            mtime: Some(mtime),
+            mtime_nseconds: Some(mtime_nseconds),
+            createtime,
+            createtime_nseconds,
+            #[cfg(unix)]
+            ctime: Some(metadata.ctime().max(0) as u64),
+            #[cfg(unix)]
+            ctime_nseconds: Some(metadata.ctime_nsec() as u32),
             #[cfg(unix)]
-            permissions: Some(metadata.mode()),
-            atime: Some(utils::unix(metadata.modified().unwrap_or(UNIX_EPOCH))),
-            mtime: Some(utils::unix(metadata.accessed().unwrap_or(UNIX_EPOCH))),
+            link_count: Some(metadata.nlink() as u32),
             ..Default::default()
-        };
+        }
+    }
+}
+
+impl FileAttributes {
+    /// Access time with its nanoseconds, if `atime` is set and representable
+    pub fn accessed(&self) -> Option<SystemTime> {
+        let nseconds = self.atime_nseconds.unwrap_or(0);
+        self.atime
+            .and_then(|atime| utils::system_time(atime, nseconds))
+    }
 
-        attrs.set_dir(metadata.is_dir());
-        attrs.set_regular(!metadata.is_dir());
+    /// Modification time with its nanoseconds, if `mtime` is set and representable
+    pub fn modified(&self) -> Option<SystemTime> {
+        let nseconds = self.mtime_nseconds.unwrap_or(0);
+        self.mtime
+            .and_then(|mtime| utils::system_time(mtime, nseconds))
+    }
+
+    /// Times to apply with [`std::fs::File::set_times`], if any is set
+    pub fn file_times(&self) -> Option<FileTimes> {
+        let (accessed, modified) = (self.accessed(), self.modified());
+        if accessed.is_none() && modified.is_none() {
+            return None;
+        }
+
+        let mut times = FileTimes::new();
+        if let Some(accessed) = accessed {
+            times = times.set_accessed(accessed);
+        }
+        if let Some(modified) = modified {
+            times = times.set_modified(modified);
+        }
 
-        attrs
+        Some(times)
     }
 }
 
-impl Serialize for FileAttributes {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+impl FileAttributes {
+    /// Type byte of version 4 and later from the type bits of `permissions`
+    fn type_byte(&self, version: u32) -> u8 {
+        let special = |r#type| match version {
+            ..=4 => SSH_FILEXFER_TYPE_SPECIAL,
+            _ => r#type,
+        };
+
+        match self.file_type() {
+            FileType::Regular => SSH_FILEXFER_TYPE_REGULAR,
+            FileType::Dir => SSH_FILEXFER_TYPE_DIRECTORY,
+            FileType::Symlink => SSH_FILEXFER_TYPE_SYMLINK,
+            FileType::Socket => special(SSH_FILEXFER_TYPE_SOCKET),
+            FileType::CharDevice => special(SSH_FILEXFER_TYPE_CHAR_DEVICE),
+            FileType::BlockDevice => special(SSH_FILEXFER_TYPE_BLOCK_DEVICE),
+            FileType::Fifo => special(SSH_FILEXFER_TYPE_FIFO),
+            FileType::Unknown => SSH_FILEXFER_TYPE_UNKNOWN,
+        }
+    }
+
+    /// Type bits of a unix mode from the type byte of version 4 and later
+    fn type_mode(type_byte: u8) -> u32 {
+        let r#type = match type_byte {
+            SSH_FILEXFER_TYPE_REGULAR => FileType::Regular,
+            SSH_FILEXFER_TYPE_DIRECTORY => FileType::Dir,
+            SSH_FILEXFER_TYPE_SYMLINK => FileType::Symlink,
+            SSH_FILEXFER_TYPE_SOCKET => FileType::Socket,
+            SSH_FILEXFER_TYPE_CHAR_DEVICE => FileType::CharDevice,
+            SSH_FILEXFER_TYPE_BLOCK_DEVICE => FileType::BlockDevice,
+            SSH_FILEXFER_TYPE_FIFO => FileType::Fifo,
+            _ => FileType::Unknown,
+        };
+
+        r#type.mode()
+    }
+
+    fn serialize_v3<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
@@ -183,12 +589,297 @@ impl Serialize for FileAttributes {
         }
 
         if self.atime.is_some() || self.mtime.is_some() {
-            s.serialize_field("atime", &self.atime.unwrap_or(0))?;
-            s.serialize_field("mtime", &self.mtime.unwrap_or(0))?;
+            s.serialize_field("atime", &(self.atime.unwrap_or(0) as u32))?;
+            s.serialize_field("mtime", &(self.mtime.unwrap_or(0) as u32))?;
         }
 
         s.end()
     }
+
+    fn serialize_v4<S>(&self, serializer: S, version: u32) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut attrs = FileAttr::default();
+        attrs.set(FileAttr::SIZE, self.size.is_some());
+        attrs.set(FileAttr::PERMISSIONS, self.permissions.is_some());
+        attrs.set(FileAttr::ACCESSTIME, self.atime.is_some());
+        attrs.set(FileAttr::CREATETIME, self.createtime.is_some());
+        attrs.set(FileAttr::MODIFYTIME, self.mtime.is_some());
+        attrs.set(FileAttr::CTIME, self.ctime.is_some());
+        attrs.set(FileAttr::ACL, self.acl.is_some());
+        attrs.set(
+            FileAttr::OWNERGROUP,
+            self.user.is_some() || self.group.is_some() || self.uid.is_some() || self.gid.is_some(),
+        );
+        attrs.set(
+            FileAttr::SUBSECOND_TIMES,
+            self.atime_nseconds.is_some()
+                || self.mtime_nseconds.is_some()
+                || self.createtime_nseconds.is_some()
+                || self.ctime_nseconds.is_some(),
+        );
+        attrs.set(FileAttr::BITS, self.attrib_bits.is_some());
+        attrs.set(FileAttr::ALLOCATION_SIZE, self.allocation_size.is_some());
+        attrs.set(FileAttr::TEXT_HINT, self.text_hint.is_some());
+        attrs.set(FileAttr::MIME_TYPE, self.mime_type.is_some());
+        attrs.set(FileAttr::LINK_COUNT, self.link_count.is_some());
+        attrs.set(
+            FileAttr::UNTRANSLATED_NAME,
+            self.untranslated_name.is_some(),
+        );
+        attrs &= FileAttr::supported(version);
+
+        let subsecond = attrs.contains(FileAttr::SUBSECOND_TIMES);
+        let mut s = serializer.serialize_struct("FileAttributes", FIELDS.len())?;
+        s.serialize_field("attrs", &attrs)?;
+        s.serialize_field("type", &self.type_byte(version))?;
+
+        if attrs.contains(FileAttr::SIZE) {
+            s.serialize_field("size", &self.size)?;
+        }
+
+        if attrs.contains(FileAttr::ALLOCATION_SIZE) {
+            s.serialize_field("allocation_size", &self.allocation_size)?;
+        }
+
+        if attrs.contains(FileAttr::OWNERGROUP) {
+            let name = |name: &Option<String>, id: Option<u32>| {
+                name.clone()
+                    .or(id.map(|id| id.to_string()))
+                    .unwrap_or_default()
+            };
+            s.serialize_field("owner", &name(&self.user, self.uid))?;
+            s.serialize_field("group", &name(&self.group, self.gid))?;
+        }
+
+        if let Some(permissions) = self.permissions {
+            s.serialize_field("permissions", &(permissions & !S_IFMT))?;
+        }
+
+        for (flag, time, nseconds) in [
+            (FileAttr::ACCESSTIME, self.atime, self.atime_nseconds),
+            (
+                FileAttr::CREATETIME,
+                self.createtime,
+                self.createtime_nseconds,
+            ),
+            (FileAttr::MODIFYTIME, self.mtime, self.mtime_nseconds),
+            (FileAttr::CTIME, self.ctime, self.ctime_nseconds),
+        ] {
+            if attrs.contains(flag) {
+                s.serialize_field("time", &time.unwrap_or(0))?;
+                if subsecond {
+                    s.serialize_field("nseconds", &nseconds.unwrap_or(0))?;
+                }
+            }
+        }
+
+        if attrs.contains(FileAttr::ACL) {
+            s.serialize_field("acl", &self.acl)?;
+        }
+
+        if attrs.contains(FileAttr::BITS) {
+            s.serialize_field("attrib_bits", &self.attrib_bits)?;
+            if version >= 6 {
+                let valid = self.attrib_bits_valid.unwrap_or(u32::MAX);
+                s.serialize_field("attrib_bits_valid", &valid)?;
+            }
+        }
+
+        if attrs.contains(FileAttr::TEXT_HINT) {
+            s.serialize_field("text_hint", &self.text_hint)?;
+        }
+
+        if attrs.contains(FileAttr::MIME_TYPE) {
+            s.serialize_field("mime_type", &self.mime_type)?;
+        }
+
+        if attrs.contains(FileAttr::LINK_COUNT) {
+            s.serialize_field("link_count", &self.link_count)?;
+        }
+
+        if attrs.contains(FileAttr::UNTRANSLATED_NAME) {
+            s.serialize_field("untranslated_name", &self.untranslated_name)?;
+        }
+
+        s.end()
+    }
+}
+
+impl Serialize for FileAttributes {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match current_version() {
+            ..=3 => self.serialize_v3(serializer),
+            version => self.serialize_v4(serializer, version),
+        }
+    }
+}
+
+/// Upper bound of the fields read in any version
+const FIELDS: &[&str] = &[
+    "attrs",
+    "type",
+    "size",
+    "allocation_size",
+    "uid",
+    "gid",
+    "permissions",
+    "atime",
+    "atime_nseconds",
+    "createtime",
+    "createtime_nseconds",
+    "mtime",
+    "mtime_nseconds",
+    "ctime",
+    "ctime_nseconds",
+    "acl",
+    "attrib_bits",
+    "attrib_bits_valid",
+    "text_hint",
+    "mime_type",
+    "link_count",
+    "untranslated_name",
+    "extended",
+];
+
+/// Vendor attributes, read to skip them
+type ExtendedAttrs = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Reads the next field only if its flag is present
+fn next_field<'de, A, T>(seq: &mut A, present: bool) -> Result<Option<T>, A::Error>
+where
+    A: SeqAccess<'de>,
+    T: Deserialize<'de>,
+{
+    if !present {
+        return Ok(None);
+    }
+
+    seq.next_element()?
+        .map(Some)
+        .ok_or_else(|| A::Error::custom("missing attribute"))
+}
+
+fn visit_v3<'de, A>(mut seq: A, attrs: FileAttr) -> Result<FileAttributes, A::Error>
+where
+    A: SeqAccess<'de>,
+{
+    let size = next_field(&mut seq, attrs.contains(FileAttr::SIZE))?;
+    let uid = next_field(&mut seq, attrs.contains(FileAttr::UIDGID))?;
+    let gid = next_field(&mut seq, attrs.contains(FileAttr::UIDGID))?;
+    let permissions = next_field(&mut seq, attrs.contains(FileAttr::PERMISSIONS))?;
+    let atime: Option<u32> = next_field(&mut seq, attrs.contains(FileAttr::ACMODTIME))?;
+    let mtime: Option<u32> = next_field(&mut seq, attrs.contains(FileAttr::ACMODTIME))?;
+    next_field::<_, ExtendedAttrs>(&mut seq, attrs.contains(FileAttr::EXTENDED))?;
+
+    Ok(FileAttributes {
+        size,
+        uid,
+        user: None,
+        gid,
+        group: None,
+        permissions,
+        atime: atime.map(u64::from),
+        mtime: mtime.map(u64::from),
+        ..FileAttributes::empty()
+    })
+}
+
+fn visit_v4<'de, A>(mut seq: A, attrs: FileAttr, version: u32) -> Result<FileAttributes, A::Error>
+where
+    A: SeqAccess<'de>,
+{
+    let v5 = version >= 5;
+    let v6 = version >= 6;
+    let subsecond = attrs.contains(FileAttr::SUBSECOND_TIMES);
+
+    let type_byte: u8 = next_field(&mut seq, true)?.unwrap_or(SSH_FILEXFER_TYPE_UNKNOWN);
+    let size = next_field(&mut seq, attrs.contains(FileAttr::SIZE))?;
+    let allocation_size = next_field(&mut seq, v6 && attrs.contains(FileAttr::ALLOCATION_SIZE))?;
+    let user: Option<String> = next_field(&mut seq, attrs.contains(FileAttr::OWNERGROUP))?;
+    let group: Option<String> = next_field(&mut seq, attrs.contains(FileAttr::OWNERGROUP))?;
+    let permissions: Option<u32> = next_field(&mut seq, attrs.contains(FileAttr::PERMISSIONS))?;
+    let atime = next_field(&mut seq, attrs.contains(FileAttr::ACCESSTIME))?;
+    let atime_nseconds = next_field(&mut seq, subsecond && atime.is_some())?;
+    let createtime = next_field(&mut seq, attrs.contains(FileAttr::CREATETIME))?;
+    let createtime_nseconds = next_field(&mut seq, subsecond && createtime.is_some())?;
+    let mtime = next_field(&mut seq, attrs.contains(FileAttr::MODIFYTIME))?;
+    let mtime_nseconds = next_field(&mut seq, subsecond && mtime.is_some())?;
+    let ctime = next_field(&mut seq, v6 && attrs.contains(FileAttr::CTIME))?;
+    let ctime_nseconds = next_field(&mut seq, subsecond && ctime.is_some())?;
+    let acl = next_field(&mut seq, attrs.contains(FileAttr::ACL))?;
+    let attrib_bits = next_field(&mut seq, v5 && attrs.contains(FileAttr::BITS))?;
+    let attrib_bits_valid = next_field(&mut seq, v6 && attrs.contains(FileAttr::BITS))?;
+    let text_hint = next_field(&mut seq, v6 && attrs.contains(FileAttr::TEXT_HINT))?;
+    let mime_type = next_field(&mut seq, v6 && attrs.contains(FileAttr::MIME_TYPE))?;
+    let link_count = next_field(&mut seq, v6 && attrs.contains(FileAttr::LINK_COUNT))?;
+    let untranslated_name =
+        next_field(&mut seq, v6 && attrs.contains(FileAttr::UNTRANSLATED_NAME))?;
+    next_field::<_, ExtendedAttrs>(&mut seq, attrs.contains(FileAttr::EXTENDED))?;
+
+    Ok(FileAttributes {
+        size,
+        uid: user.as_ref().and_then(|user| user.parse().ok()),
+        gid: group.as_ref().and_then(|group| group.parse().ok()),
+        user,
+        group,
+        //the type is kept in the mode, even when the permissions are not sent
+        permissions: match (permissions, FileAttributes::type_mode(type_byte)) {
+            (None, 0) => None,
+            (permissions, mode) => Some(permissions.unwrap_or(0) & !S_IFMT | mode),
+        },
+        atime,
+        mtime,
+        atime_nseconds,
+        mtime_nseconds,
+        createtime,
+        createtime_nseconds,
+        ctime,
+        ctime_nseconds,
+        acl,
+        attrib_bits,
+        attrib_bits_valid,
+        allocation_size,
+        text_hint,
+        mime_type,
+        link_count,
+        untranslated_name,
+    })
+}
+
+impl<'de> Deserialize<'de> for FileAttributes {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FileAttributesVisitor;
+
+        impl<'de> Visitor<'de> for FileAttributesVisitor {
+            type Value = FileAttributes;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("file attributes")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let attrs = FileAttr::from_bits_retain(next_field(&mut seq, true)?.unwrap_or(0));
+
+                match current_version() {
+                    ..=3 => visit_v3(seq, attrs),
+                    version => visit_v4(seq, attrs, version),
+                }
+            }
+        }
+
+        deserializer.deserialize_struct("FileAttributes", FIELDS, FileAttributesVisitor)
+    }
 }
 
 impl From<&FileAttributes> for Bytes {
@@ -229,8 +920,8 @@ impl From<&FileAttributes> for Bytes {
         }
 
         if file_attrs.atime.is_some() || file_attrs.mtime.is_some() {
-            bytes.put_u32(file_attrs.atime.unwrap_or(0));
-            bytes.put_u32(file_attrs.mtime.unwrap_or(0));
+            bytes.put_u32(file_attrs.atime.unwrap_or(0) as u32);
+            bytes.put_u32(file_attrs.mtime.unwrap_or(0) as u32);
         }
 
         bytes.freeze()
@@ -267,15 +958,178 @@ impl TryFrom<&mut Bytes> for FileAttributes {
                 None
             },
             atime: if attrs.contains(FileAttr::ACMODTIME) {
-                Some(bytes.try_get_u32()?)
+                Some(bytes.try_get_u32()?.into())
             } else {
                 None
             },
             mtime: if attrs.contains(FileAttr::ACMODTIME) {
-                Some(bytes.try_get_u32()?)
+                Some(bytes.try_get_u32()?.into())
             } else {
                 None
             },
+            ..Self::empty()
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::{Attrs, Packet, SSH_FXP_ATTRS};
+    #[cfg(unix)]
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_type_without_permissions() {
+        for version in [4, 5, 6] {
+            let mut attrs = FileAttributes::empty();
+            attrs.size = Some(42);
+            attrs.set_dir(true);
+
+            let mut bytes = Packet::Attrs(Attrs { id: 1, attrs })
+                .encode(version)
+                .unwrap();
+            bytes.advance(4);
+            let attrs = match Packet::decode(&mut bytes, version).unwrap() {
+                Packet::Attrs(attrs) => attrs.attrs,
+                _ => panic!("wrong packet type"),
+            };
+
+            assert!(attrs.is_dir());
+            assert_eq!(attrs.size, Some(42));
+            assert_eq!(attrs.file_permissions(), FilePermissions::from_bits(0));
+        }
+
+        //a v4 server may send the type alone
+        let mut bytes = BytesMut::new();
+        bytes.put_u8(SSH_FXP_ATTRS);
+        bytes.put_u32(1);
+        bytes.put_u32(FileAttr::SIZE.bits());
+        bytes.put_u8(SSH_FILEXFER_TYPE_REGULAR);
+        bytes.put_u64(7);
+        match Packet::decode(&mut bytes.freeze(), 4).unwrap() {
+            Packet::Attrs(attrs) => {
+                assert!(attrs.attrs.is_regular());
+                assert_eq!(attrs.attrs.size, Some(7));
+            }
+            _ => panic!("wrong packet type"),
+        }
+    }
+
+    #[test]
+    fn test_file_permissions() {
+        let permissions = FilePermissions::from_mode(0o102754);
+        assert_eq!(permissions.bits(), 0o2754);
+        assert_eq!(permissions.octal(), "2754");
+        assert_eq!(permissions.rwx(), "rwxr-sr--");
+        assert_eq!(
+            FilePermissions::from_octal("755").unwrap().rwx(),
+            "rwxr-xr-x"
+        );
+        assert_eq!(FilePermissions::from_octal("0644").unwrap().bits(), 0o644);
+        assert_eq!(FilePermissions::from_octal("100644"), None);
+        assert_eq!(FilePermissions::from_octal("8"), None);
+
+        for mode in [0o0, 0o644, 0o755, 0o1777, 0o1776, 0o4711, 0o6000, 0o7777] {
+            let permissions = FilePermissions::from_bits(mode).unwrap();
+            assert_eq!(
+                FilePermissions::from_rwx(&permissions.rwx()),
+                Some(permissions)
+            );
+        }
+        assert_eq!(FilePermissions::from_rwx("rwxr-xr-"), None);
+        assert_eq!(FilePermissions::from_rwx("rwxr-xr-s"), None);
+        assert_eq!(FilePermissions::from_rwx("xwrr-xr-x"), None);
+
+        #[cfg(unix)]
+        {
+            let std = Permissions::from(FilePermissions::from_bits(0o750).unwrap());
+            assert_eq!(std.mode() & 0o7777, 0o750);
+            assert_eq!(FilePermissions::from(&std).bits(), 0o750);
+        }
+    }
+
+    #[test]
+    fn test_file_type() {
+        let mut attrs = FileAttributes::empty();
+        assert_eq!(attrs.file_type(), FileType::Unknown);
+        assert_eq!(attrs.file_permissions(), None);
+
+        attrs.set_dir(true);
+        attrs.set_file_permissions(FilePermissions::from_bits(0o755).unwrap());
+        assert_eq!(attrs.permissions, Some(0o040755));
+
+        attrs.set_regular(true);
+        assert!(attrs.is_regular() && !attrs.is_dir());
+        assert_eq!(attrs.permissions, Some(0o100755));
+
+        attrs.set_dir(false);
+        assert!(attrs.is_regular());
+        attrs.set_regular(false);
+        assert_eq!(attrs.file_type(), FileType::Unknown);
+        assert_eq!(attrs.permissions, Some(0o755));
+
+        for r#type in [
+            FileType::Regular,
+            FileType::Dir,
+            FileType::Symlink,
+            FileType::Fifo,
+            FileType::CharDevice,
+            FileType::BlockDevice,
+            FileType::Socket,
+        ] {
+            assert_eq!(FileType::from_mode(r#type.mode() | 0o644), r#type);
+        }
+        assert_eq!(FileType::from_mode(0o050000), FileType::Unknown);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_metadata() {
+        use std::{
+            ffi::CString,
+            os::unix::{ffi::OsStrExt, fs::symlink, net::UnixListener},
+            time::Duration,
+        };
+
+        let dir = TempDir::new("metadata");
+
+        let file = fs::File::create(dir.join("file")).unwrap();
+        let times = FileTimes::new()
+            .set_accessed(UNIX_EPOCH + Duration::new(1_000_000_000, 1))
+            .set_modified(UNIX_EPOCH + Duration::new(1_500_000_000, 2));
+        file.set_times(times).unwrap();
+        let attrs = FileAttributes::from(&file.metadata().unwrap());
+        assert_eq!(attrs.file_type(), FileType::Regular);
+        assert_eq!(
+            (attrs.atime, attrs.atime_nseconds),
+            (Some(1_000_000_000), Some(1))
+        );
+        assert_eq!(
+            (attrs.mtime, attrs.mtime_nseconds),
+            (Some(1_500_000_000), Some(2))
+        );
+        assert_eq!(
+            attrs.modified(),
+            Some(UNIX_EPOCH + Duration::new(1_500_000_000, 2))
+        );
+
+        symlink("file", dir.join("link")).unwrap();
+        let _listener = UnixListener::bind(dir.join("socket")).unwrap();
+        let fifo = CString::new(dir.join("fifo").as_os_str().as_bytes()).unwrap();
+        // SAFETY: the path is nul-terminated
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+        for (name, r#type) in [
+            ("link", FileType::Symlink),
+            ("socket", FileType::Socket),
+            ("fifo", FileType::Fifo),
+            (".", FileType::Dir),
+        ] {
+            let metadata = fs::symlink_metadata(dir.join(name)).unwrap();
+            assert_eq!(FileAttributes::from(&metadata).file_type(), r#type);
+        }
+        let null = FileAttributes::from(&fs::metadata("/dev/null").unwrap());
+        assert_eq!(null.file_type(), FileType::CharDevice);
+    }
+}
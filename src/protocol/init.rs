@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use super::{VERSION, RequestId};
+use super::{VERSION_MAX, RequestId};
 
 /// Implementation for SSH_FXP_INIT
 #[derive(Debug, Serialize, Deserialize)]
@@ -10,9 +10,10 @@ pub struct Init {
 }
 
 impl Init {
+    /// Asks for the highest version supported by the crate
     pub fn new() -> Self {
         Self {
-            version: VERSION,
+            version: VERSION_MAX,
             extensions: HashMap::new(),
         }
     }
@@ -0,0 +1,13 @@
+use super::{impl_request_id, RequestId};
+
+/// Implementation for SSH_FXP_LINK, since version 6
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Link {
+    pub id: u32,
+    pub new_link_path: String,
+    pub existing_path: String,
+    /// A symbolic link is created when set, a hard link otherwise
+    pub symlink: bool,
+}
+
+impl_request_id!(Link);
@@ -1,10 +1,14 @@
 mod attrs;
+mod block;
+mod codec;
 mod data;
 mod extended;
+mod extensions;
 mod file_attrs;
 mod handle;
 mod handle_attrs;
 mod init;
+mod link;
 mod name;
 mod open;
 mod path;
@@ -17,22 +21,33 @@ mod symlink;
 mod version;
 mod write;
 
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use serde::{de::SeqAccess, Deserialize};
+use std::cell::Cell;
 
-use crate::{buf::TryBuf, de, error::Error, ser};
+use crate::{de, error::Error, framing::Frame, ser};
 
 pub mod types {
     pub use self::{
-        super::attrs::*, super::data::*, super::extended::*, super::file_attrs::*,
-        super::handle::*, super::handle_attrs::*, super::init::*, super::name::*, super::open::*,
-        super::path::*, super::path_attrs::*, super::read::*, super::remove::*, super::rename::*,
+        super::attrs::*, super::block::*, super::data::*, super::extended::*,
+        super::extensions::*, super::file_attrs::*, super::handle::*, super::handle_attrs::*,
+        super::init::*, super::link::*, super::name::*, super::open::*, super::path::*,
+        super::path_attrs::*, super::read::*, super::remove::*, super::rename::*,
         super::symlink::*, super::version::*, super::write::*,
     };
 }
-pub use status::{Status, StatusCode};
+pub use self::{
+    codec::SftpCodec,
+    status::{Status, StatusCode, StatusError},
+};
 use types::*;
 
+/// Version of the specification most implementations speak, OpenSSH included
 pub const VERSION: u32 = 3;
+/// Highest version supported by the crate
+pub const VERSION_MAX: u32 = 6;
+/// Default maximum length of a packet, the one of OpenSSH
+pub const MAX_PACKET_LEN: u32 = 256 * 1024;
 
 const SSH_FXP_INIT: u8 = 1;
 const SSH_FXP_VERSION: u8 = 2;
@@ -54,6 +69,9 @@ const SSH_FXP_STAT: u8 = 17;
 const SSH_FXP_RENAME: u8 = 18;
 const SSH_FXP_READLINK: u8 = 19;
 const SSH_FXP_SYMLINK: u8 = 20;
+const SSH_FXP_LINK: u8 = 21;
+const SSH_FXP_BLOCK: u8 = 22;
+const SSH_FXP_UNBLOCK: u8 = 23;
 
 const SSH_FXP_STATUS: u8 = 101;
 const SSH_FXP_HANDLE: u8 = 102;
@@ -64,6 +82,46 @@ const SSH_FXP_ATTRS: u8 = 105;
 const SSH_FXP_EXTENDED: u8 = 200;
 const SSH_FXP_EXTENDED_REPLY: u8 = 201;
 
+thread_local! {
+    static CURRENT_VERSION: Cell<u32> = const { Cell::new(VERSION) };
+}
+
+/// Restores the previous version when dropped
+struct VersionGuard(u32);
+
+impl Drop for VersionGuard {
+    fn drop(&mut self) {
+        CURRENT_VERSION.with(|version| version.set(self.0));
+    }
+}
+
+/// Protocol version of the packet being serialized or deserialized,
+/// so that types can choose their layout on the wire
+pub(crate) fn current_version() -> u32 {
+    CURRENT_VERSION.with(Cell::get)
+}
+
+/// Runs `f` with `version` as the [`current_version`]
+fn with_version<R>(version: u32, f: impl FnOnce() -> R) -> R {
+    let _guard = VersionGuard(CURRENT_VERSION.with(|current| current.replace(version)));
+    f()
+}
+
+/// Length of `value` once serialized in the protocol `version`
+pub(crate) fn encoded_len<T: serde::Serialize>(value: &T, version: u32) -> Result<usize, Error> {
+    with_version(version, || ser::to_bytes(value).map(|bytes| bytes.len()))
+}
+
+/// Reads a field that must be present
+pub(crate) fn next_element<'de, A, T>(seq: &mut A) -> Result<T, A::Error>
+where
+    A: SeqAccess<'de>,
+    T: Deserialize<'de>,
+{
+    seq.next_element()?
+        .ok_or_else(|| serde::de::Error::custom("missing field"))
+}
+
 pub(crate) trait RequestId: Sized {
     fn get_request_id(&self) -> u32;
 }
@@ -91,9 +149,12 @@ macro_rules! impl_packet_for {
 pub(crate) use impl_packet_for;
 pub(crate) use impl_request_id;
 
+/// Any SFTP packet. [`Packet::decode`] and [`Packet::encode`] read and write
+/// it as laid out in a protocol version, [`SftpCodec`] frames it on a stream
 #[derive(Debug)]
-pub(crate) enum Packet {
+pub enum Packet {
     Attrs(Attrs),
+    Block(Block),
     Close(Close),
     Data(Data),
     Extended(Extended),
@@ -102,6 +163,7 @@ pub(crate) enum Packet {
     FStat(FStat),
     Handle(Handle),
     Init(Init),
+    Link(Link),
     LStat(LStat),
     MkDir(MkDir),
     Name(Name),
@@ -118,35 +180,82 @@ pub(crate) enum Packet {
     Stat(Stat),
     Status(Status),
     Symlink(Symlink),
+    Unblock(Unblock),
     Version(Version),
     Write(Write),
 }
 
 impl Packet {
-    // pub fn get_request_id(&self) -> u32 {
-    //     match self {
-    //         Self::Open(open) => open.get_request_id(),
-    //         Self::Close(close) => close.get_request_id(),
-    //         Self::Read(read) => read.get_request_id(),
-    //         Self::Write(write) => write.get_request_id(),
-    //         Self::LStat(lstat) => lstat.get_request_id(),
-    //         Self::FStat(fstat) => fstat.get_request_id(),
-    //         Self::SetStat(setstat) => setstat.get_request_id(),
-    //         Self::FSetStat(fsetstat) => fsetstat.get_request_id(),
-    //         Self::OpenDir(opendir) => opendir.get_request_id(),
-    //         Self::ReadDir(readdir) => readdir.get_request_id(),
-    //         Self::Remove(remove) => remove.get_request_id(),
-    //         Self::MkDir(mkdir) => mkdir.get_request_id(),
-    //         Self::RmDir(rmdir) => rmdir.get_request_id(),
-    //         Self::RealPath(realpath) => realpath.get_request_id(),
-    //         Self::Stat(stat) => stat.get_request_id(),
-    //         Self::Rename(rename) => rename.get_request_id(),
-    //         Self::ReadLink(readlink) => readlink.get_request_id(),
-    //         Self::Symlink(symlink) => symlink.get_request_id(),
-    //         Self::Extended(extended) => extended.get_request_id(),
-    //         _ => 0,
-    //     }
-    // }
+    pub fn get_request_id(&self) -> u32 {
+        match self {
+            Self::Attrs(attrs) => attrs.get_request_id(),
+            Self::Block(block) => block.get_request_id(),
+            Self::Close(close) => close.get_request_id(),
+            Self::Data(data) => data.get_request_id(),
+            Self::Extended(extended) => extended.get_request_id(),
+            Self::ExtendedReply(reply) => reply.get_request_id(),
+            Self::FSetStat(fsetstat) => fsetstat.get_request_id(),
+            Self::FStat(fstat) => fstat.get_request_id(),
+            Self::Handle(handle) => handle.get_request_id(),
+            Self::Init(init) => init.get_request_id(),
+            Self::Link(link) => link.get_request_id(),
+            Self::LStat(lstat) => lstat.get_request_id(),
+            Self::MkDir(mkdir) => mkdir.get_request_id(),
+            Self::Name(name) => name.get_request_id(),
+            Self::Open(open) => open.get_request_id(),
+            Self::OpenDir(opendir) => opendir.get_request_id(),
+            Self::Read(read) => read.get_request_id(),
+            Self::ReadDir(readdir) => readdir.get_request_id(),
+            Self::ReadLink(readlink) => readlink.get_request_id(),
+            Self::RealPath(realpath) => realpath.get_request_id(),
+            Self::Remove(remove) => remove.get_request_id(),
+            Self::Rename(rename) => rename.get_request_id(),
+            Self::RmDir(rmdir) => rmdir.get_request_id(),
+            Self::SetStat(setstat) => setstat.get_request_id(),
+            Self::Stat(stat) => stat.get_request_id(),
+            Self::Status(status) => status.get_request_id(),
+            Self::Symlink(symlink) => symlink.get_request_id(),
+            Self::Unblock(unblock) => unblock.get_request_id(),
+            Self::Version(version) => version.get_request_id(),
+            Self::Write(write) => write.get_request_id(),
+        }
+    }
+
+    /// Name of the packet type, such as `SSH_FXP_OPEN`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Attrs(_) => "SSH_FXP_ATTRS",
+            Self::Block(_) => "SSH_FXP_BLOCK",
+            Self::Close(_) => "SSH_FXP_CLOSE",
+            Self::Data(_) => "SSH_FXP_DATA",
+            Self::Extended(_) => "SSH_FXP_EXTENDED",
+            Self::ExtendedReply(_) => "SSH_FXP_EXTENDED_REPLY",
+            Self::FSetStat(_) => "SSH_FXP_FSETSTAT",
+            Self::FStat(_) => "SSH_FXP_FSTAT",
+            Self::Handle(_) => "SSH_FXP_HANDLE",
+            Self::Init(_) => "SSH_FXP_INIT",
+            Self::Link(_) => "SSH_FXP_LINK",
+            Self::LStat(_) => "SSH_FXP_LSTAT",
+            Self::MkDir(_) => "SSH_FXP_MKDIR",
+            Self::Name(_) => "SSH_FXP_NAME",
+            Self::Open(_) => "SSH_FXP_OPEN",
+            Self::OpenDir(_) => "SSH_FXP_OPENDIR",
+            Self::Read(_) => "SSH_FXP_READ",
+            Self::ReadDir(_) => "SSH_FXP_READDIR",
+            Self::ReadLink(_) => "SSH_FXP_READLINK",
+            Self::RealPath(_) => "SSH_FXP_REALPATH",
+            Self::Remove(_) => "SSH_FXP_REMOVE",
+            Self::Rename(_) => "SSH_FXP_RENAME",
+            Self::RmDir(_) => "SSH_FXP_RMDIR",
+            Self::SetStat(_) => "SSH_FXP_SETSTAT",
+            Self::Stat(_) => "SSH_FXP_STAT",
+            Self::Status(_) => "SSH_FXP_STATUS",
+            Self::Symlink(_) => "SSH_FXP_SYMLINK",
+            Self::Unblock(_) => "SSH_FXP_UNBLOCK",
+            Self::Version(_) => "SSH_FXP_VERSION",
+            Self::Write(_) => "SSH_FXP_WRITE",
+        }
+    }
 
     pub fn status(id: u32, status_code: StatusCode, msg: &str, tag: &str) -> Self {
         Packet::Status(Status {
@@ -157,15 +266,32 @@ impl Packet {
         })
     }
 
-    pub fn error(id: u32, status_code: StatusCode) -> Self {
-        Self::status(id, status_code, &status_code.to_string(), "en-US")
+    pub fn error(id: u32, error: impl Into<StatusError>) -> Self {
+        let error = error.into();
+        Self::status(id, error.status_code, &error.message, &error.language_tag)
     }
 }
 
-impl TryFrom<&mut Bytes> for Packet {
-    type Error = Error;
+impl Packet {
+    /// Reads a packet without its length, laid out as in the protocol `version`
+    pub fn decode(bytes: &mut Bytes, version: u32) -> Result<Self, Error> {
+        with_version(version, || Self::decode_current(bytes))
+    }
 
-    fn try_from(bytes: &mut Bytes) -> Result<Self, Self::Error> {
+    /// Writes a packet with its length, laid out as in the protocol `version`
+    pub fn encode(self, version: u32) -> Result<Bytes, Error> {
+        let mut frame = self.encode_frame(version)?;
+        Ok(frame.copy_to_bytes(frame.remaining()))
+    }
+
+    /// Same as [`Packet::encode`], keeping the data of
+    /// SSH_FXP_DATA and SSH_FXP_WRITE apart from the headers
+    pub(crate) fn encode_frame(self, version: u32) -> Result<Frame, Error> {
+        with_version(version, || self.encode_current())
+    }
+
+    fn decode_current(bytes: &mut Bytes) -> Result<Self, Error> {
+        let version = current_version();
         let r#type = bytes.try_get_u8()?;
         debug!("packet type {}", r#type);
 
@@ -175,7 +301,7 @@ impl TryFrom<&mut Bytes> for Packet {
             SSH_FXP_OPEN => Self::Open(de::from_bytes(bytes)?),
             SSH_FXP_CLOSE => Self::Close(de::from_bytes(bytes)?),
             SSH_FXP_READ => Self::Read(de::from_bytes(bytes)?),
-            SSH_FXP_WRITE => Self::Write(de::from_bytes(bytes)?),
+            SSH_FXP_WRITE => Self::Write(Write::decode(bytes)?),
             SSH_FXP_LSTAT => Self::LStat(de::from_bytes(bytes)?),
             SSH_FXP_FSTAT => Self::FStat(de::from_bytes(bytes)?),
             SSH_FXP_SETSTAT => Self::SetStat(de::from_bytes(bytes)?),
@@ -190,31 +316,44 @@ impl TryFrom<&mut Bytes> for Packet {
             SSH_FXP_RENAME => Self::Rename(de::from_bytes(bytes)?),
             SSH_FXP_READLINK => Self::ReadLink(de::from_bytes(bytes)?),
             SSH_FXP_SYMLINK => Self::Symlink(de::from_bytes(bytes)?),
+            SSH_FXP_LINK if version >= 6 => Self::Link(de::from_bytes(bytes)?),
+            SSH_FXP_BLOCK if version >= 6 => Self::Block(de::from_bytes(bytes)?),
+            SSH_FXP_UNBLOCK if version >= 6 => Self::Unblock(de::from_bytes(bytes)?),
             SSH_FXP_STATUS => Self::Status(de::from_bytes(bytes)?),
             SSH_FXP_HANDLE => Self::Handle(de::from_bytes(bytes)?),
-            SSH_FXP_DATA => Self::Data(de::from_bytes(bytes)?),
+            SSH_FXP_DATA => Self::Data(Data::decode(bytes)?),
             SSH_FXP_NAME => Self::Name(de::from_bytes(bytes)?),
             SSH_FXP_ATTRS => Self::Attrs(de::from_bytes(bytes)?),
-            SSH_FXP_EXTENDED => Self::Extended(de::from_bytes(bytes)?),
-            SSH_FXP_EXTENDED_REPLY => Self::ExtendedReply(de::from_bytes(bytes)?),
+            SSH_FXP_EXTENDED => Self::Extended(Extended::decode(bytes)?),
+            SSH_FXP_EXTENDED_REPLY => Self::ExtendedReply(ExtendedReply::decode(bytes)?),
             _ => return Err(Error::BadMessage),
         };
 
+        // the attributes wanted by stat requests since version 4 are only a hint
+        if version >= 4
+            && matches!(r#type, SSH_FXP_LSTAT | SSH_FXP_FSTAT | SSH_FXP_STAT)
+            && bytes.remaining() >= 4
+        {
+            bytes.advance(4);
+        }
+
         Ok(request)
     }
-}
-
-impl TryFrom<Packet> for Bytes {
-    type Error = Error;
 
-    fn try_from(packet: Packet) -> Result<Self, Self::Error> {
-        let (r#type, payload): (u8, Bytes) = match packet {
+    fn encode_current(self) -> Result<Frame, Error> {
+        let version = current_version();
+        let mut data = Bytes::new();
+        let (r#type, payload): (u8, Bytes) = match self {
             Packet::Init(init) => (SSH_FXP_INIT, ser::to_bytes(&init)?),
             Packet::Version(version) => (SSH_FXP_VERSION, ser::to_bytes(&version)?),
             Packet::Open(open) => (SSH_FXP_OPEN, ser::to_bytes(&open)?),
             Packet::Close(close) => (SSH_FXP_CLOSE, ser::to_bytes(&close)?),
             Packet::Read(read) => (SSH_FXP_READ, ser::to_bytes(&read)?),
-            Packet::Write(write) => (SSH_FXP_WRITE, ser::to_bytes(&write)?),
+            Packet::Write(write) => {
+                let (header, payload) = write.encode();
+                data = payload;
+                (SSH_FXP_WRITE, header)
+            }
             Packet::LStat(stat) => (SSH_FXP_LSTAT, ser::to_bytes(&stat)?),
             Packet::FStat(stat) => (SSH_FXP_FSTAT, ser::to_bytes(&stat)?),
             Packet::SetStat(setstat) => (SSH_FXP_SETSTAT, ser::to_bytes(&setstat)?),
@@ -229,21 +368,58 @@ impl TryFrom<Packet> for Bytes {
             Packet::Rename(rename) => (SSH_FXP_RENAME, ser::to_bytes(&rename)?),
             Packet::ReadLink(readlink) => (SSH_FXP_READLINK, ser::to_bytes(&readlink)?),
             Packet::Symlink(symlink) => (SSH_FXP_SYMLINK, ser::to_bytes(&symlink)?),
+            Packet::Link(link) if version >= 6 => (SSH_FXP_LINK, ser::to_bytes(&link)?),
+            Packet::Block(block) if version >= 6 => (SSH_FXP_BLOCK, ser::to_bytes(&block)?),
+            Packet::Unblock(unblock) if version >= 6 => {
+                (SSH_FXP_UNBLOCK, ser::to_bytes(&unblock)?)
+            }
+            Packet::Link(_) | Packet::Block(_) | Packet::Unblock(_) => {
+                return Err(Error::BadMessage)
+            }
             Packet::Status(status) => (SSH_FXP_STATUS, ser::to_bytes(&status)?),
             Packet::Handle(handle) => (SSH_FXP_HANDLE, ser::to_bytes(&handle)?),
-            Packet::Data(data) => (SSH_FXP_DATA, ser::to_bytes(&data)?),
+            Packet::Data(reply) => {
+                let (header, payload) = reply.encode();
+                data = payload;
+                (SSH_FXP_DATA, header)
+            }
             Packet::Name(name) => (SSH_FXP_NAME, ser::to_bytes(&name)?),
             Packet::Attrs(attrs) => (SSH_FXP_ATTRS, ser::to_bytes(&attrs)?),
-            Packet::Extended(extended) => (SSH_FXP_EXTENDED, ser::to_bytes(&extended)?),
-            Packet::ExtendedReply(reply) => (SSH_FXP_EXTENDED_REPLY, ser::to_bytes(&reply)?),
+            Packet::Extended(extended) => (SSH_FXP_EXTENDED, extended.encode()),
+            Packet::ExtendedReply(reply) => (SSH_FXP_EXTENDED_REPLY, reply.encode()),
         };
 
-        let length = payload.len() as u32 + 1;
         let mut bytes = BytesMut::new();
-        bytes.put_u32(length);
+        bytes.put_u32(0);
         bytes.put_u8(r#type);
         bytes.put_slice(&payload);
-        Ok(bytes.freeze())
+
+        // ask for every attribute in stat requests since version 4
+        if version >= 4 && matches!(r#type, SSH_FXP_LSTAT | SSH_FXP_FSTAT | SSH_FXP_STAT) {
+            bytes.put_u32(FileAttr::supported(version).bits());
+        }
+
+        let length = (bytes.len() - 4 + data.len()) as u32;
+        bytes[..4].copy_from_slice(&length.to_be_bytes());
+        Ok(bytes.freeze().chain(data))
+    }
+}
+
+/// Reads a packet of [`VERSION`]
+impl TryFrom<&mut Bytes> for Packet {
+    type Error = Error;
+
+    fn try_from(bytes: &mut Bytes) -> Result<Self, Self::Error> {
+        Self::decode(bytes, VERSION)
+    }
+}
+
+/// Writes a packet of [`VERSION`]
+impl TryFrom<Packet> for Bytes {
+    type Error = Error;
+
+    fn try_from(packet: Packet) -> Result<Self, Self::Error> {
+        packet.encode(VERSION)
     }
 }
 
@@ -255,7 +431,7 @@ mod test {
     fn test_packet() {
         let packet = Packet::Data(Data {
             id: 1,
-            data: vec![1; 100],
+            data: Bytes::from(vec![1; 100]),
         });
 
         let mut bytes = Bytes::try_from(packet).unwrap();
@@ -273,4 +449,305 @@ mod test {
             _ => panic!("wrong packet type"),
         }
     }
+
+    fn round_trip(packet: Packet, version: u32) -> Packet {
+        let mut bytes = packet.encode(version).unwrap();
+        bytes::Buf::advance(&mut bytes, 4);
+        let packet = Packet::decode(&mut bytes, version).unwrap();
+        assert!(bytes.is_empty());
+        packet
+    }
+
+    #[test]
+    fn test_attrs_versions() {
+        for version in [3, 4, 5, 6] {
+            let mut attrs = FileAttributes::empty();
+            attrs.size = Some(42);
+            attrs.uid = Some(1000);
+            attrs.gid = Some(100);
+            attrs.permissions = Some(0o100644);
+            attrs.atime = Some(1 << 33);
+            attrs.mtime = Some(1_700_000_000);
+            attrs.mtime_nseconds = Some(500);
+            attrs.mime_type = Some("text/plain".to_string());
+
+            let attrs = match round_trip(Packet::Attrs(Attrs { id: 7, attrs }), version) {
+                Packet::Attrs(attrs) => attrs.attrs,
+                _ => panic!("wrong packet type"),
+            };
+
+            assert_eq!(attrs.size, Some(42));
+            assert_eq!(attrs.uid, Some(1000));
+            assert_eq!(attrs.gid, Some(100));
+            assert_eq!(attrs.permissions, Some(0o100644));
+            assert_eq!(attrs.mtime, Some(1_700_000_000));
+            match version {
+                3 => {
+                    assert_eq!(attrs.atime, Some((1u64 << 33) as u32 as u64));
+                    assert_eq!(attrs.mtime_nseconds, None);
+                    assert_eq!(attrs.mime_type, None);
+                }
+                _ => {
+                    assert_eq!(attrs.atime, Some(1 << 33));
+                    assert_eq!(attrs.mtime_nseconds, Some(500));
+                    assert_eq!(attrs.mime_type.is_some(), version >= 6);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_stat_flags() {
+        for version in [3, 6] {
+            let stat = Packet::Stat(Stat {
+                id: 1,
+                path: "/".to_string(),
+            });
+
+            match round_trip(stat, version) {
+                Packet::Stat(stat) => assert_eq!(stat.path, "/"),
+                _ => panic!("wrong packet type"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_open_v5() {
+        let open = Packet::Open(Open {
+            id: 1,
+            filename: "file".to_string(),
+            pflags: OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+            attrs: FileAttributes::empty(),
+            access: None,
+        });
+
+        let open = match round_trip(open, 5) {
+            Packet::Open(open) => open,
+            _ => panic!("wrong packet type"),
+        };
+
+        let access = open.access.unwrap();
+        assert!(access.desired_access.contains(AceMask::WRITE_DATA));
+        assert_eq!(
+            access.flags.disposition(),
+            Some(OpenDisposition::CreateTruncate)
+        );
+        assert_eq!(
+            open.pflags.bits(),
+            (OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE).bits()
+        );
+    }
+
+    #[test]
+    fn test_status_downgrade() {
+        let status = |status_code| Packet::Status(Status::new(1, status_code, ""));
+
+        for (status_code, version, expected) in [
+            (StatusCode::NoSuchPath, 3, StatusCode::NoSuchFile),
+            (StatusCode::NoSuchPath, 4, StatusCode::NoSuchPath),
+            (StatusCode::FileIsADirectory, 5, StatusCode::Failure),
+            (StatusCode::FileIsADirectory, 6, StatusCode::FileIsADirectory),
+        ] {
+            match round_trip(status(status_code), version) {
+                Packet::Status(status) => assert_eq!(status.status_code, expected),
+                _ => panic!("wrong packet type"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_link_needs_v6() {
+        let link = || {
+            Packet::Link(Link {
+                id: 1,
+                new_link_path: "link".to_string(),
+                existing_path: "file".to_string(),
+                symlink: true,
+            })
+        };
+
+        assert!(link().encode(5).is_err());
+        match round_trip(link(), 6) {
+            Packet::Link(link) => assert!(link.symlink),
+            _ => panic!("wrong packet type"),
+        }
+    }
+
+    #[test]
+    fn test_extended_layout() {
+        let rename = PosixRename {
+            id: 3,
+            oldpath: "a".to_string(),
+            newpath: "b".to_string(),
+        };
+
+        let mut bytes = Packet::Extended(Extended::new(&rename).unwrap())
+            .encode(VERSION)
+            .unwrap();
+        bytes.advance(5);
+        let mut expected = BytesMut::new();
+        expected.put_u32(3);
+        expected.put_u32(24);
+        expected.put_slice(b"posix-rename@openssh.com");
+        expected.put_slice(&[0, 0, 0, 1, b'a', 0, 0, 0, 1, b'b']);
+        assert_eq!(bytes, expected);
+
+        let rename = match Packet::decode(&mut Bytes::from([&[200], &bytes[..]].concat()), 3) {
+            Ok(Packet::Extended(extended)) => extended.parse::<PosixRename>().unwrap(),
+            _ => panic!("wrong packet type"),
+        };
+        assert_eq!((rename.id, rename.newpath.as_str()), (3, "b"));
+    }
+
+    /// Packets of every kind, flagged when no prefix of them is valid
+    fn samples() -> Vec<(Packet, bool)> {
+        let mut attrs = FileAttributes::empty();
+        attrs.size = Some(1);
+        attrs.permissions = Some(0o100644);
+        attrs.mtime = Some(2);
+        let file = File {
+            filename: "file".to_string(),
+            longname: "-rw-r--r-- 1 0 0 1 Jan 1 1970 file".to_string(),
+            attrs: FileAttributes::default(),
+        };
+
+        vec![
+            (Packet::Init(Init::new()), true),
+            (Packet::Version(Version::new()), false),
+            (
+                Packet::Open(Open {
+                    id: 1,
+                    filename: "file".to_string(),
+                    pflags: OpenFlags::READ,
+                    attrs,
+                    access: None,
+                }),
+                true,
+            ),
+            (
+                Packet::Write(Write {
+                    id: 2,
+                    handle: "1".to_string(),
+                    offset: 3,
+                    data: Bytes::from(vec![4; 16]),
+                }),
+                true,
+            ),
+            (
+                Packet::Rename(Rename {
+                    id: 3,
+                    oldpath: "a".to_string(),
+                    newpath: "b".to_string(),
+                    flags: RenameFlags::OVERWRITE,
+                }),
+                true,
+            ),
+            (
+                Packet::Stat(Stat {
+                    id: 4,
+                    path: "/".to_string(),
+                }),
+                false,
+            ),
+            (
+                Packet::Name(Name {
+                    id: 5,
+                    files: vec![file],
+                }),
+                true,
+            ),
+            (Packet::error(6, StatusCode::NoSuchFile), true),
+            (
+                Packet::Extended(Extended {
+                    id: 7,
+                    request: "limits@openssh.com".to_string(),
+                    data: vec![],
+                }),
+                true,
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_truncated_packets() {
+        for version in [3, 6] {
+            for (packet, strict) in samples() {
+                let bytes = packet.encode(version).unwrap();
+                for end in 4..bytes.len() {
+                    let result = Packet::decode(&mut bytes.slice(4..end), version);
+                    if strict {
+                        assert!(result.is_err(), "prefix of {} bytes decoded", end);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_garbage_packets() {
+        //xorshift, so that failures can be reproduced
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for version in [3, 4, 5, 6] {
+            for r#type in 0..=u8::MAX {
+                for len in 0..64 {
+                    let mut bytes = vec![r#type];
+                    bytes.extend((0..len).map(|_| next() as u8));
+                    let _ = Packet::decode(&mut Bytes::from(bytes), version);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_oversized_lengths() {
+        let mut name = BytesMut::new();
+        name.put_u8(SSH_FXP_NAME);
+        name.put_u32(1);
+        name.put_u32(u32::MAX);
+        assert!(Packet::decode(&mut name.freeze(), VERSION).is_err());
+
+        let mut string = BytesMut::new();
+        string.put_u32(u32::MAX);
+        string.put_slice(b"short");
+        assert!(de::from_bytes::<String>(&mut string.clone().freeze()).is_err());
+        assert!(de::from_bytes::<Vec<u8>>(&mut string.freeze()).is_err());
+
+        let mut truncated = Bytes::from_static(&[0, 0, 0, 1, 0, 0]);
+        assert!(de::from_bytes::<(u32, u64)>(&mut truncated).is_err());
+    }
+
+    #[test]
+    fn test_zero_copy_data() {
+        let write = Packet::Write(Write {
+            id: 1,
+            handle: "1".to_string(),
+            offset: 0,
+            data: Bytes::from(vec![7; 1024]),
+        });
+
+        let frame = write.encode_frame(VERSION).unwrap();
+        assert_eq!(frame.last_ref().len(), 1024);
+
+        let mut bytes = Packet::Write(Write {
+            id: 1,
+            handle: "1".to_string(),
+            offset: 0,
+            data: Bytes::from(vec![7; 1024]),
+        })
+        .encode(VERSION)
+        .unwrap();
+        bytes.advance(4);
+        let range = bytes.as_ptr_range();
+        match Packet::decode(&mut bytes, VERSION).unwrap() {
+            Packet::Write(write) => assert!(range.contains(&write.data.as_ptr())),
+            _ => panic!("wrong packet type"),
+        }
+    }
 }
@@ -1,8 +1,18 @@
 use chrono::{DateTime, Utc};
-use serde::{Serialize, ser::SerializeStruct};
-use std::time::{Duration, UNIX_EPOCH};
+use serde::{
+    de::{SeqAccess, Visitor},
+    ser::SerializeStruct,
+    Deserialize, Deserializer, Serialize,
+};
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use super::{impl_packet_for, impl_request_id, Packet, RequestId, FileAttributes};
+use super::{
+    current_version, impl_packet_for, impl_request_id, next_element, FileAttributes, Packet,
+    RequestId,
+};
 
 /// Implementation for SSH_FXP_NAME
 #[derive(Debug, Serialize, Deserialize)]
@@ -14,65 +24,55 @@ pub struct Name {
 impl_request_id!(Name);
 impl_packet_for!(Name);
 
-#[derive(Debug, Deserialize)]
+/// The `longname` is only sent in version 3.
+/// When empty, it is formed from the attributes like `ls -l` does
+#[derive(Debug)]
 pub struct File {
     pub filename: String,
     pub longname: String,
     pub attrs: FileAttributes,
 }
 
-impl File {
-    fn permission(&self, permission: u32) -> String {
-        let read = (permission >> 2) & 0x1;
-        let write = (permission >> 1) & 0x1;
-        let execute = permission & 0x1;
-
-        let read = if read == 0x1 { "r" } else { "-" };
-        let write = if write == 0x01 { "w" } else { "-" };
-        let execute = if execute == 0x01 { "x" } else { "-" };
+/// Files modified within this many seconds show the time instead of the year
+const RECENT: u64 = 365 * 24 * 60 * 60 / 2;
 
-        format!("{}{}{}", read, write, execute)
+impl File {
+    /// Get formed longname, laid out as by OpenSSH:
+    /// `-rw-r--r--   1 user     group        1024 Jan  2 03:04 name`.
+    /// The time is shown instead of the year for files modified
+    /// within the last six months
+    pub fn longname(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        self.longname_at(now)
     }
 
-    fn permissions(&self) -> String {
-        let permissions = self.attrs.permissions.unwrap_or(0);
-
-        let owner = self.permission((permissions >> 6) & 0x7);
-        let group = self.permission((permissions >> 3) & 0x7);
-        let other = self.permission(permissions & 0x7);
-
-        let directory = if self.attrs.is_dir() { "d" } else { "-" };
-
-        format!("{}{}{}{}", directory, owner, group, other)
-    }
+    fn longname_at(&self, now: u64) -> String {
+        let name = |name: &Option<String>, id: Option<u32>| {
+            name.clone().unwrap_or_else(|| id.unwrap_or(0).to_string())
+        };
 
-    /// Get formed longname
-    pub fn longname(&self) -> String {
-        let permissions = self.permissions();
-        let size = self.attrs.size.unwrap_or(0);
-        let uid = self.attrs.uid.unwrap_or(0);
-        let gid = self.attrs.gid.unwrap_or(0);
         let mtime = self.attrs.mtime.unwrap_or(0);
-
-        let date = UNIX_EPOCH + Duration::from_secs(mtime as u64);
-        let datetime = DateTime::<Utc>::from(date);
-        let delayed = datetime.format("%b %d %Y %H:%M");
+        //times beyond what a date can hold are shown as the epoch
+        let datetime = i64::try_from(mtime)
+            .ok()
+            .and_then(|mtime| DateTime::<Utc>::from_timestamp(mtime, 0))
+            .unwrap_or_default();
+        let date = match mtime <= now && now - mtime < RECENT {
+            true => datetime.format("%b %e %H:%M"),
+            false => datetime.format("%b %e  %Y"),
+        };
 
         format!(
-            "{} 0 {} {} {} {} {}",
-            permissions,
-            if let Some(user) = &self.attrs.user {
-                user.to_string()
-            } else {
-                uid.to_string()
-            },
-            if let Some(group) = &self.attrs.group {
-                group.to_string()
-            } else {
-                gid.to_string()
-            },
-            size,
-            delayed,
+            "{}{} {:>3} {:<8} {:<8} {:>8} {} {}",
+            self.attrs.file_type().ls_char(),
+            self.attrs.file_permissions().unwrap_or_default().rwx(),
+            self.attrs.link_count.unwrap_or(1),
+            name(&self.attrs.user, self.attrs.uid),
+            name(&self.attrs.group, self.attrs.gid),
+            self.attrs.size.unwrap_or(0),
+            date,
             self.filename
         )
     }
@@ -85,8 +85,117 @@ impl Serialize for File {
     {
         let mut s = serializer.serialize_struct("File", 3)?;
         s.serialize_field("filename", &self.filename)?;
-        s.serialize_field("longname", &self.longname())?;
+        if current_version() <= 3 {
+            match self.longname.is_empty() {
+                true => s.serialize_field("longname", &self.longname())?,
+                false => s.serialize_field("longname", &self.longname)?,
+            }
+        }
         s.serialize_field("attrs", &self.attrs)?;
         s.end()
     }
-}
\ No newline at end of file
+}
+
+impl<'de> Deserialize<'de> for File {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FileVisitor;
+
+        impl<'de> Visitor<'de> for FileVisitor {
+            type Value = File;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("file")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                Ok(File {
+                    filename: next_element(&mut seq)?,
+                    longname: match current_version() {
+                        ..=3 => next_element(&mut seq)?,
+                        _ => String::new(),
+                    },
+                    attrs: next_element(&mut seq)?,
+                })
+            }
+        }
+
+        deserializer.deserialize_struct("File", &["filename", "longname", "attrs"], FileVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Buf;
+
+    use super::*;
+    use crate::protocol::VERSION;
+
+    #[test]
+    fn test_longname() {
+        let file = |permissions, mtime| File {
+            filename: "name".to_string(),
+            longname: String::new(),
+            attrs: FileAttributes {
+                size: Some(1024),
+                uid: Some(1000),
+                gid: Some(100),
+                user: Some("user".to_string()),
+                permissions: Some(permissions),
+                mtime: Some(mtime),
+                link_count: Some(2),
+                ..FileAttributes::empty()
+            },
+        };
+
+        assert_eq!(
+            file(0o104755, 0).longname(),
+            "-rwsr-xr-x   2 user     100          1024 Jan  1  1970 name"
+        );
+        for (mode, expected) in [
+            (0o040755, "drwxr-xr-x"),
+            (0o120777, "lrwxrwxrwx"),
+            (0o010644, "prw-r--r--"),
+            (0o020600, "crw-------"),
+            (0o060660, "brw-rw----"),
+            (0o140755, "srwxr-xr-x"),
+            (0o041777, "drwxrwxrwt"),
+            (0o041776, "drwxrwxrwT"),
+            (0o102644, "-rw-r-Sr--"),
+            (0o106754, "-rwsr-sr--"),
+            (0o000644, "?rw-r--r--"),
+        ] {
+            assert!(file(mode, 0).longname().starts_with(expected), "{:o}", mode);
+        }
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let recent = file(0o100644, now - 60).longname();
+        for mtime in [i64::MAX as u64, u64::MAX] {
+            let longname = file(0o100644, mtime).longname();
+            assert!(longname.contains(" Jan  1  1970 "), "{}", longname);
+        }
+        assert!(recent.contains(':') && !recent.contains(" 1970 "));
+
+        let mut given = file(0o100644, 0);
+        given.longname = "custom".to_string();
+        let mut bytes = Packet::Name(Name {
+            id: 1,
+            files: vec![given],
+        })
+        .encode(VERSION)
+        .unwrap();
+        bytes.advance(4);
+        match Packet::decode(&mut bytes, VERSION).unwrap() {
+            Packet::Name(name) => assert_eq!(name.files[0].longname, "custom"),
+            _ => panic!("wrong packet type"),
+        }
+    }
+}
@@ -1,4 +1,11 @@
-use super::{impl_request_id, RequestId, FileAttributes};
+use serde::{
+    de::{SeqAccess, Visitor},
+    ser::SerializeStruct,
+    Deserialize, Deserializer, Serialize,
+};
+use std::fmt;
+
+use super::{current_version, impl_request_id, next_element, FileAttributes, RequestId};
 
 /// Opening flags according to the specification
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -41,13 +48,238 @@ impl OpenFlags {
     }
 }
 
+/// `desired-access` of SSH_FXP_OPEN since version 5
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AceMask(u32);
+
+bitflags! {
+    impl AceMask: u32 {
+        const READ_DATA = 0x00000001;
+        const LIST_DIRECTORY = 0x00000001;
+        const WRITE_DATA = 0x00000002;
+        const ADD_FILE = 0x00000002;
+        const APPEND_DATA = 0x00000004;
+        const ADD_SUBDIRECTORY = 0x00000004;
+        const READ_NAMED_ATTRS = 0x00000008;
+        const WRITE_NAMED_ATTRS = 0x00000010;
+        const EXECUTE = 0x00000020;
+        const DELETE_CHILD = 0x00000040;
+        const READ_ATTRIBUTES = 0x00000080;
+        const WRITE_ATTRIBUTES = 0x00000100;
+        const DELETE = 0x00010000;
+        const READ_ACL = 0x00020000;
+        const WRITE_ACL = 0x00040000;
+        const WRITE_OWNER = 0x00080000;
+        const SYNCHRONIZE = 0x00100000;
+    }
+}
+
+/// `flags` of SSH_FXP_OPEN since version 5.
+/// The lowest three bits hold the [`OpenDisposition`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenFlagsV5(u32);
+
+bitflags! {
+    impl OpenFlagsV5: u32 {
+        const ACCESS_DISPOSITION = 0x00000007;
+        const APPEND_DATA = 0x00000008;
+        const APPEND_DATA_ATOMIC = 0x00000010;
+        const TEXT_MODE = 0x00000020;
+        const BLOCK_READ = 0x00000040;
+        const BLOCK_WRITE = 0x00000080;
+        const BLOCK_DELETE = 0x00000100;
+        /// Since version 6
+        const BLOCK_ADVISORY = 0x00000200;
+        const NOFOLLOW = 0x00000400;
+        const DELETE_ON_CLOSE = 0x00000800;
+        const ACCESS_AUDIT_ALARM_INFO = 0x00001000;
+        const ACCESS_BACKUP = 0x00002000;
+        const BACKUP_STREAM = 0x00004000;
+        const OVERRIDE_OWNER = 0x00008000;
+    }
+}
+
+/// What to do depending on whether the file exists, since version 5
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenDisposition {
+    CreateNew = 0,
+    CreateTruncate = 1,
+    OpenExisting = 2,
+    OpenOrCreate = 3,
+    TruncateExisting = 4,
+}
+
+impl OpenFlagsV5 {
+    /// `None` for values the specification does not define
+    pub fn disposition(&self) -> Option<OpenDisposition> {
+        match self.bits() & Self::ACCESS_DISPOSITION.bits() {
+            0 => Some(OpenDisposition::CreateNew),
+            1 => Some(OpenDisposition::CreateTruncate),
+            2 => Some(OpenDisposition::OpenExisting),
+            3 => Some(OpenDisposition::OpenOrCreate),
+            4 => Some(OpenDisposition::TruncateExisting),
+            _ => None,
+        }
+    }
+
+    pub fn set_disposition(&mut self, disposition: OpenDisposition) {
+        *self = Self::from_bits_retain(
+            self.bits() & !Self::ACCESS_DISPOSITION.bits() | disposition as u32,
+        );
+    }
+}
+
+/// Access requested by SSH_FXP_OPEN since version 5
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OpenAccess {
+    pub desired_access: AceMask,
+    pub flags: OpenFlagsV5,
+}
+
+impl OpenAccess {
+    /// Closest flags of version 3
+    pub fn pflags(&self) -> OpenFlags {
+        let mut pflags = OpenFlags::empty();
+        pflags.set(
+            OpenFlags::READ,
+            self.desired_access.contains(AceMask::READ_DATA),
+        );
+        pflags.set(
+            OpenFlags::WRITE,
+            self.desired_access
+                .intersects(AceMask::WRITE_DATA | AceMask::APPEND_DATA),
+        );
+        pflags.set(
+            OpenFlags::APPEND,
+            self.flags
+                .intersects(OpenFlagsV5::APPEND_DATA | OpenFlagsV5::APPEND_DATA_ATOMIC),
+        );
+
+        pflags |= match self.flags.disposition() {
+            Some(OpenDisposition::CreateNew) => OpenFlags::CREATE | OpenFlags::EXCLUDE,
+            Some(OpenDisposition::CreateTruncate) => OpenFlags::CREATE | OpenFlags::TRUNCATE,
+            Some(OpenDisposition::OpenOrCreate) => OpenFlags::CREATE,
+            Some(OpenDisposition::TruncateExisting) => OpenFlags::TRUNCATE,
+            Some(OpenDisposition::OpenExisting) | None => OpenFlags::empty(),
+        };
+
+        pflags
+    }
+}
+
+impl From<&OpenFlags> for OpenAccess {
+    fn from(pflags: &OpenFlags) -> Self {
+        let mut access = Self::default();
+
+        if pflags.read() {
+            access.desired_access |= AceMask::READ_DATA | AceMask::READ_ATTRIBUTES;
+        }
+
+        if pflags.write() {
+            access.desired_access |= AceMask::WRITE_DATA | AceMask::WRITE_ATTRIBUTES;
+        }
+
+        if pflags.append() {
+            access.desired_access |= AceMask::APPEND_DATA;
+            access.flags |= OpenFlagsV5::APPEND_DATA;
+        }
+
+        access.flags.set_disposition(
+            match (pflags.create(), pflags.truncate(), pflags.exclude()) {
+                (true, _, true) => OpenDisposition::CreateNew,
+                (true, true, false) => OpenDisposition::CreateTruncate,
+                (true, false, false) => OpenDisposition::OpenOrCreate,
+                (false, true, _) => OpenDisposition::TruncateExisting,
+                (false, false, _) => OpenDisposition::OpenExisting,
+            },
+        );
+
+        access
+    }
+}
+
 /// Implementation for SSH_FXP_OPEN
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug)]
 pub struct Open {
     pub id: u32,
     pub filename: String,
     pub pflags: OpenFlags,
     pub attrs: FileAttributes,
+    /// Sent instead of `pflags` since version 5, derived from them when not set.
+    /// When received, `pflags` are derived from it
+    pub access: Option<OpenAccess>,
 }
 
-impl_request_id!(Open);
\ No newline at end of file
+impl_request_id!(Open);
+
+impl Serialize for Open {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut s = serializer.serialize_struct("Open", 5)?;
+        s.serialize_field("id", &self.id)?;
+        s.serialize_field("filename", &self.filename)?;
+
+        if current_version() >= 5 {
+            let access = self
+                .access
+                .unwrap_or_else(|| OpenAccess::from(&self.pflags));
+            s.serialize_field("desired_access", &access.desired_access)?;
+            s.serialize_field("flags", &access.flags)?;
+        } else {
+            s.serialize_field("pflags", &self.pflags)?;
+        }
+
+        s.serialize_field("attrs", &self.attrs)?;
+        s.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Open {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct OpenVisitor;
+
+        impl<'de> Visitor<'de> for OpenVisitor {
+            type Value = Open;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("open request")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let id = next_element(&mut seq)?;
+                let filename = next_element(&mut seq)?;
+                let (pflags, access) = if current_version() >= 5 {
+                    let access = OpenAccess {
+                        desired_access: next_element(&mut seq)?,
+                        flags: next_element(&mut seq)?,
+                    };
+                    (access.pflags(), Some(access))
+                } else {
+                    (next_element(&mut seq)?, None)
+                };
+
+                Ok(Open {
+                    id,
+                    filename,
+                    pflags,
+                    attrs: next_element(&mut seq)?,
+                    access,
+                })
+            }
+        }
+
+        deserializer.deserialize_struct(
+            "Open",
+            &["id", "filename", "desired_access", "flags", "attrs"],
+            OpenVisitor,
+        )
+    }
+}
@@ -1,11 +1,88 @@
-use super::{impl_request_id, RequestId};
+use serde::{
+    de::{SeqAccess, Visitor},
+    ser::SerializeStruct,
+    Deserialize, Deserializer, Serialize,
+};
+use std::fmt;
+
+use super::{current_version, impl_request_id, next_element, RequestId};
+
+/// Flags of SSH_FXP_RENAME since version 5
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenameFlags(u32);
+
+bitflags! {
+    impl RenameFlags: u32 {
+        const OVERWRITE = 0x00000001;
+        const ATOMIC = 0x00000002;
+        const NATIVE = 0x00000004;
+    }
+}
 
 /// Implementation for SSH_FXP_RENAME
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug)]
 pub struct Rename {
     pub id: u32,
     pub oldpath: String,
     pub newpath: String,
+    /// Since version 5
+    pub flags: RenameFlags,
+}
+
+impl_request_id!(Rename);
+
+impl Serialize for Rename {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut s = serializer.serialize_struct("Rename", 4)?;
+        s.serialize_field("id", &self.id)?;
+        s.serialize_field("oldpath", &self.oldpath)?;
+        s.serialize_field("newpath", &self.newpath)?;
+
+        if current_version() >= 5 {
+            s.serialize_field("flags", &self.flags)?;
+        }
+
+        s.end()
+    }
 }
 
-impl_request_id!(Rename);
\ No newline at end of file
+impl<'de> Deserialize<'de> for Rename {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct RenameVisitor;
+
+        impl<'de> Visitor<'de> for RenameVisitor {
+            type Value = Rename;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("rename request")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                Ok(Rename {
+                    id: next_element(&mut seq)?,
+                    oldpath: next_element(&mut seq)?,
+                    newpath: next_element(&mut seq)?,
+                    flags: match current_version() {
+                        5.. => next_element(&mut seq)?,
+                        _ => RenameFlags::empty(),
+                    },
+                })
+            }
+        }
+
+        deserializer.deserialize_struct(
+            "Rename",
+            &["id", "oldpath", "newpath", "flags"],
+            RenameVisitor,
+        )
+    }
+}
@@ -1,9 +1,15 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::io;
 use thiserror::Error;
 
-use super::{impl_packet_for, impl_request_id, Packet, RequestId};
+use super::{current_version, impl_packet_for, impl_request_id, Packet, RequestId};
 
-/// Error Codes for SSH_FXP_STATUS
-#[derive(Debug, Error, Clone, Copy, Serialize, Deserialize)]
+/// Error Codes for SSH_FXP_STATUS.
+///
+/// Codes after [`StatusCode::OpUnsupported`] were added in versions 4 to 6.
+/// They are sent as the closest code known in the negotiated version,
+/// and unknown codes are received as [`StatusCode::Failure`]
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
 pub enum StatusCode {
     /// Indicates successful completion of the operation.
     #[error("Ok")]
@@ -38,6 +44,220 @@ pub enum StatusCode {
     /// or it may be returned by the server if the server does not implement an operation).
     #[error("Operation unsupported")]
     OpUnsupported = 8,
+    /// The handle value was invalid. Since version 4
+    #[error("Invalid handle")]
+    InvalidHandle = 9,
+    /// The file path does not exist or is invalid. Since version 4
+    #[error("No such path")]
+    NoSuchPath = 10,
+    /// The file already exists. Since version 4
+    #[error("File already exists")]
+    FileAlreadyExists = 11,
+    /// The file is on read-only media, or the media is write protected. Since version 4
+    #[error("Write protect")]
+    WriteProtect = 12,
+    /// There is no media available in the drive. Since version 4
+    #[error("No media")]
+    NoMedia = 13,
+    /// Insufficient free space on the filesystem. Since version 5
+    #[error("No space on filesystem")]
+    NoSpaceOnFilesystem = 14,
+    /// The quota of the user was exceeded. Since version 5
+    #[error("Quota exceeded")]
+    QuotaExceeded = 15,
+    /// A principal referenced by the request was unknown. Since version 5
+    #[error("Unknown principal")]
+    UnknownPrincipal = 16,
+    /// The file could not be opened because it is locked by another process. Since version 5
+    #[error("Lock conflict")]
+    LockConflict = 17,
+    /// The directory is not empty. Since version 6
+    #[error("Directory not empty")]
+    DirNotEmpty = 18,
+    /// The specified file is not a directory. Since version 6
+    #[error("Not a directory")]
+    NotADirectory = 19,
+    /// The filename is not valid. Since version 6
+    #[error("Invalid filename")]
+    InvalidFilename = 20,
+    /// Too many symbolic links were encountered. Since version 6
+    #[error("Link loop")]
+    LinkLoop = 21,
+    /// The file cannot be deleted. Since version 6
+    #[error("Cannot delete")]
+    CannotDelete = 22,
+    /// A parameter was out of range or otherwise invalid. Since version 6
+    #[error("Invalid parameter")]
+    InvalidParameter = 23,
+    /// The file is a directory where it cannot be. Since version 6
+    #[error("File is a directory")]
+    FileIsADirectory = 24,
+    /// A read or write conflicts with a byte range lock. Since version 6
+    #[error("Byte range lock conflict")]
+    ByteRangeLockConflict = 25,
+    /// A byte range lock could not be granted. Since version 6
+    #[error("Byte range lock refused")]
+    ByteRangeLockRefused = 26,
+    /// The file is pending deletion. Since version 6
+    #[error("Delete pending")]
+    DeletePending = 27,
+    /// The file is corrupt. Since version 6
+    #[error("File corrupt")]
+    FileCorrupt = 28,
+    /// The principal cannot be the owner of the file. Since version 6
+    #[error("Owner invalid")]
+    OwnerInvalid = 29,
+    /// The principal cannot be the group of the file. Since version 6
+    #[error("Group invalid")]
+    GroupInvalid = 30,
+    /// The range given to SSH_FXP_UNBLOCK is not locked. Since version 6
+    #[error("No matching byte range lock")]
+    NoMatchingByteRangeLock = 31,
+}
+
+impl StatusCode {
+    /// `None` if the code is not defined by any version
+    pub fn from_u32(code: u32) -> Option<Self> {
+        use StatusCode::*;
+
+        Some(match code {
+            0 => Ok,
+            1 => Eof,
+            2 => NoSuchFile,
+            3 => PermissionDenied,
+            4 => Failure,
+            5 => BadMessage,
+            6 => NoConnection,
+            7 => ConnectionLost,
+            8 => OpUnsupported,
+            9 => InvalidHandle,
+            10 => NoSuchPath,
+            11 => FileAlreadyExists,
+            12 => WriteProtect,
+            13 => NoMedia,
+            14 => NoSpaceOnFilesystem,
+            15 => QuotaExceeded,
+            16 => UnknownPrincipal,
+            17 => LockConflict,
+            18 => DirNotEmpty,
+            19 => NotADirectory,
+            20 => InvalidFilename,
+            21 => LinkLoop,
+            22 => CannotDelete,
+            23 => InvalidParameter,
+            24 => FileIsADirectory,
+            25 => ByteRangeLockConflict,
+            26 => ByteRangeLockRefused,
+            27 => DeletePending,
+            28 => FileCorrupt,
+            29 => OwnerInvalid,
+            30 => GroupInvalid,
+            31 => NoMatchingByteRangeLock,
+            _ => return None,
+        })
+    }
+
+    /// The closest code defined in the protocol `version`
+    pub fn for_version(self, version: u32) -> Self {
+        let last = match version {
+            ..=3 => Self::OpUnsupported,
+            4 => Self::NoMedia,
+            5 => Self::LockConflict,
+            _ => Self::NoMatchingByteRangeLock,
+        };
+
+        if self as u32 <= last as u32 {
+            return self;
+        }
+
+        match self {
+            Self::NoSuchPath => Self::NoSuchFile,
+            Self::WriteProtect => Self::PermissionDenied,
+            Self::ByteRangeLockConflict | Self::ByteRangeLockRefused if version >= 5 => {
+                Self::LockConflict
+            }
+            _ => Self::Failure,
+        }
+    }
+}
+
+/// Codes of errors that [`io::ErrorKind`] does not tell apart
+#[cfg(unix)]
+fn from_errno(errno: i32) -> Option<StatusCode> {
+    Some(match errno {
+        libc::EBADF => StatusCode::InvalidHandle,
+        libc::ELOOP => StatusCode::LinkLoop,
+        libc::EOPNOTSUPP | libc::ENOSYS => StatusCode::OpUnsupported,
+        #[cfg(target_os = "linux")]
+        libc::ENOMEDIUM => StatusCode::NoMedia,
+        libc::ENOLCK | libc::EDEADLK => StatusCode::LockConflict,
+        _ => return None,
+    })
+}
+
+#[cfg(not(unix))]
+fn from_errno(_errno: i32) -> Option<StatusCode> {
+    None
+}
+
+/// The code closest to an I/O error, from its OS error number
+/// when there is one and otherwise from its kind
+impl From<&io::Error> for StatusCode {
+    fn from(err: &io::Error) -> Self {
+        if let Some(status) = err.get_ref().and_then(|e| e.downcast_ref::<StatusError>()) {
+            return status.status_code;
+        }
+        if let Some(code) = err.raw_os_error().and_then(from_errno) {
+            return code;
+        }
+
+        match err.kind() {
+            io::ErrorKind::NotFound => Self::NoSuchFile,
+            io::ErrorKind::PermissionDenied => Self::PermissionDenied,
+            io::ErrorKind::AlreadyExists => Self::FileAlreadyExists,
+            io::ErrorKind::UnexpectedEof => Self::Eof,
+            io::ErrorKind::Unsupported => Self::OpUnsupported,
+            io::ErrorKind::InvalidInput => Self::InvalidParameter,
+            io::ErrorKind::InvalidData => Self::BadMessage,
+            io::ErrorKind::InvalidFilename => Self::InvalidFilename,
+            io::ErrorKind::NotADirectory => Self::NotADirectory,
+            io::ErrorKind::IsADirectory => Self::FileIsADirectory,
+            io::ErrorKind::DirectoryNotEmpty => Self::DirNotEmpty,
+            io::ErrorKind::ReadOnlyFilesystem => Self::WriteProtect,
+            io::ErrorKind::StorageFull => Self::NoSpaceOnFilesystem,
+            io::ErrorKind::QuotaExceeded => Self::QuotaExceeded,
+            io::ErrorKind::NotConnected => Self::NoConnection,
+            io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset => {
+                Self::ConnectionLost
+            }
+            _ => Self::Failure,
+        }
+    }
+}
+
+impl From<io::Error> for StatusCode {
+    fn from(err: io::Error) -> Self {
+        Self::from(&err)
+    }
+}
+
+impl Serialize for StatusCode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u32(self.for_version(current_version()) as u32)
+    }
+}
+
+impl<'de> Deserialize<'de> for StatusCode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let code = u32::deserialize(deserializer)?;
+        Ok(Self::from_u32(code).unwrap_or(Self::Failure))
+    }
 }
 
 /// Implementation for SSH_FXP_STATUS as defined in the specification draft
@@ -63,3 +283,101 @@ impl Status {
 
 impl_request_id!(Status);
 impl_packet_for!(Status);
+
+/// Failure of a request, answered with SSH_FXP_STATUS.
+///
+/// Errors of handlers are converted into it, so they can carry
+/// a message for the client besides the code. An [`io::Error`]
+/// wrapping a `StatusError` converts back into it unchanged
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("{status_code}: {message}")]
+pub struct StatusError {
+    pub status_code: StatusCode,
+    pub message: String,
+    pub language_tag: String,
+}
+
+impl StatusError {
+    pub fn new(status_code: StatusCode, message: impl ToString) -> Self {
+        Self {
+            status_code,
+            message: message.to_string(),
+            language_tag: "en-US".to_string(),
+        }
+    }
+}
+
+/// The message is the description of the code
+impl From<StatusCode> for StatusError {
+    fn from(status_code: StatusCode) -> Self {
+        Self::new(status_code, status_code)
+    }
+}
+
+/// The message is the one of the OS, such as `No such file or directory`
+impl From<io::Error> for StatusError {
+    fn from(err: io::Error) -> Self {
+        if let Some(status) = err.get_ref().and_then(|e| e.downcast_ref::<Self>()) {
+            return status.clone();
+        }
+
+        let mut message = err.to_string();
+        if let Some(errno) = err.raw_os_error() {
+            let suffix = format!(" (os error {})", errno);
+            if message.ends_with(&suffix) {
+                message.truncate(message.len() - suffix.len());
+            }
+        }
+        Self::new(StatusCode::from(&err), message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Buf;
+
+    use super::*;
+    use crate::protocol::VERSION;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_io_errors() {
+        for (errno, code) in [
+            (libc::ENOENT, StatusCode::NoSuchFile),
+            (libc::EACCES, StatusCode::PermissionDenied),
+            (libc::EEXIST, StatusCode::FileAlreadyExists),
+            (libc::ENOTEMPTY, StatusCode::DirNotEmpty),
+            (libc::ENOTDIR, StatusCode::NotADirectory),
+            (libc::EISDIR, StatusCode::FileIsADirectory),
+            (libc::EROFS, StatusCode::WriteProtect),
+            (libc::ENOSPC, StatusCode::NoSpaceOnFilesystem),
+            (libc::ELOOP, StatusCode::LinkLoop),
+            (libc::EBADF, StatusCode::InvalidHandle),
+            (libc::EIO, StatusCode::Failure),
+        ] {
+            assert_eq!(StatusCode::from(io::Error::from_raw_os_error(errno)), code);
+        }
+        assert_eq!(
+            StatusCode::from(io::Error::from(io::ErrorKind::NotFound)),
+            StatusCode::NoSuchFile
+        );
+
+        let error = StatusError::from(io::Error::from_raw_os_error(libc::ENOENT));
+        assert_eq!(error.message, "No such file or directory");
+        assert_eq!(
+            StatusError::from(io::Error::other("custom")).message,
+            "custom"
+        );
+
+        let packet = Packet::error(1, io::Error::from_raw_os_error(libc::ENOTEMPTY));
+        let mut bytes = packet.encode(VERSION).unwrap();
+        bytes.advance(4);
+        match Packet::decode(&mut bytes, VERSION).unwrap() {
+            Packet::Status(status) => {
+                assert_eq!(status.status_code, StatusCode::Failure);
+                assert_eq!(status.error_message, "Directory not empty");
+            }
+            _ => panic!("wrong packet type"),
+        }
+    }
+}
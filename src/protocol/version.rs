@@ -1,3 +1,4 @@
+use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
 
 use super::{impl_packet_for, Packet, VERSION, RequestId};
@@ -6,9 +7,27 @@ use super::{impl_packet_for, Packet, VERSION, RequestId};
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Version {
     pub version: u32,
+    #[serde(deserialize_with = "lossy_extensions")]
     pub extensions: HashMap<String, String>,
 }
 
+/// Extensions of later versions such as `supported2` carry binary data
+fn lossy_extensions<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let extensions = HashMap::<Vec<u8>, Vec<u8>>::deserialize(deserializer)?;
+    Ok(extensions
+        .into_iter()
+        .map(|(name, data)| {
+            (
+                String::from_utf8_lossy(&name).into_owned(),
+                String::from_utf8_lossy(&data).into_owned(),
+            )
+        })
+        .collect())
+}
+
 impl_packet_for!(Version);
 
 impl Version {
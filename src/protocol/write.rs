@@ -1,14 +1,16 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use std::fmt;
 
 use super::{impl_request_id, RequestId};
+use crate::{buf::TryBuf, error::Error};
 
-/// Implementation for SSH_FXP_WRITE
-#[derive(Serialize, Deserialize)]
+/// Implementation for SSH_FXP_WRITE.
+/// A received `data` is a slice of the packet, it is never copied
 pub struct Write {
     pub id: u32,
     pub handle: String,
     pub offset: u64,
-    pub data: Vec<u8>,
+    pub data: Bytes,
 }
 
 impl fmt::Debug for Write {
@@ -22,4 +24,26 @@ impl fmt::Debug for Write {
     }
 }
 
-impl_request_id!(Write);
\ No newline at end of file
+impl_request_id!(Write);
+
+impl Write {
+    pub(crate) fn decode(bytes: &mut Bytes) -> Result<Self, Error> {
+        Ok(Self {
+            id: bytes.try_get_u32()?,
+            handle: bytes.try_get_string()?,
+            offset: bytes.try_get_u64()?,
+            data: bytes.try_get_shared()?,
+        })
+    }
+
+    /// Header and payload, to be written without joining them
+    pub(crate) fn encode(self) -> (Bytes, Bytes) {
+        let mut header = BytesMut::with_capacity(20 + self.handle.len());
+        header.put_u32(self.id);
+        header.put_u32(self.handle.len() as u32);
+        header.put_slice(self.handle.as_bytes());
+        header.put_u64(self.offset);
+        header.put_u32(self.data.len() as u32);
+        (header.freeze(), self.data)
+    }
+}
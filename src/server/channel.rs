@@ -0,0 +1,267 @@
+use std::{collections::HashMap, sync::Arc};
+
+use russh::{
+    server::{Handle, Msg, Session},
+    Channel, ChannelId,
+};
+use tokio::sync::Notify;
+
+use super::{serve, serve_concurrent, Config, Dispatch, SessionHandle};
+
+/// Name of the SSH subsystem carrying SFTP
+pub const SUBSYSTEM: &str = "sftp";
+
+/// Session channels of an SSH connection, kept until the client
+/// asks for a subsystem on them.
+///
+/// Meant to live in a [`russh::server::Handler`]: channels are inserted
+/// from `channel_open_session` and taken back from `subsystem_request`
+#[derive(Debug, Default)]
+pub struct SftpChannels {
+    channels: HashMap<ChannelId, Channel<Msg>>,
+}
+
+impl SftpChannels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, channel: Channel<Msg>) {
+        self.channels.insert(channel.id(), channel);
+    }
+
+    /// Forgets a channel, for example once closed by the client
+    pub fn remove(&mut self, channel_id: ChannelId) -> Option<Channel<Msg>> {
+        self.channels.remove(&channel_id)
+    }
+
+    /// Answers a subsystem request. The channel is returned when `name`
+    /// is [`SUBSYSTEM`], other requests and unknown channels are refused
+    pub fn accept(
+        &mut self,
+        channel_id: ChannelId,
+        name: &str,
+        session: &mut Session,
+    ) -> Option<Channel<Msg>> {
+        let channel = match name {
+            SUBSYSTEM => self.channels.remove(&channel_id),
+            _ => None,
+        };
+
+        match channel {
+            Some(_) => session.channel_success(channel_id),
+            None => {
+                debug!("subsystem {} refused on channel {}", name, channel_id);
+                session.channel_failure(channel_id);
+            }
+        }
+
+        channel
+    }
+
+    /// Answers a subsystem request and serves `handler` through
+    /// [`run_channel`] when it is accepted
+    pub async fn subsystem_request<H>(
+        &mut self,
+        channel_id: ChannelId,
+        name: &str,
+        session: &mut Session,
+        handler: H,
+    ) -> Option<SessionHandle>
+    where
+        H: Dispatch + 'static,
+    {
+        let channel = self.accept(channel_id, name, session)?;
+        Some(run_channel(channel, session.handle(), handler).await)
+    }
+}
+
+/// Same as [`run`](super::run) on a russh channel. Once the session
+/// ends, EOF is sent and the channel is closed through `handle`
+pub async fn run_channel<H>(channel: Channel<Msg>, handle: Handle, handler: H) -> SessionHandle
+where
+    H: Dispatch + 'static,
+{
+    let stop = Arc::new(Notify::new());
+    let id = channel.id();
+    let session = serve(
+        channel.into_stream(),
+        handler,
+        Config::default(),
+        stop.clone(),
+    );
+
+    SessionHandle::spawn(stop, async move {
+        let result = session.await;
+        let _ = handle.close(id).await;
+        result
+    })
+}
+
+/// Same as [`run_concurrent`](super::run_concurrent) on a russh channel.
+/// Once the session ends, EOF is sent and the channel is closed through `handle`
+pub async fn run_channel_concurrent<H>(
+    channel: Channel<Msg>,
+    handle: Handle,
+    handler: H,
+    config: Config,
+) -> SessionHandle
+where
+    H: Dispatch + Clone + 'static,
+{
+    let stop = Arc::new(Notify::new());
+    let id = channel.id();
+    let session = serve_concurrent(channel.into_stream(), handler, config, stop.clone());
+
+    SessionHandle::spawn(stop, async move {
+        let result = session.await;
+        let _ = handle.close(id).await;
+        result
+    })
+}
+
+#[cfg(all(test, feature = "impls"))]
+mod test {
+    use std::time::Duration;
+
+    use russh::server::Auth;
+    use russh_keys::key::{KeyPair, PublicKey};
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        client::SftpSession,
+        protocol::{types::FileAttributes, StatusCode},
+        server::vfs::{MemoryFs, VfsHandler},
+    };
+
+    struct SshServer {
+        channels: SftpChannels,
+        sessions: mpsc::UnboundedSender<SessionHandle>,
+    }
+
+    #[async_trait]
+    impl russh::server::Handler for SshServer {
+        type Error = russh::Error;
+
+        async fn auth_none(&mut self, _: &str) -> Result<Auth, Self::Error> {
+            Ok(Auth::Accept)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            channel: Channel<Msg>,
+            _: &mut Session,
+        ) -> Result<bool, Self::Error> {
+            self.channels.insert(channel);
+            Ok(true)
+        }
+
+        async fn subsystem_request(
+            &mut self,
+            channel_id: ChannelId,
+            name: &str,
+            session: &mut Session,
+        ) -> Result<(), Self::Error> {
+            let handler = VfsHandler::new(MemoryFs::default());
+            if let Some(sftp) = self
+                .channels
+                .subsystem_request(channel_id, name, session, handler)
+                .await
+            {
+                let _ = self.sessions.send(sftp);
+            }
+            Ok(())
+        }
+    }
+
+    struct SshClient;
+
+    #[async_trait]
+    impl russh::client::Handler for SshClient {
+        type Error = russh::Error;
+
+        async fn check_server_key(&mut self, _: &PublicKey) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    /// Authenticated client connected to an in-process server, and the
+    /// sessions started by the server
+    async fn connect() -> (
+        russh::client::Handle<SshClient>,
+        mpsc::UnboundedReceiver<SessionHandle>,
+    ) {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (sessions, started) = mpsc::unbounded_channel();
+
+        let config = russh::server::Config {
+            keys: vec![KeyPair::generate_ed25519()],
+            ..Default::default()
+        };
+        let handler = SshServer {
+            channels: SftpChannels::new(),
+            sessions,
+        };
+        tokio::spawn(async move {
+            let session = russh::server::run_stream(Arc::new(config), server, handler).await;
+            let _ = session.unwrap().await;
+        });
+
+        let config = Arc::new(russh::client::Config::default());
+        let mut handle = russh::client::connect_stream(config, client, SshClient)
+            .await
+            .unwrap();
+        assert!(handle.authenticate_none("user").await.unwrap());
+        (handle, started)
+    }
+
+    async fn ended(session: SessionHandle) -> Result<(), crate::Error> {
+        tokio::time::timeout(Duration::from_secs(5), session)
+            .await
+            .expect("session still running")
+    }
+
+    #[tokio::test]
+    async fn test_subsystem() {
+        let (handle, mut started) = connect().await;
+
+        let sftp = SftpSession::open_channel(&handle, Default::default())
+            .await
+            .unwrap();
+        sftp.mkdir("/dir", FileAttributes::empty()).await.unwrap();
+        assert!(sftp.stat("/dir").await.unwrap().is_dir());
+
+        //EOF from the client ends the session on the server
+        let session = started.recv().await.unwrap();
+        drop(sftp);
+        ended(session).await.unwrap();
+
+        //and the end of the session on the server reaches the client
+        let sftp = SftpSession::open_channel(&handle, Default::default())
+            .await
+            .unwrap();
+        let session = started.recv().await.unwrap();
+        session.shutdown();
+        ended(session).await.unwrap();
+        assert!(matches!(
+            sftp.stat("/").await,
+            Err(StatusCode::ConnectionLost)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_subsystem_refused() {
+        let (handle, _started) = connect().await;
+
+        let mut channel = handle.channel_open_session().await.unwrap();
+        channel.request_subsystem(true, "other").await.unwrap();
+        loop {
+            match channel.wait().await {
+                Some(russh::ChannelMsg::Failure) => break,
+                Some(russh::ChannelMsg::Success) | None => panic!("subsystem accepted"),
+                Some(_) => continue,
+            }
+        }
+    }
+}
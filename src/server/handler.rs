@@ -1,30 +1,37 @@
-
-use crate::protocol::{
-    types::*,
-    Status, StatusCode, VERSION,
-};
+use crate::protocol::{types::*, Status, StatusError, VERSION, VERSION_MAX};
 
 /// Server handler for each client. This is `async_trait`
 #[async_trait]
 pub trait Handler: Sized {
-    /// The type must have an Into<StatusCode>
+    /// The type must have an Into<StatusError>
     /// implementation because a response must be sent
     /// to any request, even if completed by error.
-    type Error: Into<StatusCode>;
+    /// [`StatusCode`](crate::protocol::StatusCode) and [`std::io::Error`] have one,
+    /// use [`StatusError`] to choose the message
+    type Error: Into<StatusError>;
 
     /// Called by the handler when the packet is not implemented
     fn unimplemented(&self) -> Self::Error;
 
-    /// The default is to send an SSH_FXP_VERSION response with
-    /// the protocol version and ignore any extensions.
+    /// The default is to send an SSH_FXP_VERSION response with the lowest
+    /// of the client version and [`VERSION_MAX`] and ignore any extensions.
+    /// The session then uses the lowest of both versions.
     #[allow(unused_variables)]
     async fn init(&mut self, arg: Init) -> Result<Version, Self::Error> {
-        if arg.version != VERSION {
-            panic!("version mismatch: {} != {}", arg.version, VERSION);
+        if arg.version < VERSION {
+            return Err(self.unimplemented());
         }
-        Ok(Version::default())
+        Ok(Version {
+            version: arg.version.min(VERSION_MAX),
+            ..Default::default()
+        })
     }
 
+    /// Called once when the session ends for any reason, after every
+    /// request was answered. Lets the handler flush and release what
+    /// it holds, such as files left open
+    async fn on_close(&mut self) {}
+
     /// Called on SSH_FXP_OPEN
     #[allow(unused_variables)]
     async fn open(&mut self, arg: Open) -> Result<Handle, Self::Error> {
@@ -140,6 +147,27 @@ pub trait Handler: Sized {
         Err(self.unimplemented())
     }
 
+    /// Called on SSH_FXP_LINK, which exists since version 6.
+    /// The status can be returned as Ok or as Err
+    #[allow(unused_variables)]
+    async fn link(&mut self, arg: Link) -> Result<Status, Self::Error> {
+        Err(self.unimplemented())
+    }
+
+    /// Called on SSH_FXP_BLOCK, which exists since version 6.
+    /// The status can be returned as Ok or as Err
+    #[allow(unused_variables)]
+    async fn block(&mut self, arg: Block) -> Result<Status, Self::Error> {
+        Err(self.unimplemented())
+    }
+
+    /// Called on SSH_FXP_UNBLOCK, which exists since version 6.
+    /// The status can be returned as Ok or as Err
+    #[allow(unused_variables)]
+    async fn unblock(&mut self, arg: Unblock) -> Result<Status, Self::Error> {
+        Err(self.unimplemented())
+    }
+
     /// Called on SSH_FXP_EXTENDED.
     /// If the server does not recognize the `request' name
     /// the server must respond with an SSH_FX_OP_UNSUPPORTED error
@@ -147,5 +175,97 @@ pub trait Handler: Sized {
     async fn extended(&mut self, arg: Extended) -> Result<ExtendedReply, Self::Error> {
         Err(self.unimplemented())
     }
-}
 
+    /// Names of the typed extensions implemented by the handler, such as
+    /// [`PosixRename::NAME`](Extension::NAME). They are added to the
+    /// extensions of SSH_FXP_VERSION after calling [`Handler::init`]
+    fn extensions(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Called on SSH_FXP_EXTENDED with `posix-rename@openssh.com`.
+    /// The status can be returned as Ok or as Err
+    #[allow(unused_variables)]
+    async fn posix_rename(&mut self, arg: PosixRename) -> Result<Status, Self::Error> {
+        Err(self.unimplemented())
+    }
+
+    /// Called on SSH_FXP_EXTENDED with `statvfs@openssh.com`
+    #[allow(unused_variables)]
+    async fn statvfs(&mut self, arg: Statvfs) -> Result<StatvfsReply, Self::Error> {
+        Err(self.unimplemented())
+    }
+
+    /// Called on SSH_FXP_EXTENDED with `fstatvfs@openssh.com`
+    #[allow(unused_variables)]
+    async fn fstatvfs(&mut self, arg: Fstatvfs) -> Result<StatvfsReply, Self::Error> {
+        Err(self.unimplemented())
+    }
+
+    /// Called on SSH_FXP_EXTENDED with `hardlink@openssh.com`.
+    /// The status can be returned as Ok or as Err
+    #[allow(unused_variables)]
+    async fn hardlink(&mut self, arg: Hardlink) -> Result<Status, Self::Error> {
+        Err(self.unimplemented())
+    }
+
+    /// Called on SSH_FXP_EXTENDED with `fsync@openssh.com`.
+    /// The status can be returned as Ok or as Err
+    #[allow(unused_variables)]
+    async fn fsync(&mut self, arg: Fsync) -> Result<Status, Self::Error> {
+        Err(self.unimplemented())
+    }
+
+    /// Called on SSH_FXP_EXTENDED with `lsetstat@openssh.com`.
+    /// The status can be returned as Ok or as Err
+    #[allow(unused_variables)]
+    async fn lsetstat(&mut self, arg: LSetStat) -> Result<Status, Self::Error> {
+        Err(self.unimplemented())
+    }
+
+    /// Called on SSH_FXP_EXTENDED with `limits@openssh.com`
+    #[allow(unused_variables)]
+    async fn limits(&mut self, arg: Limits) -> Result<LimitsReply, Self::Error> {
+        Err(self.unimplemented())
+    }
+
+    /// Called on SSH_FXP_EXTENDED with `expand-path@openssh.com`
+    #[allow(unused_variables)]
+    async fn expand_path(&mut self, arg: ExpandPath) -> Result<Name, Self::Error> {
+        Err(self.unimplemented())
+    }
+
+    /// Called on SSH_FXP_EXTENDED with `copy-data`.
+    /// The status can be returned as Ok or as Err
+    #[allow(unused_variables)]
+    async fn copy_data(&mut self, arg: CopyData) -> Result<Status, Self::Error> {
+        Err(self.unimplemented())
+    }
+
+    /// Called on SSH_FXP_EXTENDED with `check-file-name`
+    #[allow(unused_variables)]
+    async fn check_file_name(&mut self, arg: CheckFileName) -> Result<CheckFileReply, Self::Error> {
+        Err(self.unimplemented())
+    }
+
+    /// Called on SSH_FXP_EXTENDED with `check-file-handle`
+    #[allow(unused_variables)]
+    async fn check_file_handle(
+        &mut self,
+        arg: CheckFileHandle,
+    ) -> Result<CheckFileReply, Self::Error> {
+        Err(self.unimplemented())
+    }
+
+    /// Called on SSH_FXP_EXTENDED with `md5-hash`
+    #[allow(unused_variables)]
+    async fn md5_hash(&mut self, arg: Md5Hash) -> Result<Md5HashReply, Self::Error> {
+        Err(self.unimplemented())
+    }
+
+    /// Called on SSH_FXP_EXTENDED with `md5-hash-handle`
+    #[allow(unused_variables)]
+    async fn md5_hash_handle(&mut self, arg: Md5HashHandle) -> Result<Md5HashReply, Self::Error> {
+        Err(self.unimplemented())
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+};
+
+use crate::protocol::{StatusCode, StatusError};
+
+/// Default limit of open handles per session, the one of OpenSSH
+pub const MAX_HANDLES: usize = 512;
+
+/// Open handles of a session.
+///
+/// Handles are short opaque strings counted up from a random start,
+/// so they tell nothing about what is behind them and are never reused
+/// within a session. Once the limit is reached new handles are refused
+#[derive(Debug)]
+pub struct HandleTable<T> {
+    entries: HashMap<String, T>,
+    next: u64,
+    limit: usize,
+}
+
+impl<T> HandleTable<T> {
+    /// Table accepting up to `limit` open handles
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            next: RandomState::new().build_hasher().finish(),
+            limit,
+        }
+    }
+
+    /// Maximum number of open handles
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Fails once the limit is reached, to refuse a request before doing its work
+    pub fn check_limit(&self) -> Result<(), StatusError> {
+        match self.entries.len() >= self.limit {
+            true => Err(StatusError::new(
+                StatusCode::Failure,
+                "too many open handles",
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Stores `entry` under a new handle, failing once the limit is reached
+    pub fn insert(&mut self, entry: T) -> Result<String, StatusError> {
+        self.check_limit()?;
+
+        let handle = format!("{:x}", self.next);
+        self.next = self.next.wrapping_add(1);
+        self.entries.insert(handle.clone(), entry);
+        Ok(handle)
+    }
+
+    pub fn get(&self, handle: &str) -> Option<&T> {
+        self.entries.get(handle)
+    }
+
+    pub fn get_mut(&mut self, handle: &str) -> Option<&mut T> {
+        self.entries.get_mut(handle)
+    }
+
+    pub fn remove(&mut self, handle: &str) -> Option<T> {
+        self.entries.remove(handle)
+    }
+
+    /// Number of open handles
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes every handle, for example to close them all at the end of a session
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.entries.drain().map(|(_, entry)| entry)
+    }
+}
+
+impl<T> Default for HandleTable<T> {
+    fn default() -> Self {
+        Self::with_limit(MAX_HANDLES)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_handle_table() {
+        let mut table = HandleTable::with_limit(2);
+        let first = table.insert(1).unwrap();
+        let second = table.insert(2).unwrap();
+        assert_ne!(first, second);
+        assert!(first.len() <= 16);
+
+        assert!(table.check_limit().is_err());
+        let err = table.insert(3).unwrap_err();
+        assert_eq!(err.status_code, StatusCode::Failure);
+
+        assert_eq!(table.remove(&first), Some(1));
+        assert_eq!(table.get(&first), None);
+        let third = table.insert(3).unwrap();
+        assert_ne!(third, first);
+        assert_eq!(table.get(&third), Some(&3));
+
+        let mut left = table.drain().collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(left, [2, 3]);
+        assert!(table.is_empty());
+    }
+}
@@ -0,0 +1,370 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::Level;
+
+use super::{exec_request, Handler};
+use crate::protocol::{Packet, StatusCode};
+
+/// What [`run`](super::run) and [`run_concurrent`](super::run_concurrent)
+/// serve: any [`Handler`], and handlers wrapped in layers with
+/// [`Dispatch::layer`]. This is `async_trait`
+#[async_trait]
+pub trait Dispatch: Send {
+    /// Executes a request and returns the response,
+    /// which is an SSH_FXP_STATUS on errors
+    async fn dispatch(&mut self, request: Packet) -> Packet;
+
+    /// Called once when the session ends, see [`Handler::on_close`]
+    async fn on_close(&mut self);
+
+    /// Wraps the handler in `layer`, which then sees every request
+    /// before it. The last layer added is the first to see a request
+    fn layer<L: Layer>(self, layer: L) -> Layered<L, Self>
+    where
+        Self: Sized,
+    {
+        Layered {
+            layer: Arc::new(layer),
+            inner: self,
+        }
+    }
+}
+
+#[async_trait]
+impl<H: Handler + Send> Dispatch for H {
+    async fn dispatch(&mut self, request: Packet) -> Packet {
+        exec_request(request, self).await
+    }
+
+    async fn on_close(&mut self) {
+        Handler::on_close(self).await
+    }
+}
+
+/// Middleware seeing every request of a session and its response,
+/// such as logging, metrics, access checks or rate limits. This is `async_trait`
+///
+/// Requests are the decoded packets, SSH_FXP_INIT and SSH_FXP_EXTENDED
+/// included, and failures of the handler come back as SSH_FXP_STATUS.
+/// A layer may change both, or answer without calling the handler at all
+#[async_trait]
+pub trait Layer: Send + Sync + 'static {
+    /// Handles `request`, usually by passing it on with [`Next::run`]
+    async fn call(&self, request: Packet, next: Next<'_>) -> Packet;
+}
+
+/// Lets a layer be shared, for example to read [`Timing`] while it is used
+#[async_trait]
+impl<L: Layer> Layer for Arc<L> {
+    async fn call(&self, request: Packet, next: Next<'_>) -> Packet {
+        self.as_ref().call(request, next).await
+    }
+}
+
+/// Layers and handler below a [`Layer`]
+pub struct Next<'a> {
+    inner: &'a mut dyn Dispatch,
+}
+
+impl Next<'_> {
+    /// Passes `request` on to the next layer, or to the handler
+    pub async fn run(self, request: Packet) -> Packet {
+        self.inner.dispatch(request).await
+    }
+}
+
+/// Handler wrapped in a [`Layer`], see [`Dispatch::layer`].
+/// Clones share the layer, so it can be used with
+/// [`run_concurrent`](super::run_concurrent)
+pub struct Layered<L, H> {
+    layer: Arc<L>,
+    inner: H,
+}
+
+impl<L, H: Clone> Clone for Layered<L, H> {
+    fn clone(&self) -> Self {
+        Self {
+            layer: self.layer.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<L, H> Layered<L, H> {
+    /// Wrapped handler
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<L: Layer, H: Dispatch> Dispatch for Layered<L, H> {
+    async fn dispatch(&mut self, request: Packet) -> Packet {
+        let next = Next {
+            inner: &mut self.inner,
+        };
+        self.layer.call(request, next).await
+    }
+
+    async fn on_close(&mut self) {
+        self.inner.on_close().await
+    }
+}
+
+/// Type of a request, with the name of the extension for SSH_FXP_EXTENDED
+fn request_name(request: &Packet) -> Cow<'static, str> {
+    match request {
+        Packet::Extended(extended) => Cow::Owned(extended.request.clone()),
+        request => Cow::Borrowed(request.name()),
+    }
+}
+
+/// Status code of a response, `None` for anything but SSH_FXP_STATUS
+fn status_code(response: &Packet) -> Option<StatusCode> {
+    match response {
+        Packet::Status(status) => Some(status.status_code),
+        _ => None,
+    }
+}
+
+/// Logs every request with its outcome and duration,
+/// at [`Level::Debug`] unless set with [`Logging::with_level`]
+#[derive(Debug, Clone)]
+pub struct Logging {
+    level: Level,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Self {
+            level: Level::Debug,
+        }
+    }
+}
+
+impl Logging {
+    /// Logs at [`Level::Debug`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Logs at `level` instead
+    pub fn with_level(self, level: Level) -> Self {
+        Self { level }
+    }
+}
+
+#[async_trait]
+impl Layer for Logging {
+    async fn call(&self, request: Packet, next: Next<'_>) -> Packet {
+        if !log_enabled!(self.level) {
+            return next.run(request).await;
+        }
+
+        let id = request.get_request_id();
+        let name = request_name(&request);
+        let start = Instant::now();
+        let response = next.run(request).await;
+
+        let elapsed = start.elapsed();
+        match status_code(&response) {
+            Some(code) => log!(self.level, "{} {}: {:?} in {:?}", name, id, code, elapsed),
+            None => log!(
+                self.level,
+                "{} {}: {} in {:?}",
+                name,
+                id,
+                response.name(),
+                elapsed
+            ),
+        }
+        response
+    }
+}
+
+/// Durations of one type of request, see [`Timing`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestTimes {
+    /// Requests answered
+    pub count: u64,
+    /// Requests answered with an error status. SSH_FX_EOF, which ends
+    /// every read of a file and listing of a directory, is not one
+    pub errors: u64,
+    /// Time taken by all the requests together
+    pub total: Duration,
+    /// Time taken by the slowest request
+    pub max: Duration,
+}
+
+/// Measures the time taken by the layers below, by type of request.
+/// Wrap it in an [`Arc`] to read [`Timing::times`] while it is used
+#[derive(Debug, Default)]
+pub struct Timing {
+    times: Mutex<BTreeMap<String, RequestTimes>>,
+}
+
+impl Timing {
+    /// Starts with no requests measured
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Durations so far, by request type such as `SSH_FXP_READ`,
+    /// or by extension name such as `posix-rename@openssh.com`
+    pub fn times(&self) -> BTreeMap<String, RequestTimes> {
+        self.times.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Layer for Timing {
+    async fn call(&self, request: Packet, next: Next<'_>) -> Packet {
+        let name = request_name(&request);
+        let start = Instant::now();
+        let response = next.run(request).await;
+        let elapsed = start.elapsed();
+
+        let mut times = self.times.lock().unwrap();
+        let times = times.entry(name.into_owned()).or_default();
+        times.count += 1;
+        let code = status_code(&response);
+        if !matches!(code, None | Some(StatusCode::Ok | StatusCode::Eof)) {
+            times.errors += 1;
+        }
+        times.total += elapsed;
+        times.max = times.max.max(elapsed);
+        response
+    }
+}
+
+#[cfg(all(test, feature = "impls"))]
+mod test {
+    use tokio::io;
+
+    use super::*;
+    use crate::{
+        client::SftpSession,
+        protocol::{
+            types::{Extension, FileAttributes, Handle, OpenFlags, PosixRename},
+            Status,
+        },
+        server::{
+            self,
+            vfs::{MemoryFs, VfsHandler},
+        },
+    };
+
+    /// Refuses removals without calling the handler
+    struct NoRemove;
+
+    #[async_trait]
+    impl Layer for NoRemove {
+        async fn call(&self, request: Packet, next: Next<'_>) -> Packet {
+            match request {
+                Packet::Remove(remove) => Packet::error(remove.id, StatusCode::PermissionDenied),
+                request => next.run(request).await,
+            }
+        }
+    }
+
+    /// Records the order in which layers see requests and responses
+    struct Trace(&'static str, Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl Layer for Trace {
+        async fn call(&self, request: Packet, next: Next<'_>) -> Packet {
+            self.1
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", self.0, request.name()));
+            let response = next.run(request).await;
+            self.1
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", self.0, response.name()));
+            response
+        }
+    }
+
+    #[tokio::test]
+    async fn test_layers() {
+        let timing = Arc::new(Timing::new());
+        let handler = VfsHandler::new(MemoryFs::default())
+            .layer(NoRemove)
+            .layer(timing.clone())
+            .layer(Logging::new());
+
+        let (client, server) = io::duplex(4096);
+        let session = server::run_concurrent(server, handler, Default::default()).await;
+        let sftp = SftpSession::new(client).await.unwrap();
+
+        //extensions of the handler are still advertised through the layers
+        assert!(sftp.extensions().contains_key(PosixRename::NAME));
+
+        sftp.mkdir("/dir", FileAttributes::empty()).await.unwrap();
+        sftp.posix_rename("/dir", "/moved").await.unwrap();
+        let denied = sftp.remove("/moved").await;
+        assert_eq!(denied, Err(StatusCode::PermissionDenied));
+        let missing = sftp.stat("/dir").await;
+        assert_eq!(missing.err(), Some(StatusCode::NoSuchFile));
+        assert!(sftp.stat("/moved").await.unwrap().is_dir());
+
+        let flags = OpenFlags::READ | OpenFlags::WRITE | OpenFlags::CREATE;
+        let handle = sftp
+            .open("/file", flags, FileAttributes::empty())
+            .await
+            .unwrap();
+        let read = sftp.read(handle.clone(), 0, 64).await;
+        assert_eq!(read, Err(StatusCode::Eof));
+        sftp.close(handle).await.unwrap();
+
+        drop(sftp);
+        session.await.unwrap();
+
+        let times = timing.times();
+        let count = |name: &str| times.get(name).map(|times| (times.count, times.errors));
+        assert_eq!(count("SSH_FXP_INIT"), Some((1, 0)));
+        assert_eq!(count("SSH_FXP_MKDIR"), Some((1, 0)));
+        assert_eq!(count(PosixRename::NAME), Some((1, 0)));
+        assert_eq!(count("SSH_FXP_REMOVE"), Some((1, 1)));
+        assert_eq!(count("SSH_FXP_STAT"), Some((2, 1)));
+        assert_eq!(count("SSH_FXP_READ"), Some((1, 0)));
+        assert!(times.values().all(|times| times.max <= times.total));
+    }
+
+    #[tokio::test]
+    async fn test_order() {
+        let trace = Arc::new(Mutex::new(Vec::new()));
+        let mut handler = VfsHandler::new(MemoryFs::default())
+            .layer(Trace("inner", trace.clone()))
+            .layer(Trace("outer", trace.clone()));
+
+        let close = Packet::Close(Handle {
+            id: 1,
+            handle: "missing".to_string(),
+        });
+        match handler.dispatch(close).await {
+            Packet::Status(Status {
+                id: 1, status_code, ..
+            }) => {
+                assert_eq!(status_code, StatusCode::InvalidHandle)
+            }
+            response => panic!("unexpected {:?}", response),
+        }
+
+        assert_eq!(
+            *trace.lock().unwrap(),
+            [
+                "outer SSH_FXP_CLOSE",
+                "inner SSH_FXP_CLOSE",
+                "inner SSH_FXP_STATUS",
+                "outer SSH_FXP_STATUS",
+            ]
+        );
+    }
+}
@@ -1,28 +1,63 @@
+mod channel;
 mod handler;
+mod handles;
+mod layer;
+pub mod vfs;
 
 #[cfg(feature = "impls")]
 pub mod implementation;
 
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
 use bytes::Bytes;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite, AsyncWriteExt},
+    sync::{
+        mpsc,
+        oneshot::{self, error::TryRecvError},
+        Notify, Semaphore,
+    },
+    task::JoinHandle,
+};
 
 use crate::{
     error::Error,
-    protocol::{Packet, RequestId, StatusCode}, handler_call,
+    framing::{read_packet, write_frame, Frame},
+    handler_call,
+    protocol::{types::*, Packet, RequestId, StatusCode, MAX_PACKET_LEN, VERSION, VERSION_MAX},
 };
 
-pub use self::handler::Handler;
-
-async fn read_buf<S>(stream: &mut S) -> Result<Bytes, Error>
-where
-    S: AsyncRead + AsyncWrite + Unpin,
-{
-    let length = stream.read_u32().await?;
+pub use self::{
+    channel::{run_channel, run_channel_concurrent, SftpChannels, SUBSYSTEM},
+    handler::Handler,
+    handles::{HandleTable, MAX_HANDLES},
+    layer::{Dispatch, Layer, Layered, Logging, Next, RequestTimes, Timing},
+};
 
-    let mut buf = vec![0; length as usize];
-    stream.read_exact(&mut buf).await?;
+/// Settings of [`run_concurrent`]
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Maximum number of requests processed at the same time.
+    /// No more packets are read from the stream once reached. Default: 64
+    pub max_concurrent_requests: usize,
+    /// Longest packet accepted from the client. The connection
+    /// is closed on a longer one. Default: [`MAX_PACKET_LEN`]
+    pub max_packet_len: u32,
+}
 
-    Ok(Bytes::from(buf))
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_concurrent_requests: 64,
+            max_packet_len: MAX_PACKET_LEN,
+        }
+    }
 }
 
 async fn exec_request<H>(packet: Packet, processor: &mut H) -> Packet
@@ -30,7 +65,7 @@ where
     H: Handler + Send,
 {
     match packet {
-        Packet::Init(init) => handler_call!(processor, init),
+        Packet::Init(init) => exec_init(init, processor).await,
         Packet::Open(open) => handler_call!(processor, open),
         Packet::Close(close) => handler_call!(processor, close),
         Packet::Read(read) => handler_call!(processor, read),
@@ -49,47 +84,605 @@ where
         Packet::Rename(rename) => handler_call!(processor, rename),
         Packet::ReadLink(readlink) => handler_call!(processor, readlink),
         Packet::Symlink(symlink) => handler_call!(processor, symlink),
-        Packet::Extended(extended) => handler_call!(processor, extended),
+        Packet::Link(link) => handler_call!(processor, link),
+        Packet::Block(block) => handler_call!(processor, block),
+        Packet::Unblock(unblock) => handler_call!(processor, unblock),
+        Packet::Extended(extended) => exec_extended(extended, processor).await,
         _ => Packet::error(0, StatusCode::BadMessage),
     }
 }
 
-async fn packet_processor<H, S>(stream: &mut S, handler: &mut H) -> Result<(), Error>
+async fn exec_extended<H>(extended: Extended, processor: &mut H) -> Packet
 where
     H: Handler + Send,
-    S: AsyncRead + AsyncWrite + Unpin,
 {
-    let mut bytes = read_buf(stream).await?;
+    let id = extended.id;
+    macro_rules! extension_call {
+        ($method:ident) => {
+            match extended.parse() {
+                Ok(arg) => match processor.$method(arg).await {
+                    Err(err) => Packet::error(id, err),
+                    Ok(packet) => packet.into(),
+                },
+                Err(_) => Packet::error(id, StatusCode::BadMessage),
+            }
+        };
+    }
+
+    match extended.request.as_str() {
+        PosixRename::NAME => extension_call!(posix_rename),
+        Statvfs::NAME => extension_call!(statvfs),
+        Fstatvfs::NAME => extension_call!(fstatvfs),
+        Hardlink::NAME => extension_call!(hardlink),
+        Fsync::NAME => extension_call!(fsync),
+        LSetStat::NAME => extension_call!(lsetstat),
+        Limits::NAME => extension_call!(limits),
+        ExpandPath::NAME => extension_call!(expand_path),
+        CopyData::NAME => extension_call!(copy_data),
+        CheckFileName::NAME => extension_call!(check_file_name),
+        CheckFileHandle::NAME => extension_call!(check_file_handle),
+        Md5Hash::NAME => extension_call!(md5_hash),
+        Md5HashHandle::NAME => extension_call!(md5_hash_handle),
+        _ => handler_call!(processor, extended),
+    }
+}
+
+/// Executes SSH_FXP_INIT, advertising the typed extensions of the handler
+async fn exec_init<H>(init: Init, handler: &mut H) -> Packet
+where
+    H: Handler + Send,
+{
+    let mut response = handler_call!(handler, init);
+    if let Packet::Version(version) = &mut response {
+        for name in handler.extensions() {
+            version
+                .extensions
+                .entry(name.to_string())
+                .or_insert_with(|| "1".to_string());
+        }
+    }
 
-    let response = match Packet::try_from(&mut bytes) {
-        Ok(request) => exec_request(request, handler).await,
+    response
+}
+
+/// Executes SSH_FXP_INIT, also returning the version used
+/// for the rest of the session, if any
+async fn dispatch_init<H>(init: Init, handler: &mut H) -> (Packet, Option<u32>)
+where
+    H: Dispatch,
+{
+    let client_version = init.version;
+    let response = handler.dispatch(Packet::Init(init)).await;
+    match &response {
+        Packet::Version(version) => {
+            let negotiated = version.version.min(client_version);
+            (response, Some(negotiated.clamp(VERSION, VERSION_MAX)))
+        }
+        _ => (response, None),
+    }
+}
+
+/// Decodes and executes a request, returning the response
+async fn process<H>(mut bytes: Bytes, handler: &mut H, version: &mut u32) -> Packet
+where
+    H: Dispatch,
+{
+    match Packet::decode(&mut bytes, *version) {
+        Ok(Packet::Init(init)) => {
+            let (response, negotiated) = dispatch_init(init, handler).await;
+            *version = negotiated.unwrap_or(*version);
+            response
+        }
+        Ok(request) => handler.dispatch(request).await,
         Err(e) => {
             warn!("error: {:?}", e);
             Packet::error(0, StatusCode::BadMessage)
         }
+    }
+}
+
+/// Running session of [`run`] or [`run_concurrent`].
+///
+/// Await it for the end of the session: `Ok` once the client closed the
+/// stream or after [`SessionHandle::shutdown`], an error when the stream
+/// failed. Dropping it leaves the session running
+#[derive(Debug)]
+pub struct SessionHandle {
+    task: JoinHandle<Result<(), Error>>,
+    stop: Arc<Notify>,
+}
+
+impl SessionHandle {
+    fn spawn<F>(stop: Arc<Notify>, session: F) -> Self
+    where
+        F: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        Self {
+            task: tokio::spawn(session),
+            stop,
+        }
+    }
+
+    /// Stops reading requests. The session ends once the requests
+    /// in progress are answered and [`Handler::on_close`] returned
+    pub fn shutdown(&self) {
+        self.stop.notify_one();
+    }
+
+    /// Ends the session at once, without answering the requests in
+    /// progress nor calling [`Handler::on_close`]
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+
+    /// Whether the session ended
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+impl Future for SessionHandle {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.task)
+            .poll(cx)
+            .map(|result| match result {
+                Ok(result) => result,
+                Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+                Err(_) => Err(Error::IO("session aborted".to_string())),
+            })
+    }
+}
+
+/// Run processing stream as SFTP.
+///
+/// The session ends when the client closes the stream, on a failure of
+/// the stream or a packet longer than [`MAX_PACKET_LEN`], and on
+/// [`SessionHandle::shutdown`]. [`Handler::on_close`] is called then
+pub async fn run<S, H>(stream: S, handler: H) -> SessionHandle
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    H: Dispatch + 'static,
+{
+    run_with_config(stream, handler, Config::default()).await
+}
+
+/// Same as [`run`] with custom settings. Requests are executed one after
+/// the other, so [`Config::max_concurrent_requests`] is not used
+pub async fn run_with_config<S, H>(stream: S, handler: H, config: Config) -> SessionHandle
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    H: Dispatch + 'static,
+{
+    let stop = Arc::new(Notify::new());
+    SessionHandle::spawn(stop.clone(), serve(stream, handler, config, stop))
+}
+
+/// Session of [`run`], stopped once `stopped` is notified
+async fn serve<S, H>(
+    mut stream: S,
+    mut handler: H,
+    config: Config,
+    stopped: Arc<Notify>,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    H: Dispatch + 'static,
+{
+    let mut version = VERSION;
+    let result = loop {
+        let bytes = tokio::select! {
+            biased;
+            _ = stopped.notified() => break Ok(()),
+            bytes = read_packet(&mut stream, config.max_packet_len) => bytes,
+        };
+        let bytes = match bytes {
+            Ok(bytes) => bytes,
+            Err(Error::UnexpectedEof) => break Ok(()),
+            Err(err) => break Err(err),
+        };
+
+        let response = process(bytes, &mut handler, &mut version).await;
+        match response.encode_frame(version) {
+            Ok(frame) => {
+                if let Err(err) = write_frame(&mut stream, frame).await {
+                    break Err(err);
+                }
+            }
+            Err(err) => warn!("{}", err),
+        }
+    };
+
+    if let Err(err) = &result {
+        warn!("{}", err);
+    }
+    handler.on_close().await;
+    //lets the client see the end of the session
+    let _ = stream.shutdown().await;
+    debug!("sftp stream ended");
+    result
+}
+
+/// Handle of a request, if the request is bound to one
+fn request_handle(packet: &Packet) -> Option<String> {
+    let handle = match packet {
+        Packet::Close(close) => &close.handle,
+        Packet::Read(read) => &read.handle,
+        Packet::Write(write) => &write.handle,
+        Packet::FStat(fstat) => &fstat.handle,
+        Packet::FSetStat(fsetstat) => &fsetstat.handle,
+        Packet::ReadDir(readdir) => &readdir.handle,
+        Packet::Block(block) => &block.handle,
+        Packet::Unblock(unblock) => &unblock.handle,
+        Packet::Extended(extended) => return extended_handle(extended),
+        _ => return None,
     };
 
-    let packet = Bytes::try_from(response)?;
-    stream.write_all(&packet).await?;
+    Some(handle.clone())
+}
+
+/// Handle of a typed extension bound to one. For copy-data
+/// this is the written file, which must see the previous writes
+fn extended_handle(extended: &Extended) -> Option<String> {
+    match extended.request.as_str() {
+        Fsync::NAME => extended.parse::<Fsync>().ok().map(|r| r.handle),
+        Fstatvfs::NAME => extended.parse::<Fstatvfs>().ok().map(|r| r.handle),
+        CopyData::NAME => extended.parse::<CopyData>().ok().map(|r| r.write_to_handle),
+        CheckFileHandle::NAME => extended.parse::<CheckFileHandle>().ok().map(|r| r.handle),
+        Md5HashHandle::NAME => extended.parse::<Md5HashHandle>().ok().map(|r| r.handle),
+        _ => None,
+    }
+}
+
+/// Encodes a response for the writer task of [`run_concurrent`]
+fn send(tx: &mpsc::UnboundedSender<Frame>, response: Packet, version: u32) {
+    match response.encode_frame(version) {
+        Ok(packet) => {
+            let _ = tx.send(packet);
+        }
+        Err(err) => warn!("{}", err),
+    }
+}
 
-    Ok(())
+/// Same as [`run`] over the standard input and output of the process,
+/// the way OpenSSH starts the `sftp` subsystem. The session ends on EOF
+/// of the input, nothing else may be written to the standard output
+pub async fn run_stdio<H>(handler: H) -> SessionHandle
+where
+    H: Dispatch + 'static,
+{
+    run(io::join(io::stdin(), io::stdout()), handler).await
 }
 
-/// Run processing stream as SFTP
-pub async fn run<S, H>(mut stream: S, mut handler: H)
+/// Run processing stream as SFTP, executing up to
+/// [`Config::max_concurrent_requests`] requests at the same time.
+///
+/// Every request is executed on its own clone of the handler, so any state
+/// shared between requests (open handles for example) must live behind
+/// an `Arc`. SSH_FXP_INIT is executed on the original handler before
+/// anything else. Requests bound to the same handle are executed in
+/// the order they were received, all others may complete in any order
+/// and their responses are sent as soon as they are ready.
+///
+/// The session ends like the one of [`run`], once the requests in progress
+/// are answered. [`Handler::on_close`] is called on the original handler
+pub async fn run_concurrent<S, H>(stream: S, handler: H, config: Config) -> SessionHandle
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
-    H: Handler + Send + 'static,
+    H: Dispatch + Clone + 'static,
 {
-    tokio::spawn(async move {
-        loop {
-            match packet_processor(&mut stream, &mut handler).await {
-                Err(Error::UnexpectedEof) => break,
-                Err(err) => warn!("{}", err),
-                Ok(_) => (),
+    let stop = Arc::new(Notify::new());
+    SessionHandle::spawn(
+        stop.clone(),
+        serve_concurrent(stream, handler, config, stop),
+    )
+}
+
+/// Session of [`run_concurrent`], stopped once `stop` is notified
+async fn serve_concurrent<S, H>(
+    stream: S,
+    mut handler: H,
+    config: Config,
+    stop: Arc<Notify>,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    H: Dispatch + Clone + 'static,
+{
+    let (mut reader, mut writer) = io::split(stream);
+    let (tx, mut rx) = mpsc::unbounded_channel::<Frame>();
+
+    // a failed write stops the reader too
+    let stopped = stop.clone();
+    let writes = tokio::spawn(async move {
+        while let Some(packet) = rx.recv().await {
+            if let Err(err) = write_frame(&mut writer, packet).await {
+                stopped.notify_one();
+                return Err(err);
             }
         }
 
-        debug!("sftp stream ended");
+        //lets the client see the end of the session
+        let _ = writer.shutdown().await;
+        Ok(())
     });
+
+    let max_concurrent_requests = config.max_concurrent_requests.max(1);
+    let semaphore = Arc::new(Semaphore::new(max_concurrent_requests));
+    // completion of the last request received for each handle
+    let mut queues: HashMap<String, oneshot::Receiver<()>> = HashMap::new();
+    let mut version = VERSION;
+
+    let result = loop {
+        let bytes = tokio::select! {
+            biased;
+            _ = stop.notified() => break Ok(()),
+            bytes = read_packet(&mut reader, config.max_packet_len) => bytes,
+        };
+        let mut bytes = match bytes {
+            Ok(bytes) => bytes,
+            Err(Error::UnexpectedEof) => break Ok(()),
+            Err(err) => break Err(err),
+        };
+
+        let request = match Packet::decode(&mut bytes, version) {
+            Ok(Packet::Init(init)) => {
+                let (response, negotiated) = dispatch_init(init, &mut handler).await;
+                version = negotiated.unwrap_or(version);
+                send(&tx, response, version);
+                continue;
+            }
+            Ok(request) => request,
+            Err(e) => {
+                warn!("error: {:?}", e);
+                send(&tx, Packet::error(0, StatusCode::BadMessage), version);
+                continue;
+            }
+        };
+
+        let permit = match semaphore.clone().acquire_owned().await {
+            Ok(permit) => permit,
+            Err(_) => break Ok(()),
+        };
+
+        // forget handles without unfinished requests
+        queues.retain(|_, queued| matches!(queued.try_recv(), Err(TryRecvError::Empty)));
+
+        let (done, queued) = oneshot::channel();
+        let previous = match request_handle(&request) {
+            Some(handle) => queues.insert(handle, queued),
+            None => None,
+        };
+
+        let mut processor = handler.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if let Some(previous) = previous {
+                let _ = previous.await;
+            }
+
+            let response = processor.dispatch(request).await;
+            send(&tx, response, version);
+            let _ = done.send(());
+            drop(permit);
+        });
+    };
+
+    // every request in progress holds a permit
+    let _ = semaphore.acquire_many(max_concurrent_requests as u32).await;
+    handler.on_close().await;
+
+    drop(tx);
+    let result = match (result, writes.await) {
+        (Err(err), _) | (Ok(_), Ok(Err(err))) => Err(err),
+        (Ok(_), Ok(Ok(_))) => Ok(()),
+        (Ok(_), Err(err)) => Err(Error::IO(err.to_string())),
+    };
+    if let Err(err) = &result {
+        warn!("{}", err);
+    }
+    debug!("sftp stream ended");
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Cursor,
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    };
+
+    use tokio::io::{AsyncWriteExt, ReadBuf};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SlowHandler {
+        executed: Arc<std::sync::Mutex<Vec<u32>>>,
+        closed: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl Handler for SlowHandler {
+        type Error = StatusCode;
+
+        fn unimplemented(&self) -> Self::Error {
+            StatusCode::OpUnsupported
+        }
+
+        async fn on_close(&mut self) {
+            self.closed.store(true, Ordering::Relaxed);
+        }
+
+        async fn read(&mut self, arg: Read) -> Result<Data, Self::Error> {
+            if arg.handle == "slow" {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+
+            self.executed.lock().unwrap().push(arg.id);
+            Ok(Data {
+                id: arg.id,
+                data: Bytes::new(),
+            })
+        }
+    }
+
+    async fn exchange(config: Config, handles: &[&str]) -> (Vec<u32>, Vec<u32>) {
+        let handler = SlowHandler::default();
+        let (mut client, server) = io::duplex(4096);
+        run_concurrent(server, handler.clone(), config).await;
+
+        for (id, handle) in handles.iter().enumerate() {
+            let read = read_request(id as u32 + 1, handle);
+            client.write_all(&read).await.unwrap();
+        }
+
+        let mut responses = Vec::new();
+        for _ in handles {
+            let mut bytes = read_packet(&mut client, MAX_PACKET_LEN).await.unwrap();
+            responses.push(Packet::try_from(&mut bytes).unwrap().get_request_id());
+        }
+
+        let executed = handler.executed.lock().unwrap().clone();
+        (responses, executed)
+    }
+
+    #[tokio::test]
+    async fn test_out_of_order() {
+        let (responses, _) = exchange(Config::default(), &["slow", "fast"]).await;
+        assert_eq!(responses, vec![2, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_same_handle_in_order() {
+        let (responses, executed) = exchange(Config::default(), &["slow", "slow", "fast"]).await;
+        assert_eq!(responses, vec![3, 1, 2]);
+        assert_eq!(executed, vec![3, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        let config = Config {
+            max_concurrent_requests: 1,
+            ..Default::default()
+        };
+        let (responses, _) = exchange(config, &["slow", "fast"]).await;
+        assert_eq!(responses, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_packet() {
+        let config = Config {
+            max_packet_len: 1024,
+            ..Default::default()
+        };
+        for concurrent in [true, false] {
+            let (mut client, server) = io::duplex(4096);
+            let handler = SlowHandler::default();
+            let session = match concurrent {
+                true => run_concurrent(server, handler, config.clone()).await,
+                false => run_with_config(server, handler, config.clone()).await,
+            };
+
+            client.write_all(&[0, 0, 4, 1]).await.unwrap();
+            assert!(matches!(
+                read_packet(&mut client, MAX_PACKET_LEN).await,
+                Err(Error::UnexpectedEof)
+            ));
+            assert!(matches!(session.await, Err(Error::PacketTooLarge(_))));
+        }
+    }
+
+    fn read_request(id: u32, handle: &str) -> Bytes {
+        let read = Packet::Read(Read {
+            id,
+            handle: handle.to_string(),
+            offset: 0,
+            len: 0,
+        });
+        Bytes::try_from(read).unwrap()
+    }
+
+    /// Stream holding one request, failing every write
+    struct BrokenPipe(Cursor<Bytes>);
+
+    impl AsyncRead for BrokenPipe {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for BrokenPipe {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            _: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        for concurrent in [false, true] {
+            let handler = SlowHandler::default();
+            let (mut client, server) = io::duplex(4096);
+            let session = match concurrent {
+                false => run(server, handler.clone()).await,
+                true => run_concurrent(server, handler.clone(), Config::default()).await,
+            };
+
+            client.write_all(&read_request(1, "slow")).await.unwrap();
+            let mut bytes = read_packet(&mut client, MAX_PACKET_LEN).await.unwrap();
+            assert_eq!(Packet::try_from(&mut bytes).unwrap().get_request_id(), 1);
+
+            session.shutdown();
+            session.await.unwrap();
+            assert!(handler.closed.load(Ordering::Relaxed));
+            assert!(matches!(
+                read_packet(&mut client, MAX_PACKET_LEN).await,
+                Err(Error::UnexpectedEof)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_gone() {
+        let handler = SlowHandler::default();
+        let (client, server) = io::duplex(4096);
+        let session = run_concurrent(server, handler.clone(), Config::default()).await;
+
+        drop(client);
+        session.await.unwrap();
+        assert!(handler.closed.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_broken_stream() {
+        for concurrent in [false, true] {
+            let handler = SlowHandler::default();
+            let stream = BrokenPipe(Cursor::new(read_request(1, "fast")));
+            let session = match concurrent {
+                false => run(stream, handler.clone()).await,
+                true => run_concurrent(stream, handler.clone(), Config::default()).await,
+            };
+
+            assert!(matches!(session.await, Err(Error::IO(_))));
+            assert!(handler.closed.load(Ordering::Relaxed));
+        }
+    }
 }
@@ -0,0 +1,761 @@
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use tokio::{runtime, sync::Mutex};
+
+use super::Vfs;
+use crate::{
+    hash::HashAlgorithm,
+    protocol::{
+        encoded_len, types::*, Status, StatusCode, StatusError, MAX_PACKET_LEN, VERSION,
+        VERSION_MAX,
+    },
+    server::{HandleTable, Handler},
+};
+
+/// Size of the chunks of copy-data
+const COPY_CHUNK: u32 = 64 * 1024;
+/// Smallest block of check-file
+const MIN_HASH_BLOCK: u32 = 256;
+/// Bytes of a range whose hash is compared with the quick check hash of md5-hash
+const QUICK_CHECK_LEN: u64 = 2048;
+/// Room left for the header of SSH_FXP_DATA and SSH_FXP_WRITE in a packet
+const HEADER_ROOM: u32 = 1024;
+/// Longest read answered, leaving room for the header of SSH_FXP_DATA
+const MAX_READ_LEN: u32 = MAX_PACKET_LEN - HEADER_ROOM;
+/// Longest list of entries answered to SSH_FXP_READDIR, with the same room
+const MAX_NAME_LEN: usize = MAX_READ_LEN as usize;
+
+enum Entry<F, D> {
+    File(Arc<F>),
+    Dir(Arc<Mutex<DirCursor<D>>>),
+}
+
+/// Directory being listed. Entries are read from the backend when the
+/// client asks for them and sent in batches fitting in a packet
+struct DirCursor<D> {
+    dir: D,
+    /// Read from the backend but not sent yet
+    pending: VecDeque<File>,
+    done: bool,
+}
+
+/// Open handles of a session. Files left open are closed by
+/// [`Handler::on_close`], or once the last clone of the handler is dropped
+struct Handles<V: Vfs> {
+    vfs: Arc<V>,
+    table: Mutex<HandleTable<Entry<V::File, V::Dir>>>,
+}
+
+impl<V: Vfs> Drop for Handles<V> {
+    fn drop(&mut self) {
+        let files = self
+            .table
+            .get_mut()
+            .drain()
+            .filter_map(|entry| match entry {
+                Entry::File(file) => Some(file),
+                Entry::Dir(_) => None,
+            })
+            .collect::<Vec<_>>();
+        if files.is_empty() {
+            return;
+        }
+
+        let vfs = self.vfs.clone();
+        match runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    for file in files {
+                        if let Err(err) = vfs.close(&file).await {
+                            warn!("closing file left open failed: {}", err);
+                        }
+                    }
+                });
+            }
+            Err(_) => warn!("no runtime to close {} files left open", files.len()),
+        }
+    }
+}
+
+/// Server handler on top of any [`Vfs`].
+///
+/// Keeps the table of open handles, answers SSH_FX_EOF at the end of
+/// files and directories and converts the errors of the backend into
+/// status codes. Clones share the open handles and the negotiated
+/// version, so the handler can be
+/// used with [`run_concurrent`](crate::server::run_concurrent).
+/// Files still open when the session ends are closed
+pub struct VfsHandler<V: Vfs> {
+    vfs: Arc<V>,
+    handles: Arc<Handles<V>>,
+    version: Arc<AtomicU32>,
+    read_only: bool,
+    max_packet_len: u32,
+}
+
+impl<V: Vfs> VfsHandler<V> {
+    pub fn new(vfs: V) -> Self {
+        let vfs = Arc::new(vfs);
+        Self {
+            handles: Arc::new(Handles {
+                vfs: vfs.clone(),
+                table: Mutex::new(HandleTable::default()),
+            }),
+            vfs,
+            version: Arc::new(AtomicU32::new(VERSION)),
+            read_only: false,
+            max_packet_len: MAX_PACKET_LEN,
+        }
+    }
+
+    /// Limits the number of handles open at the same time, by default
+    /// [`MAX_HANDLES`](crate::server::MAX_HANDLES). To be called before
+    /// the handler is cloned, as clones made before keep the old limit
+    pub fn with_max_handles(mut self, limit: usize) -> Self {
+        self.handles = Arc::new(Handles {
+            vfs: self.vfs.clone(),
+            table: Mutex::new(HandleTable::with_limit(limit)),
+        });
+        self
+    }
+
+    /// Refuses every request modifying the file system with
+    /// SSH_FX_PERMISSION_DENIED, like `sftp-server -R`
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Longest packet accepted by the session, to be the same as its
+    /// [`Config::max_packet_len`](crate::server::Config::max_packet_len).
+    /// Reported by limits@openssh.com, and bounds the reads and writes
+    /// announced there. Default: [`MAX_PACKET_LEN`]
+    pub fn with_max_packet_len(mut self, limit: u32) -> Self {
+        self.max_packet_len = limit;
+        self
+    }
+
+    /// Longest data of a read or a write fitting in a packet, at least a byte
+    fn max_data_len(&self) -> u32 {
+        self.max_packet_len
+            .saturating_sub(HEADER_ROOM)
+            .clamp(1, MAX_READ_LEN)
+    }
+
+    /// Backend of the handler
+    pub fn vfs(&self) -> &V {
+        &self.vfs
+    }
+
+    fn check_writable(&self) -> Result<(), StatusError> {
+        match self.read_only {
+            true => Err(StatusError::new(
+                StatusCode::PermissionDenied,
+                "read-only session",
+            )),
+            false => Ok(()),
+        }
+    }
+
+    async fn insert(&self, entry: Entry<V::File, V::Dir>) -> Result<String, StatusError> {
+        self.handles.table.lock().await.insert(entry)
+    }
+
+    async fn file(&self, handle: &str) -> Result<Arc<V::File>, StatusCode> {
+        match self.handles.table.lock().await.get(handle) {
+            Some(Entry::File(file)) => Ok(file.clone()),
+            _ => Err(StatusCode::InvalidHandle),
+        }
+    }
+
+    /// Hashes `length` bytes from `start`, or until the end of the file when
+    /// `length` is zero, in blocks of `block_size` or at once when it is zero
+    async fn hash_range(
+        &self,
+        file: &V::File,
+        algorithm: HashAlgorithm,
+        start: u64,
+        length: u64,
+        block_size: u64,
+    ) -> io::Result<Vec<u8>> {
+        let end = match length {
+            0 => u64::MAX,
+            length => start.saturating_add(length),
+        };
+
+        let mut hasher = algorithm.hasher();
+        let mut hashes = Vec::new();
+        let mut offset = start;
+        let mut hashed = 0;
+        while offset < end {
+            let mut len = (end - offset).min(COPY_CHUNK.into());
+            if block_size != 0 {
+                len = len.min(block_size - hashed);
+            }
+
+            let data = self.vfs.read_at(file, offset, len as u32).await?;
+            if data.is_empty() {
+                break;
+            }
+
+            hasher.update(&data);
+            offset += data.len() as u64;
+            hashed += data.len() as u64;
+            if hashed == block_size {
+                hashes.extend_from_slice(&hasher.finalize_reset());
+                hashed = 0;
+            }
+        }
+
+        if hashed != 0 || hashes.is_empty() {
+            hashes.extend_from_slice(&hasher.finalize());
+        }
+
+        Ok(hashes)
+    }
+
+    async fn check_file(
+        &self,
+        file: &V::File,
+        hash_algorithms: &str,
+        start_offset: u64,
+        length: u64,
+        block_size: u32,
+    ) -> Result<(HashAlgorithm, Vec<u8>), StatusError> {
+        let algorithm = HashAlgorithm::select(hash_algorithms).ok_or_else(|| {
+            StatusError::new(StatusCode::OpUnsupported, "no supported hash algorithm")
+        })?;
+        if block_size != 0 && block_size < MIN_HASH_BLOCK {
+            return Err(StatusError::new(
+                StatusCode::Failure,
+                "block size below 256",
+            ));
+        }
+
+        //the hashes of all the blocks are sent in one packet
+        if block_size != 0 {
+            let size = self.vfs.fstat(file).await?.size.unwrap_or(u64::MAX);
+            let range = match length {
+                0 => u64::MAX,
+                length => length,
+            };
+            let blocks = range
+                .min(size.saturating_sub(start_offset))
+                .div_ceil(block_size.into());
+            let digest_len = algorithm.hasher().output_size() as u64;
+            if blocks.saturating_mul(digest_len) > MAX_READ_LEN.into() {
+                return Err(StatusError::new(
+                    StatusCode::Failure,
+                    "too many blocks to hash",
+                ));
+            }
+        }
+
+        let hashes = self
+            .hash_range(file, algorithm, start_offset, length, block_size.into())
+            .await?;
+        Ok((algorithm, hashes))
+    }
+
+    /// Hash of md5-hash, empty if `quick_check_hash` is not the one
+    /// of the start of the range
+    async fn md5_hash_range(
+        &self,
+        file: &V::File,
+        start_offset: u64,
+        length: u64,
+        quick_check_hash: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        if !quick_check_hash.is_empty() {
+            let quick_len = match length {
+                0 => QUICK_CHECK_LEN,
+                length => length.min(QUICK_CHECK_LEN),
+            };
+            let quick = self
+                .hash_range(file, HashAlgorithm::Md5, start_offset, quick_len, 0)
+                .await?;
+            if quick != quick_check_hash {
+                return Ok(Vec::new());
+            }
+        }
+
+        self.hash_range(file, HashAlgorithm::Md5, start_offset, length, 0)
+            .await
+    }
+
+    /// Opens a file to hash it by name
+    async fn open_read(&self, path: &str) -> io::Result<V::File> {
+        self.vfs
+            .open(path, OpenFlags::READ, &FileAttributes::empty())
+            .await
+    }
+}
+
+impl<V: Vfs> Clone for VfsHandler<V> {
+    fn clone(&self) -> Self {
+        Self {
+            vfs: self.vfs.clone(),
+            handles: self.handles.clone(),
+            version: self.version.clone(),
+            read_only: self.read_only,
+            max_packet_len: self.max_packet_len,
+        }
+    }
+}
+
+impl<V: Vfs + Default> Default for VfsHandler<V> {
+    fn default() -> Self {
+        Self::new(V::default())
+    }
+}
+
+fn ok(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: String::new(),
+        language_tag: "en-US".to_string(),
+    }
+}
+
+#[async_trait]
+impl<V: Vfs> Handler for VfsHandler<V> {
+    type Error = StatusError;
+
+    fn unimplemented(&self) -> Self::Error {
+        StatusCode::OpUnsupported.into()
+    }
+
+    async fn init(&mut self, arg: Init) -> Result<Version, Self::Error> {
+        if arg.version < VERSION {
+            return Err(self.unimplemented());
+        }
+        let version = arg.version.min(VERSION_MAX);
+        self.version.store(version, Ordering::Relaxed);
+        Ok(Version {
+            version,
+            ..Default::default()
+        })
+    }
+
+    async fn on_close(&mut self) {
+        let entries = self.handles.table.lock().await.drain().collect::<Vec<_>>();
+        for entry in entries {
+            if let Entry::File(file) = entry {
+                if let Err(err) = self.vfs.close(&file).await {
+                    warn!("closing file left open failed: {}", err);
+                }
+            }
+        }
+    }
+
+    async fn open(&mut self, arg: Open) -> Result<Handle, Self::Error> {
+        if arg.pflags.bits() & !OpenFlags::READ.bits() != 0 {
+            self.check_writable()?;
+        }
+        self.handles.table.lock().await.check_limit()?;
+        let file = self.vfs.open(&arg.filename, arg.pflags, &arg.attrs).await?;
+        let file = Arc::new(file);
+        let handle = match self.insert(Entry::File(file.clone())).await {
+            Ok(handle) => handle,
+            Err(err) => {
+                self.vfs.close(&file).await?;
+                return Err(err);
+            }
+        };
+
+        Ok(Handle { id: arg.id, handle })
+    }
+
+    async fn close(&mut self, arg: Close) -> Result<Status, Self::Error> {
+        let entry = self.handles.table.lock().await.remove(&arg.handle);
+        match entry {
+            Some(Entry::File(file)) => self.vfs.close(&file).await?,
+            Some(Entry::Dir(_)) => (),
+            None => return Err(StatusCode::InvalidHandle.into()),
+        }
+
+        Ok(ok(arg.id))
+    }
+
+    async fn read(&mut self, arg: Read) -> Result<Data, Self::Error> {
+        let file = self.file(&arg.handle).await?;
+        let data = self
+            .vfs
+            .read_at(&file, arg.offset, arg.len.min(self.max_data_len()))
+            .await?;
+
+        if data.is_empty() && arg.len > 0 {
+            return Err(StatusCode::Eof.into());
+        }
+
+        Ok(Data {
+            id: arg.id,
+            data: data.into(),
+        })
+    }
+
+    async fn write(&mut self, arg: Write) -> Result<Status, Self::Error> {
+        let file = self.file(&arg.handle).await?;
+        self.vfs.write_at(&file, arg.offset, &arg.data).await?;
+
+        Ok(ok(arg.id))
+    }
+
+    async fn lstat(&mut self, arg: LStat) -> Result<Attrs, Self::Error> {
+        Ok(Attrs {
+            id: arg.id,
+            attrs: self.vfs.lstat(&arg.path).await?,
+        })
+    }
+
+    async fn fstat(&mut self, arg: FStat) -> Result<Attrs, Self::Error> {
+        let file = self.file(&arg.handle).await?;
+        Ok(Attrs {
+            id: arg.id,
+            attrs: self.vfs.fstat(&file).await?,
+        })
+    }
+
+    async fn setstat(&mut self, arg: SetStat) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        self.vfs.setstat(&arg.path, &arg.attrs).await?;
+
+        Ok(ok(arg.id))
+    }
+
+    async fn fsetstat(&mut self, arg: FSetStat) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        let file = self.file(&arg.handle).await?;
+        self.vfs.fsetstat(&file, &arg.attrs).await?;
+
+        Ok(ok(arg.id))
+    }
+
+    async fn opendir(&mut self, arg: OpenDir) -> Result<Handle, Self::Error> {
+        self.handles.table.lock().await.check_limit()?;
+        let cursor = DirCursor {
+            dir: self.vfs.opendir(&arg.path).await?,
+            pending: VecDeque::new(),
+            done: false,
+        };
+
+        Ok(Handle {
+            id: arg.id,
+            handle: self
+                .insert(Entry::Dir(Arc::new(Mutex::new(cursor))))
+                .await?,
+        })
+    }
+
+    async fn readdir(&mut self, arg: ReadDir) -> Result<Name, Self::Error> {
+        let version = self.version.load(Ordering::Relaxed);
+        let cursor = match self.handles.table.lock().await.get(&arg.handle) {
+            Some(Entry::Dir(cursor)) => cursor.clone(),
+            _ => return Err(StatusCode::InvalidHandle.into()),
+        };
+
+        let mut cursor = cursor.lock().await;
+        while cursor.pending.is_empty() && !cursor.done {
+            let files = self.vfs.readdir(&cursor.dir).await?;
+            cursor.done = files.is_empty();
+            cursor.pending.extend(files);
+        }
+        let entries = &mut cursor.pending;
+
+        if entries.is_empty() {
+            return Err(StatusCode::Eof.into());
+        }
+
+        let mut files = Vec::new();
+        let mut len = 0;
+        while let Some(file) = entries.front() {
+            let file_len = encoded_len(file, version).map_err(|_| StatusCode::Failure)?;
+            if !files.is_empty() && len + file_len > MAX_NAME_LEN {
+                break;
+            }
+
+            len += file_len;
+            files.extend(entries.pop_front());
+        }
+
+        Ok(Name { id: arg.id, files })
+    }
+
+    async fn remove(&mut self, arg: Remove) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        self.vfs.remove(&arg.filename).await?;
+        Ok(ok(arg.id))
+    }
+
+    async fn mkdir(&mut self, arg: MkDir) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        self.vfs.mkdir(&arg.path, &arg.attrs).await?;
+
+        Ok(ok(arg.id))
+    }
+
+    async fn rmdir(&mut self, arg: RmDir) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        self.vfs.rmdir(&arg.path).await?;
+        Ok(ok(arg.id))
+    }
+
+    async fn realpath(&mut self, arg: RealPath) -> Result<Name, Self::Error> {
+        let filename = self.vfs.realpath(&arg.path).await?;
+        Ok(Name {
+            id: arg.id,
+            files: vec![File {
+                //like OpenSSH, a resolved path is its own longname
+                longname: filename.clone(),
+                filename,
+                attrs: FileAttributes::default(),
+            }],
+        })
+    }
+
+    async fn stat(&mut self, arg: Stat) -> Result<Attrs, Self::Error> {
+        Ok(Attrs {
+            id: arg.id,
+            attrs: self.vfs.stat(&arg.path).await?,
+        })
+    }
+
+    async fn rename(&mut self, arg: Rename) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        self.vfs.rename(&arg.oldpath, &arg.newpath).await?;
+
+        Ok(ok(arg.id))
+    }
+
+    async fn readlink(&mut self, arg: ReadLink) -> Result<Name, Self::Error> {
+        let filename = self.vfs.readlink(&arg.path).await?;
+        Ok(Name {
+            id: arg.id,
+            files: vec![File {
+                longname: filename.clone(),
+                filename,
+                attrs: FileAttributes::default(),
+            }],
+        })
+    }
+
+    async fn symlink(&mut self, arg: Symlink) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        self.vfs.symlink(&arg.linkpath, &arg.targetpath).await?;
+
+        Ok(ok(arg.id))
+    }
+
+    async fn link(&mut self, arg: Link) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        match arg.symlink {
+            true => {
+                self.vfs
+                    .symlink(&arg.new_link_path, &arg.existing_path)
+                    .await?
+            }
+            false => {
+                self.vfs
+                    .hardlink(&arg.existing_path, &arg.new_link_path)
+                    .await?
+            }
+        }
+
+        Ok(ok(arg.id))
+    }
+
+    fn extensions(&self) -> Vec<&'static str> {
+        vec![
+            PosixRename::NAME,
+            Statvfs::NAME,
+            Fstatvfs::NAME,
+            Hardlink::NAME,
+            Fsync::NAME,
+            LSetStat::NAME,
+            Limits::NAME,
+            ExpandPath::NAME,
+            CopyData::NAME,
+            CheckFileName::NAME,
+            CheckFileHandle::NAME,
+            Md5Hash::NAME,
+            Md5HashHandle::NAME,
+        ]
+    }
+
+    async fn posix_rename(&mut self, arg: PosixRename) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        self.vfs.posix_rename(&arg.oldpath, &arg.newpath).await?;
+
+        Ok(ok(arg.id))
+    }
+
+    async fn statvfs(&mut self, arg: Statvfs) -> Result<StatvfsReply, Self::Error> {
+        let reply = self.vfs.statvfs(&arg.path).await?;
+        Ok(StatvfsReply {
+            id: arg.id,
+            ..reply
+        })
+    }
+
+    async fn fstatvfs(&mut self, arg: Fstatvfs) -> Result<StatvfsReply, Self::Error> {
+        let file = self.file(&arg.handle).await?;
+        let reply = self.vfs.fstatvfs(&file).await?;
+        Ok(StatvfsReply {
+            id: arg.id,
+            ..reply
+        })
+    }
+
+    async fn hardlink(&mut self, arg: Hardlink) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        self.vfs.hardlink(&arg.oldpath, &arg.newpath).await?;
+
+        Ok(ok(arg.id))
+    }
+
+    async fn fsync(&mut self, arg: Fsync) -> Result<Status, Self::Error> {
+        let file = self.file(&arg.handle).await?;
+        self.vfs.fsync(&file).await?;
+        Ok(ok(arg.id))
+    }
+
+    async fn lsetstat(&mut self, arg: LSetStat) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        self.vfs.lsetstat(&arg.path, &arg.attrs).await?;
+
+        Ok(ok(arg.id))
+    }
+
+    async fn limits(&mut self, arg: Limits) -> Result<LimitsReply, Self::Error> {
+        Ok(LimitsReply {
+            id: arg.id,
+            max_packet_length: self.max_packet_len.into(),
+            max_read_length: self.max_data_len().into(),
+            max_write_length: self.max_data_len().into(),
+            max_open_handles: self.handles.table.lock().await.limit() as u64,
+        })
+    }
+
+    async fn expand_path(&mut self, arg: ExpandPath) -> Result<Name, Self::Error> {
+        let filename = self.vfs.expand_path(&arg.path).await?;
+        Ok(Name {
+            id: arg.id,
+            files: vec![File {
+                longname: filename.clone(),
+                filename,
+                attrs: FileAttributes::default(),
+            }],
+        })
+    }
+
+    async fn copy_data(&mut self, arg: CopyData) -> Result<Status, Self::Error> {
+        self.check_writable()?;
+        let overlapping = arg.read_data_length == 0
+            || arg.write_to_offset < arg.read_from_offset.saturating_add(arg.read_data_length)
+                && arg.read_from_offset < arg.write_to_offset.saturating_add(arg.read_data_length);
+        if arg.read_from_handle == arg.write_to_handle && overlapping {
+            return Err(StatusError::new(
+                StatusCode::Failure,
+                "source and destination overlap",
+            ));
+        }
+
+        let source = self.file(&arg.read_from_handle).await?;
+        let destination = self.file(&arg.write_to_handle).await?;
+        let mut copied = 0;
+        while arg.read_data_length == 0 || copied < arg.read_data_length {
+            let len = match arg.read_data_length {
+                0 => COPY_CHUNK,
+                length => (length - copied).min(COPY_CHUNK as u64) as u32,
+            };
+
+            let data = self
+                .vfs
+                .read_at(&source, arg.read_from_offset + copied, len)
+                .await?;
+            if data.is_empty() {
+                break;
+            }
+
+            self.vfs
+                .write_at(&destination, arg.write_to_offset + copied, &data)
+                .await?;
+            copied += data.len() as u64;
+        }
+
+        Ok(ok(arg.id))
+    }
+
+    async fn check_file_name(&mut self, arg: CheckFileName) -> Result<CheckFileReply, Self::Error> {
+        let file = self.open_read(&arg.filename).await?;
+        let result = self
+            .check_file(
+                &file,
+                &arg.hash_algorithms,
+                arg.start_offset,
+                arg.length,
+                arg.block_size,
+            )
+            .await;
+        self.vfs.close(&file).await?;
+
+        let (algorithm, hashes) = result?;
+        Ok(CheckFileReply {
+            id: arg.id,
+            hash_algorithm: algorithm.name().to_string(),
+            hashes,
+        })
+    }
+
+    async fn check_file_handle(
+        &mut self,
+        arg: CheckFileHandle,
+    ) -> Result<CheckFileReply, Self::Error> {
+        let file = self.file(&arg.handle).await?;
+        let (algorithm, hashes) = self
+            .check_file(
+                &file,
+                &arg.hash_algorithms,
+                arg.start_offset,
+                arg.length,
+                arg.block_size,
+            )
+            .await?;
+
+        Ok(CheckFileReply {
+            id: arg.id,
+            hash_algorithm: algorithm.name().to_string(),
+            hashes,
+        })
+    }
+
+    async fn md5_hash(&mut self, arg: Md5Hash) -> Result<Md5HashReply, Self::Error> {
+        let file = self.open_read(&arg.filename).await?;
+        let result = self
+            .md5_hash_range(&file, arg.start_offset, arg.length, &arg.quick_check_hash)
+            .await;
+        self.vfs.close(&file).await?;
+
+        Ok(Md5HashReply {
+            id: arg.id,
+            hash: result?,
+        })
+    }
+
+    async fn md5_hash_handle(&mut self, arg: Md5HashHandle) -> Result<Md5HashReply, Self::Error> {
+        let file = self.file(&arg.handle).await?;
+        let hash = self
+            .md5_hash_range(&file, arg.start_offset, arg.length, &arg.quick_check_hash)
+            .await?;
+
+        Ok(Md5HashReply { id: arg.id, hash })
+    }
+}
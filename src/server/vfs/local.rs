@@ -0,0 +1,1105 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::Permissions,
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+#[cfg(unix)]
+use std::{
+    ffi::CString,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt, io::AsRawFd},
+};
+
+use tokio::fs;
+
+use super::{UserLookup, Vfs};
+use crate::protocol::{
+    types::{File, FileAttributes, OpenFlags, StatvfsReply},
+    StatusError,
+};
+
+/// Number of symbolic links followed before giving up
+const MAX_SYMLINKS: usize = 40;
+/// Entries read and stat-ed at a time, like OpenSSH
+const DIR_BATCH: usize = 100;
+
+/// Backend serving the local file system through [`tokio::fs`].
+///
+/// Owners and groups are named with [`SystemUsers`](super::SystemUsers)
+/// on unix, see [`LocalFs::with_users`]
+#[derive(Clone)]
+pub struct LocalFs {
+    root: Option<PathBuf>,
+    users: Arc<dyn UserLookup>,
+}
+
+impl Default for LocalFs {
+    fn default() -> Self {
+        Self {
+            root: None,
+            #[cfg(unix)]
+            users: Arc::new(super::SystemUsers::default()),
+            #[cfg(not(unix))]
+            users: Arc::new(super::NumericIds),
+        }
+    }
+}
+
+impl fmt::Debug for LocalFs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalFs").field("root", &self.root).finish()
+    }
+}
+
+impl LocalFs {
+    /// Serves the whole file system, paths are used as they are
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Confines every path to `root`, which is seen by the client as `/`.
+    ///
+    /// `..` never leaves the root and symbolic links are resolved inside it,
+    /// absolute targets included. Reading a link whose absolute target lies
+    /// outside the root is refused. Links replaced on disk between resolving
+    /// a path and using it are not guarded against
+    pub fn with_root<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        Ok(Self {
+            root: Some(std::fs::canonicalize(root)?),
+            ..Self::default()
+        })
+    }
+
+    /// Names owners and groups with `users`, for example
+    /// [`NumericIds`](super::NumericIds) to leave them as numbers
+    pub fn with_users(self, users: impl UserLookup) -> Self {
+        Self {
+            users: Arc::new(users),
+            ..self
+        }
+    }
+
+    /// Attributes of `metadata` with the names of owner and group
+    fn attrs(&self, metadata: &std::fs::Metadata) -> FileAttributes {
+        let mut attrs = FileAttributes::from(metadata);
+        self.users.fill(&mut attrs);
+        attrs
+    }
+
+    /// Path on disk for a path of the client.
+    /// With `follow` unset a symbolic link in the last component is kept
+    async fn path(&self, path: &str, follow: bool) -> io::Result<PathBuf> {
+        match &self.root {
+            Some(root) => Ok(root.join(confine(root, path, follow).await?.join("/"))),
+            None => Ok(PathBuf::from(path)),
+        }
+    }
+}
+
+/// Components of `path` relative to `root` with `.`, `..`
+/// and symbolic links resolved without leaving `root`
+async fn confine(root: &Path, path: &str, follow: bool) -> io::Result<Vec<String>> {
+    let mut resolved = Vec::new();
+    let mut pending = path.split('/').map(String::from).collect::<VecDeque<_>>();
+    let mut links = 0;
+
+    while let Some(part) = pending.pop_front() {
+        match part.as_str() {
+            "" | "." => continue,
+            ".." => {
+                resolved.pop();
+                continue;
+            }
+            _ => (),
+        }
+
+        //reject prefixes and separators of the platform such as `C:` or `\`
+        if !matches!(
+            Path::new(&part).components().collect::<Vec<_>>()[..],
+            [Component::Normal(_)]
+        ) {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        resolved.push(part);
+        if pending.is_empty() && !follow {
+            break;
+        }
+
+        let real = root.join(resolved.join("/"));
+        match fs::symlink_metadata(&real).await {
+            Ok(metadata) if metadata.file_type().is_symlink() => (),
+            _ => continue,
+        }
+
+        links += 1;
+        if links > MAX_SYMLINKS {
+            return Err(io::Error::other("too many levels of symbolic links"));
+        }
+
+        let target = fs::read_link(&real).await?;
+        resolved.pop();
+        let target = match target.strip_prefix(root) {
+            Ok(target) => {
+                resolved.clear();
+                target.to_path_buf()
+            }
+            Err(_) => {
+                if target.has_root() {
+                    resolved.clear();
+                }
+                target
+            }
+        };
+
+        for component in target.components().rev() {
+            match component {
+                Component::Normal(part) => pending.push_front(part.to_string_lossy().into_owned()),
+                Component::ParentDir => pending.push_front("..".to_string()),
+                _ => (),
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+fn set_permissions(permissions: &mut Permissions, attrs: &FileAttributes) {
+    #[cfg(windows)]
+    {
+        if let Some(desired_perm) = attrs.permissions {
+            permissions.set_readonly(desired_perm & 0o555 == 0);
+        }
+    }
+    #[cfg(unix)]
+    {
+        if let Some(desired_perm) = attrs.permissions {
+            permissions.set_mode(desired_perm);
+        }
+    }
+}
+
+/// An open file of [`LocalFs`]. Reads and writes are positional,
+/// so concurrent requests on one handle do not share a cursor
+#[derive(Debug)]
+pub struct LocalFile {
+    file: Arc<std::fs::File>,
+    append: bool,
+}
+
+/// An open directory of [`LocalFs`], read from the disk as it is listed
+#[derive(Debug)]
+pub struct LocalDir {
+    entries: tokio::sync::Mutex<fs::ReadDir>,
+}
+
+impl LocalFile {
+    /// Runs `f` on the file in the blocking pool
+    async fn blocking<T, F>(&self, f: F) -> io::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&std::fs::File) -> io::Result<T> + Send + 'static,
+    {
+        let file = self.file.clone();
+        tokio::task::spawn_blocking(move || f(&file)).await?
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &std::fs::File, buf: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &std::fs::File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match std::os::windows::fs::FileExt::seek_write(file, buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Names the attribute that could not be set in the status message,
+/// keeping the code of the error
+fn attr_error(attr: &'static str) -> impl FnOnce(io::Error) -> io::Error {
+    move |err| {
+        let kind = err.kind();
+        let mut status = StatusError::from(err);
+        status.message = format!("cannot set {}: {}", attr, status.message);
+        io::Error::new(kind, status)
+    }
+}
+
+/// Applies the size, owner and times of `attrs` to `path` without
+/// opening it, which would block on a fifo. Owner and times are
+/// set on a symbolic link itself unless `follow`
+#[cfg(unix)]
+fn set_attrs(path: &Path, attrs: &FileAttributes, follow: bool) -> io::Result<()> {
+    let check = |result: libc::c_int, attr| match result {
+        0 => Ok(()),
+        _ => Err(attr_error(attr)(io::Error::last_os_error())),
+    };
+    let path = CString::new(path.as_os_str().as_bytes())?;
+
+    if let Some(size) = attrs.size {
+        let size = libc::off_t::try_from(size).map_err(|_| io::ErrorKind::InvalidInput)?;
+        // SAFETY: the path is nul-terminated
+        check(unsafe { libc::truncate(path.as_ptr(), size) }, "size")?;
+    }
+
+    //ownership before the permissions, as changing it clears the set-id bits
+    if attrs.uid.is_some() || attrs.gid.is_some() {
+        //an id of -1 is left unchanged
+        let uid = attrs.uid.unwrap_or(libc::uid_t::MAX);
+        let gid = attrs.gid.unwrap_or(libc::gid_t::MAX);
+        let chown = match follow {
+            true => libc::chown,
+            false => libc::lchown,
+        };
+        // SAFETY: the path is nul-terminated
+        check(unsafe { chown(path.as_ptr(), uid, gid) }, "owner")?;
+    }
+
+    if attrs.atime.is_some() || attrs.mtime.is_some() {
+        let timespec = |seconds: Option<u64>, nseconds: Option<u32>| {
+            // SAFETY: timespec is plain data, some targets have padding fields
+            let mut time: libc::timespec = unsafe { std::mem::zeroed() };
+            match seconds {
+                Some(seconds) => {
+                    time.tv_sec = libc::time_t::try_from(seconds)
+                        .map_err(|_| attr_error("times")(io::ErrorKind::InvalidInput.into()))?;
+                    time.tv_nsec = nseconds.unwrap_or(0).min(999_999_999) as _;
+                }
+                None => time.tv_nsec = libc::UTIME_OMIT,
+            }
+            io::Result::Ok(time)
+        };
+        let times = [
+            timespec(attrs.atime, attrs.atime_nseconds)?,
+            timespec(attrs.mtime, attrs.mtime_nseconds)?,
+        ];
+        let flags = match follow {
+            true => 0,
+            false => libc::AT_SYMLINK_NOFOLLOW,
+        };
+        // SAFETY: the path is nul-terminated and `times` holds two entries
+        let result =
+            unsafe { libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), flags) };
+        check(result, "times")?;
+    }
+
+    Ok(())
+}
+
+/// Applies the size and times of `attrs` to `path`
+#[cfg(not(unix))]
+fn set_attrs(path: &Path, attrs: &FileAttributes, _follow: bool) -> io::Result<()> {
+    let times = attrs.file_times();
+    if attrs.size.is_none() && times.is_none() {
+        return Ok(());
+    }
+
+    let file = std::fs::OpenOptions::new().write(true).open(path)?;
+    if let Some(size) = attrs.size {
+        file.set_len(size).map_err(attr_error("size"))?;
+    }
+    if let Some(times) = times {
+        file.set_times(times).map_err(attr_error("times"))?;
+    }
+
+    Ok(())
+}
+
+/// Applies every attribute of `attrs` to `path`, reporting the first
+/// one that fails
+async fn set_path_attrs(path: PathBuf, attrs: &FileAttributes, follow: bool) -> io::Result<()> {
+    let (target, set) = (path.clone(), attrs.clone());
+    tokio::task::spawn_blocking(move || set_attrs(&target, &set, follow)).await??;
+
+    if attrs.permissions.is_some() {
+        let mut permissions = fs::metadata(&path).await?.permissions();
+        set_permissions(&mut permissions, attrs);
+        fs::set_permissions(path, permissions)
+            .await
+            .map_err(attr_error("permissions"))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn statvfs_reply(stat: &libc::statvfs) -> StatvfsReply {
+    let mut f_flag = 0;
+    if stat.f_flag & libc::ST_RDONLY != 0 {
+        f_flag |= StatvfsReply::ST_RDONLY;
+    }
+    if stat.f_flag & libc::ST_NOSUID != 0 {
+        f_flag |= StatvfsReply::ST_NOSUID;
+    }
+
+    #[allow(clippy::useless_conversion)]
+    StatvfsReply {
+        id: 0,
+        f_bsize: stat.f_bsize.into(),
+        f_frsize: stat.f_frsize.into(),
+        f_blocks: stat.f_blocks.into(),
+        f_bfree: stat.f_bfree.into(),
+        f_bavail: stat.f_bavail.into(),
+        f_files: stat.f_files.into(),
+        f_ffree: stat.f_ffree.into(),
+        f_favail: stat.f_favail.into(),
+        f_fsid: stat.f_fsid.into(),
+        f_flag,
+        f_namemax: stat.f_namemax.into(),
+    }
+}
+
+#[async_trait]
+impl Vfs for LocalFs {
+    type File = LocalFile;
+    type Dir = LocalDir;
+
+    async fn open(
+        &self,
+        path: &str,
+        flags: OpenFlags,
+        attrs: &FileAttributes,
+    ) -> io::Result<Self::File> {
+        //exclude is only valid with create, truncate also applies to existing files
+        if flags.exclude() && !flags.create() {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+
+        let mut options = fs::OpenOptions::new();
+        options
+            .read(flags.read() || !flags.write() && !flags.append())
+            .write(flags.write())
+            .append(flags.append());
+        //std refuses to create or truncate without write access, unlike open(2)
+        #[cfg(unix)]
+        {
+            let mut custom = 0;
+            if flags.create() {
+                custom |= libc::O_CREAT;
+            }
+            if flags.exclude() {
+                custom |= libc::O_EXCL;
+            }
+            if flags.truncate() {
+                custom |= libc::O_TRUNC;
+            }
+            options.custom_flags(custom);
+            //like OpenSSH, the mode of a new file is applied through the umask
+            if let Some(permissions) = attrs.permissions {
+                options.mode(permissions & 0o7777);
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = attrs;
+            options
+                .write(flags.write() || flags.create() || flags.truncate())
+                .create(flags.create())
+                .create_new(flags.exclude())
+                .truncate(flags.truncate());
+        }
+        let file = options.open(self.path(path, true).await?).await?;
+
+        Ok(LocalFile {
+            file: Arc::new(file.into_std().await),
+            append: flags.append(),
+        })
+    }
+
+    async fn read_at(&self, file: &Self::File, offset: u64, len: u32) -> io::Result<Vec<u8>> {
+        file.blocking(move |file| {
+            let mut buffer = vec![0; len as usize];
+            let mut filled = 0;
+            while filled < buffer.len() {
+                match read_at(file, &mut buffer[filled..], offset + filled as u64) {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            buffer.truncate(filled);
+
+            Ok(buffer)
+        })
+        .await
+    }
+
+    async fn write_at(&self, file: &Self::File, offset: u64, data: &[u8]) -> io::Result<()> {
+        let (data, append) = (data.to_vec(), file.append);
+        file.blocking(move |mut file| match append {
+            //the offset is ignored, every write goes to the end of the file
+            true => file.write_all(&data),
+            false => write_all_at(file, &data, offset),
+        })
+        .await
+    }
+
+    async fn fstat(&self, file: &Self::File) -> io::Result<FileAttributes> {
+        Ok(self.attrs(&file.blocking(|file| file.metadata()).await?))
+    }
+
+    async fn fsetstat(&self, file: &Self::File, attrs: &FileAttributes) -> io::Result<()> {
+        let attrs = attrs.clone();
+        file.blocking(move |file| {
+            if let Some(size) = attrs.size {
+                file.set_len(size).map_err(attr_error("size"))?;
+            }
+            //ownership first, as changing it clears the set-id bits
+            #[cfg(unix)]
+            if attrs.uid.is_some() || attrs.gid.is_some() {
+                std::os::unix::fs::fchown(file, attrs.uid, attrs.gid)
+                    .map_err(attr_error("owner"))?;
+            }
+            if let Some(times) = attrs.file_times() {
+                file.set_times(times).map_err(attr_error("times"))?;
+            }
+
+            if attrs.permissions.is_some() {
+                let mut permissions = file.metadata()?.permissions();
+                set_permissions(&mut permissions, &attrs);
+                file.set_permissions(permissions)
+                    .map_err(attr_error("permissions"))?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn stat(&self, path: &str) -> io::Result<FileAttributes> {
+        Ok(self.attrs(&fs::metadata(self.path(path, true).await?).await?))
+    }
+
+    async fn lstat(&self, path: &str) -> io::Result<FileAttributes> {
+        Ok(self.attrs(&fs::symlink_metadata(self.path(path, false).await?).await?))
+    }
+
+    async fn setstat(&self, path: &str, attrs: &FileAttributes) -> io::Result<()> {
+        set_path_attrs(self.path(path, true).await?, attrs, true).await
+    }
+
+    async fn opendir(&self, path: &str) -> io::Result<Self::Dir> {
+        Ok(LocalDir {
+            entries: tokio::sync::Mutex::new(fs::read_dir(self.path(path, true).await?).await?),
+        })
+    }
+
+    async fn readdir(&self, dir: &Self::Dir) -> io::Result<Vec<File>> {
+        let mut files = Vec::new();
+        let mut entries = dir.entries.lock().await;
+
+        while files.len() < DIR_BATCH {
+            let Some(entry) = entries.next_entry().await? else {
+                break;
+            };
+            let metadata = entry.metadata().await?;
+            files.push(File {
+                filename: entry.file_name().to_string_lossy().into_owned(),
+                longname: String::new(),
+                attrs: self.attrs(&metadata),
+            });
+        }
+
+        Ok(files)
+    }
+
+    async fn remove(&self, path: &str) -> io::Result<()> {
+        fs::remove_file(self.path(path, false).await?).await
+    }
+
+    async fn mkdir(&self, path: &str, attrs: &FileAttributes) -> io::Result<()> {
+        let path = self.path(path, false).await?;
+        let mut builder = fs::DirBuilder::new();
+        //like OpenSSH, the mode is applied through the umask
+        #[cfg(unix)]
+        if let Some(permissions) = attrs.permissions {
+            builder.mode(permissions & 0o7777);
+        }
+        builder.create(&path).await?;
+
+        #[cfg(unix)]
+        let attrs = &FileAttributes {
+            permissions: None,
+            ..attrs.clone()
+        };
+        set_path_attrs(path, attrs, true).await
+    }
+
+    async fn rmdir(&self, path: &str) -> io::Result<()> {
+        fs::remove_dir(self.path(path, false).await?).await
+    }
+
+    async fn realpath(&self, path: &str) -> io::Result<String> {
+        let Some(root) = &self.root else {
+            return Ok(fs::canonicalize(path).await?.to_string_lossy().into_owned());
+        };
+
+        let parts = confine(root, path, true).await?;
+        fs::symlink_metadata(root.join(parts.join("/"))).await?;
+        Ok(format!("/{}", parts.join("/")))
+    }
+
+    async fn rename(&self, oldpath: &str, newpath: &str) -> io::Result<()> {
+        fs::rename(
+            self.path(oldpath, false).await?,
+            self.path(newpath, false).await?,
+        )
+        .await
+    }
+
+    async fn readlink(&self, path: &str) -> io::Result<String> {
+        let target = fs::read_link(self.path(path, false).await?).await?;
+        let target = match self.root.as_ref().map(|root| target.strip_prefix(root)) {
+            Some(Ok(target)) => Path::new("/").join(target),
+            //absolute targets outside the root would reveal paths of the host
+            Some(Err(_)) if target.has_root() => return Err(io::ErrorKind::PermissionDenied.into()),
+            _ => target,
+        };
+
+        Ok(target.to_string_lossy().into_owned())
+    }
+
+    async fn symlink(&self, linkpath: &str, targetpath: &str) -> io::Result<()> {
+        let linkpath = self.path(linkpath, false).await?;
+        //absolute targets stay valid on disk by pointing inside the root
+        let targetpath = match &self.root {
+            Some(_) if targetpath.starts_with('/') => self.path(targetpath, false).await?,
+            _ => PathBuf::from(targetpath),
+        };
+
+        #[cfg(windows)]
+        {
+            //if target path is a directory then use symlink_dir
+            let parent = linkpath.parent().unwrap_or(Path::new(""));
+            if fs::metadata(parent.join(&targetpath)).await?.is_dir() {
+                fs::symlink_dir(&targetpath, &linkpath).await
+            } else {
+                fs::symlink_file(&targetpath, &linkpath).await
+            }
+        }
+        #[cfg(unix)]
+        {
+            fs::symlink(&targetpath, &linkpath).await
+        }
+    }
+
+    async fn posix_rename(&self, oldpath: &str, newpath: &str) -> io::Result<()> {
+        self.rename(oldpath, newpath).await
+    }
+
+    async fn hardlink(&self, oldpath: &str, newpath: &str) -> io::Result<()> {
+        fs::hard_link(
+            self.path(oldpath, false).await?,
+            self.path(newpath, false).await?,
+        )
+        .await
+    }
+
+    async fn fsync(&self, file: &Self::File) -> io::Result<()> {
+        file.blocking(|file| file.sync_all()).await
+    }
+
+    async fn lsetstat(&self, path: &str, attrs: &FileAttributes) -> io::Result<()> {
+        let path = self.path(path, false).await?;
+        let metadata = fs::symlink_metadata(&path).await?;
+        //size and permissions of a link cannot be changed without following it
+        if metadata.file_type().is_symlink()
+            && (attrs.size.is_some() || attrs.permissions.is_some())
+        {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+
+        set_path_attrs(path, attrs, false).await
+    }
+
+    #[cfg(unix)]
+    async fn statvfs(&self, path: &str) -> io::Result<StatvfsReply> {
+        let path = CString::new(self.path(path, true).await?.as_os_str().as_bytes())?;
+        tokio::task::spawn_blocking(move || {
+            let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+            // SAFETY: the path is nul-terminated and `stat` is written on success
+            if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(statvfs_reply(unsafe { &stat.assume_init() }))
+        })
+        .await?
+    }
+
+    #[cfg(unix)]
+    async fn fstatvfs(&self, file: &Self::File) -> io::Result<StatvfsReply> {
+        file.blocking(|file| {
+            let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+            // SAFETY: the descriptor stays open while the file is borrowed
+            if unsafe { libc::fstatvfs(file.as_raw_fd(), stat.as_mut_ptr()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(statvfs_reply(unsafe { &stat.assume_init() }))
+        })
+        .await
+    }
+
+    async fn expand_path(&self, path: &str) -> io::Result<String> {
+        let home = std::env::var_os("HOME");
+        match (&self.root, home, path.strip_prefix('~')) {
+            (None, Some(home), Some(rest)) if rest.is_empty() || rest.starts_with('/') => {
+                let mut home = home.to_string_lossy().into_owned();
+                home.push_str(rest);
+                self.realpath(&home).await
+            }
+            (Some(_), _, Some(rest)) if rest.is_empty() || rest.starts_with('/') => {
+                self.realpath(&format!(".{}", rest)).await
+            }
+            _ => self.realpath(path).await,
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::{
+        os::unix,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use bytes::Bytes;
+    use tokio::io;
+    use tokio_stream::StreamExt;
+
+    use super::*;
+    use crate::{
+        client::SftpSession,
+        protocol::StatusCode,
+        server::{
+            self,
+            vfs::{NumericIds, SystemUsers, UserLookup, VfsHandler},
+        },
+        test_util::TempDir,
+    };
+
+    /// Jail in an empty temporary directory next to a file that must stay hidden
+    struct Jail {
+        dir: TempDir,
+    }
+
+    impl Jail {
+        fn new(name: &str) -> Self {
+            let dir = TempDir::new(name);
+            std::fs::create_dir_all(dir.join("jail/dir")).unwrap();
+            std::fs::write(dir.join("secret"), "secret").unwrap();
+            std::fs::write(dir.join("jail/file"), "file").unwrap();
+            Self { dir }
+        }
+
+        fn root(&self) -> PathBuf {
+            self.dir.join("jail")
+        }
+
+        fn secret(&self) -> String {
+            self.dir.join("secret").to_string_lossy().into_owned()
+        }
+
+        async fn session(&self) -> SftpSession {
+            let (client, server) = io::duplex(4096);
+            let handler = VfsHandler::new(LocalFs::with_root(self.root()).unwrap());
+            tokio::spawn(server::run(server, handler));
+            SftpSession::new(client).await.unwrap()
+        }
+    }
+
+    async fn open(sftp: &SftpSession, path: &str) -> Result<Bytes, StatusCode> {
+        let handle = sftp
+            .open(path, OpenFlags::READ, FileAttributes::default())
+            .await?;
+        let data = sftp.read(handle.clone(), 0, 64).await;
+        sftp.close(handle).await?;
+        data
+    }
+
+    #[tokio::test]
+    async fn test_open_traversal() {
+        let jail = Jail::new("open");
+        let sftp = jail.session().await;
+
+        assert_eq!(open(&sftp, "/file").await.unwrap(), &b"file"[..]);
+        assert_eq!(open(&sftp, "../../file").await.unwrap(), &b"file"[..]);
+        assert_eq!(open(&sftp, "/dir/../../file").await.unwrap(), &b"file"[..]);
+        for path in [
+            "../secret",
+            "/../secret",
+            "dir/../../secret",
+            &jail.secret(),
+        ] {
+            assert_eq!(open(&sftp, path).await, Err(StatusCode::NoSuchFile));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_opendir_traversal() {
+        let jail = Jail::new("opendir");
+        let sftp = jail.session().await;
+
+        for path in ["..", "/..", "dir/../..", "/"] {
+            let mut names = sftp
+                .read_dir(path)
+                .await
+                .unwrap()
+                .map(|f| f.unwrap().filename)
+                .collect::<Vec<_>>()
+                .await;
+            names.sort();
+            assert_eq!(names, ["dir", "file"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_readdir_batches() {
+        let jail = Jail::new("readdir");
+        let sftp = jail.session().await;
+        for i in 0..250 {
+            std::fs::write(jail.root().join(format!("dir/{}", i)), "").unwrap();
+        }
+
+        //read from the disk as listed, a batch at a time
+        let handle = sftp.opendir("/dir").await.unwrap();
+        let mut listed = 0;
+        loop {
+            match sftp.readdir(handle.as_str()).await {
+                Ok(files) => {
+                    assert!((1..=DIR_BATCH).contains(&files.len()));
+                    assert!(files.iter().all(|f| f.attrs.is_regular()));
+                    listed += files.len();
+                }
+                Err(StatusCode::Eof) => break,
+                Err(err) => panic!("{}", err),
+            }
+        }
+        sftp.close(handle).await.unwrap();
+        assert_eq!(listed, 250);
+    }
+
+    #[tokio::test]
+    async fn test_open_flags() {
+        let jail = Jail::new("open-flags");
+        let sftp = jail.session().await;
+
+        let attrs = FileAttributes {
+            permissions: Some(0o600),
+            ..FileAttributes::empty()
+        };
+        let flags = OpenFlags::CREATE | OpenFlags::WRITE;
+        let handle = sftp.open("/new", flags, attrs).await.unwrap();
+        sftp.close(handle).await.unwrap();
+        let metadata = std::fs::metadata(jail.root().join("new")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o600);
+
+        //truncating an existing file, which must not be created
+        let flags = || OpenFlags::WRITE | OpenFlags::TRUNCATE;
+        let handle = sftp
+            .open("/file", flags(), FileAttributes::empty())
+            .await
+            .unwrap();
+        sftp.close(handle).await.unwrap();
+        assert_eq!(
+            std::fs::metadata(jail.root().join("file")).unwrap().len(),
+            0
+        );
+        let missing = sftp
+            .open("/missing", flags(), FileAttributes::empty())
+            .await;
+        assert_eq!(missing.map(|_| ()), Err(StatusCode::NoSuchFile));
+
+        //creating or truncating does not need write access
+        let flags = OpenFlags::READ | OpenFlags::CREATE;
+        let handle = sftp
+            .open("/read", flags, FileAttributes::empty())
+            .await
+            .unwrap();
+        let read = sftp.read(handle.clone(), 0, 64).await;
+        assert_eq!(read, Err(StatusCode::Eof));
+        let written = sftp.write(handle.clone(), 0, b"data".to_vec()).await;
+        assert!(written.is_err());
+        sftp.close(handle).await.unwrap();
+        assert_eq!(std::fs::read(jail.root().join("read")).unwrap(), b"");
+
+        std::fs::write(jail.root().join("file"), "file").unwrap();
+        let flags = OpenFlags::READ | OpenFlags::TRUNCATE;
+        let handle = sftp
+            .open("/file", flags, FileAttributes::empty())
+            .await
+            .unwrap();
+        sftp.close(handle).await.unwrap();
+        assert_eq!(std::fs::read(jail.root().join("file")).unwrap(), b"");
+
+        let flags = OpenFlags::READ | OpenFlags::CREATE | OpenFlags::EXCLUDE;
+        let exists = sftp.open("/read", flags, FileAttributes::empty()).await;
+        assert_eq!(exists.map(|_| ()), Err(StatusCode::FileAlreadyExists));
+    }
+
+    #[tokio::test]
+    async fn test_rename_traversal() {
+        let jail = Jail::new("rename");
+        let sftp = jail.session().await;
+
+        sftp.rename("/file", "../../moved").await.unwrap();
+        assert!(jail.root().join("moved").exists());
+        assert!(!jail.dir.join("moved").exists());
+
+        assert_eq!(
+            sftp.rename("../secret", "/stolen").await,
+            Err(StatusCode::NoSuchFile)
+        );
+        assert!(jail.dir.join("secret").exists());
+    }
+
+    #[tokio::test]
+    async fn test_symlink_traversal() {
+        let jail = Jail::new("symlink");
+        let sftp = jail.session().await;
+
+        sftp.symlink("/abs", &jail.secret()).await.unwrap();
+        sftp.symlink("/up", "../..").await.unwrap();
+        sftp.symlink("/dir/home", "/dir").await.unwrap();
+        unix::fs::symlink(jail.secret(), jail.root().join("host")).unwrap();
+        unix::fs::symlink("../secret", jail.root().join("relative")).unwrap();
+
+        for path in ["/abs", "/up/secret", "/host", "/relative"] {
+            assert_eq!(open(&sftp, path).await, Err(StatusCode::NoSuchFile));
+        }
+        assert_eq!(open(&sftp, "/up/file").await.unwrap(), &b"file"[..]);
+
+        assert_eq!(sftp.readlink("/dir/home").await.unwrap(), "/dir");
+        assert_eq!(sftp.readlink("/relative").await.unwrap(), "../secret");
+        unix::fs::symlink("/etc/passwd", jail.root().join("passwd")).unwrap();
+        for path in ["/host", "/passwd"] {
+            assert_eq!(sftp.readlink(path).await, Err(StatusCode::PermissionDenied));
+        }
+        assert_eq!(
+            std::fs::read_link(jail.root().join("dir/home")).unwrap(),
+            jail.root().canonicalize().unwrap().join("dir")
+        );
+        assert!(sftp.stat("/dir/home/home").await.unwrap().is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_realpath() {
+        let jail = Jail::new("realpath");
+        let sftp = jail.session().await;
+        sftp.symlink("/link", "dir").await.unwrap();
+        sftp.symlink("/loop", "loop").await.unwrap();
+
+        assert_eq!(sftp.realpath(".").await.unwrap(), "/");
+        assert_eq!(sftp.realpath("../../..").await.unwrap(), "/");
+        assert_eq!(sftp.realpath("/dir/../file").await.unwrap(), "/file");
+        assert_eq!(sftp.realpath("link/..").await.unwrap(), "/");
+        assert_eq!(sftp.realpath("/link").await.unwrap(), "/dir");
+        assert_eq!(sftp.realpath("/missing").await, Err(StatusCode::NoSuchFile));
+        assert_eq!(sftp.realpath("/loop").await, Err(StatusCode::Failure));
+    }
+
+    #[tokio::test]
+    async fn test_names() {
+        let jail = Jail::new("names");
+        let sftp = jail.session().await;
+        std::fs::set_permissions(jail.root().join("dir"), Permissions::from_mode(0o1755)).unwrap();
+
+        // SAFETY: geteuid cannot fail
+        let uid = unsafe { libc::geteuid() };
+        let attrs = sftp.lstat("/dir").await.unwrap();
+        assert_eq!(attrs.user, SystemUsers::default().user(uid));
+
+        let handle = sftp.opendir("/").await.unwrap();
+        let files = sftp.readdir(handle.as_str()).await.unwrap();
+        sftp.close(handle).await.unwrap();
+        let dir = files.into_iter().find(|f| f.filename == "dir").unwrap();
+        //the longname is only sent in version 3
+        let longname = dir.longname();
+        assert!(longname.starts_with("drwxr-xr-t"), "{}", longname);
+        assert!(longname.contains(&attrs.user.unwrap()), "{}", longname);
+
+        let handler = VfsHandler::new(
+            LocalFs::with_root(jail.root())
+                .unwrap()
+                .with_users(NumericIds),
+        );
+        let (client, server) = io::duplex(4096);
+        tokio::spawn(server::run(server, handler));
+        let sftp = SftpSession::new(client).await.unwrap();
+        assert_eq!(sftp.stat("/dir").await.unwrap().user, Some(uid.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_extensions() {
+        let jail = Jail::new("extensions");
+        let sftp = jail.session().await;
+
+        assert_eq!(sftp.expand_path("~/dir").await.unwrap(), "/dir");
+        assert!(sftp.statvfs("/").await.unwrap().f_bsize > 0);
+
+        sftp.hardlink("/file", "/dir/link").await.unwrap();
+        assert_eq!(open(&sftp, "/dir/link").await.unwrap(), &b"file"[..]);
+        sftp.hardlink("/dir/link", "../escaped").await.unwrap();
+        assert!(jail.root().join("escaped").exists());
+
+        sftp.posix_rename("/dir/link", "/file").await.unwrap();
+        assert_eq!(open(&sftp, "/file").await.unwrap(), &b"file"[..]);
+    }
+
+    #[tokio::test]
+    async fn test_setstat() {
+        let jail = Jail::new("setstat");
+        let sftp = jail.session().await;
+        let path = jail.root().join("file");
+        unix::fs::symlink("file", jail.root().join("link")).unwrap();
+
+        assert!(sftp.lstat("/link").await.unwrap().is_symlink());
+        assert!(sftp.stat("/link").await.unwrap().is_regular());
+
+        // SAFETY: geteuid and getegid cannot fail
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        let attrs = FileAttributes {
+            size: Some(2),
+            uid: Some(uid),
+            gid: Some(gid),
+            atime: Some(1_000_000_000),
+            atime_nseconds: Some(5),
+            mtime: Some(1_500_000_000),
+            mtime_nseconds: Some(123_456_789),
+            ..FileAttributes::empty()
+        };
+        sftp.setstat("/file", attrs).await.unwrap();
+
+        let metadata = std::fs::metadata(&path).unwrap();
+        let accessed = UNIX_EPOCH + Duration::new(1_000_000_000, 5);
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata.accessed().unwrap(), accessed);
+        assert_eq!(
+            metadata.modified().unwrap(),
+            UNIX_EPOCH + Duration::new(1_500_000_000, 123_456_789)
+        );
+
+        let attrs = sftp.stat("/file").await.unwrap();
+        assert_eq!(attrs.atime, Some(1_000_000_000));
+        assert_eq!(attrs.mtime, Some(1_500_000_000));
+        assert_eq!(attrs.mtime_nseconds, Some(123_456_789));
+
+        let handle = sftp
+            .open("/file", OpenFlags::WRITE, FileAttributes::empty())
+            .await
+            .unwrap();
+        let attrs = FileAttributes {
+            size: Some(0),
+            mtime: Some(2_000_000_000),
+            ..FileAttributes::empty()
+        };
+        sftp.fsetstat(handle.clone(), attrs).await.unwrap();
+        sftp.close(handle).await.unwrap();
+
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.len(), 0);
+        assert_eq!(metadata.accessed().unwrap(), accessed);
+        assert_eq!(
+            metadata.modified().unwrap(),
+            UNIX_EPOCH + Duration::from_secs(2_000_000_000)
+        );
+
+        let fs = LocalFs::with_root(jail.root()).unwrap();
+        let attrs = FileAttributes {
+            size: Some(0),
+            ..FileAttributes::empty()
+        };
+        let err = StatusError::from(fs.setstat("/dir", &attrs).await.unwrap_err());
+        assert_eq!(err.status_code, StatusCode::FileIsADirectory);
+        assert_eq!(err.message, "cannot set size: Is a directory");
+
+        let attrs = FileAttributes {
+            mtime: Some(u64::MAX),
+            ..FileAttributes::empty()
+        };
+        let err = StatusError::from(fs.setstat("/file", &attrs).await.unwrap_err());
+        assert_eq!(err.status_code, StatusCode::InvalidParameter);
+        assert_eq!(err.message, "cannot set times: invalid input parameter");
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().modified().unwrap(),
+            UNIX_EPOCH + Duration::from_secs(2_000_000_000)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lsetstat_mkdir() {
+        let jail = Jail::new("lsetstat");
+        let sftp = jail.session().await;
+        let link = jail.root().join("link");
+        unix::fs::symlink("file", &link).unwrap();
+
+        let mtime = |mtime| FileAttributes {
+            mtime: Some(mtime),
+            ..FileAttributes::empty()
+        };
+        sftp.lsetstat("/link", mtime(1_000_000_000)).await.unwrap();
+        let modified = |path| std::fs::symlink_metadata(path).unwrap().modified().unwrap();
+        assert_eq!(
+            modified(&link),
+            UNIX_EPOCH + Duration::from_secs(1_000_000_000)
+        );
+        assert_ne!(modified(&jail.root().join("file")), modified(&link));
+
+        let permissions = FileAttributes {
+            permissions: Some(0o600),
+            ..FileAttributes::empty()
+        };
+        assert_eq!(
+            sftp.lsetstat("/link", permissions).await,
+            Err(StatusCode::OpUnsupported)
+        );
+
+        let attrs = FileAttributes {
+            permissions: Some(0o700),
+            ..mtime(1_500_000_000)
+        };
+        sftp.mkdir("/made", attrs).await.unwrap();
+        let metadata = std::fs::metadata(jail.root().join("made")).unwrap();
+        assert!(metadata.is_dir());
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o700);
+        assert_eq!(
+            metadata.modified().unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_500_000_000)
+        );
+
+        //parents are not created and existing directories are errors
+        assert_eq!(
+            sftp.mkdir("/made", FileAttributes::empty()).await,
+            Err(StatusCode::FileAlreadyExists)
+        );
+        assert_eq!(
+            sftp.mkdir("/missing/made", FileAttributes::empty()).await,
+            Err(StatusCode::NoSuchFile)
+        );
+        assert!(!jail.root().join("missing").exists());
+    }
+}
@@ -0,0 +1,774 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use super::Vfs;
+use crate::{
+    protocol::types::{File, FileAttributes, FileType, OpenFlags},
+    utils,
+};
+
+/// Number of symbolic links followed before giving up
+const MAX_SYMLINKS: usize = 32;
+
+/// Largest size of a file, so that clients cannot exhaust the memory
+const MAX_FILE_SIZE: u64 = 1 << 30;
+
+#[derive(Debug)]
+enum Kind {
+    File(Vec<u8>),
+    Dir,
+    Symlink(String),
+}
+
+#[derive(Debug)]
+struct Inode {
+    kind: Kind,
+    permissions: u32,
+    atime: u64,
+    mtime: u64,
+}
+
+impl Inode {
+    fn new(kind: Kind, permissions: u32) -> Arc<Mutex<Self>> {
+        let now = utils::unix(SystemTime::now()).0;
+        Arc::new(Mutex::new(Self {
+            kind,
+            permissions: permissions & 0o7777,
+            atime: now,
+            mtime: now,
+        }))
+    }
+
+    fn attrs(&self) -> FileAttributes {
+        let (size, r#type) = match &self.kind {
+            Kind::File(data) => (data.len() as u64, FileType::Regular),
+            Kind::Dir => (0, FileType::Dir),
+            Kind::Symlink(target) => (target.len() as u64, FileType::Symlink),
+        };
+
+        FileAttributes {
+            size: Some(size),
+            permissions: Some(self.permissions | r#type.mode()),
+            atime: Some(self.atime),
+            mtime: Some(self.mtime),
+            ..Default::default()
+        }
+    }
+
+    fn apply(&mut self, attrs: &FileAttributes) -> io::Result<()> {
+        if let (Some(size), Kind::File(data)) = (attrs.size, &mut self.kind) {
+            resize(data, size)?;
+        }
+        if let Some(permissions) = attrs.permissions {
+            self.permissions = permissions & 0o7777;
+        }
+        if let Some(atime) = attrs.atime {
+            self.atime = atime;
+        }
+        if let Some(mtime) = attrs.mtime {
+            self.mtime = mtime;
+        }
+
+        Ok(())
+    }
+}
+
+/// Grows or shrinks the content of a file to `size`, up to [`MAX_FILE_SIZE`]
+fn resize(data: &mut Vec<u8>, size: u64) -> io::Result<()> {
+    if size > MAX_FILE_SIZE {
+        return Err(io::ErrorKind::StorageFull.into());
+    }
+
+    let size = size as usize;
+    if size > data.len() {
+        data.try_reserve_exact(size - data.len())
+            .map_err(|_| io::ErrorKind::StorageFull)?;
+    }
+    data.resize(size, 0);
+
+    Ok(())
+}
+
+/// An open file of [`MemoryFs`]. Stays valid after being removed or renamed
+#[derive(Debug)]
+pub struct MemoryFile {
+    inode: Arc<Mutex<Inode>>,
+    read: bool,
+    write: bool,
+    append: bool,
+}
+
+/// An open directory of [`MemoryFs`]. Its entries are those present
+/// when it was opened, with their attributes as they are when listed
+#[derive(Debug)]
+pub struct MemoryDir {
+    entries: Mutex<Vec<(String, Arc<Mutex<Inode>>)>>,
+}
+
+/// Backend keeping the whole tree in memory.
+///
+/// Paths are resolved from `/`, so `a/b` and `/a/./b` are the same file.
+/// Only symbolic links in the last component of a path are followed.
+/// Files grow up to 1 GiB, larger sizes fail with no space left
+#[derive(Debug, Clone)]
+pub struct MemoryFs {
+    tree: Arc<Mutex<BTreeMap<String, Arc<Mutex<Inode>>>>>,
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self {
+        let mut tree = BTreeMap::new();
+        tree.insert("/".to_string(), Inode::new(Kind::Dir, 0o755));
+        Self {
+            tree: Arc::new(Mutex::new(tree)),
+        }
+    }
+}
+
+type Tree = BTreeMap<String, Arc<Mutex<Inode>>>;
+
+/// Absolute form of `path` without `.`, `..` and repeated slashes
+fn normalize(path: &str) -> String {
+    let mut parts = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+
+    format!("/{}", parts.join("/"))
+}
+
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    }
+}
+
+fn child(dir: &str, name: &str) -> String {
+    match dir {
+        "/" => format!("/{}", name),
+        dir => format!("{}/{}", dir, name),
+    }
+}
+
+fn not_found() -> io::Error {
+    io::ErrorKind::NotFound.into()
+}
+
+fn get(tree: &Tree, path: &str) -> io::Result<Arc<Mutex<Inode>>> {
+    tree.get(path).cloned().ok_or_else(not_found)
+}
+
+/// Follows symbolic links until a path that is not one
+fn resolve(tree: &Tree, path: &str) -> io::Result<String> {
+    let mut path = normalize(path);
+    for _ in 0..MAX_SYMLINKS {
+        let target = match &get(tree, &path)?.lock().unwrap().kind {
+            Kind::Symlink(target) => target.clone(),
+            _ => return Ok(path),
+        };
+        path = match target.starts_with('/') {
+            true => normalize(&target),
+            false => normalize(&child(parent(&path), &target)),
+        };
+    }
+
+    Err(io::Error::other("too many symbolic links"))
+}
+
+/// Adds a new node, the parent directory must exist
+fn insert(tree: &mut Tree, path: &str, inode: Arc<Mutex<Inode>>) -> io::Result<()> {
+    if tree.contains_key(path) {
+        return Err(io::ErrorKind::AlreadyExists.into());
+    }
+    if !matches!(get(tree, parent(path))?.lock().unwrap().kind, Kind::Dir) {
+        return Err(not_found());
+    }
+
+    tree.insert(path.to_string(), inode);
+    Ok(())
+}
+
+fn is_descendant(path: &str, dir: &str) -> bool {
+    dir == "/"
+        || path
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Moves a node with its descendants, `newpath` must not exist
+fn rename(tree: &mut Tree, oldpath: &str, newpath: &str) -> io::Result<()> {
+    get(tree, oldpath)?;
+    if oldpath == "/" || is_descendant(newpath, oldpath) {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+
+    let inode = tree.remove(oldpath).unwrap();
+    if let Err(e) = insert(tree, newpath, inode.clone()) {
+        tree.insert(oldpath.to_string(), inode);
+        return Err(e);
+    }
+
+    let moved = tree
+        .keys()
+        .filter(|name| is_descendant(name, oldpath))
+        .cloned()
+        .collect::<Vec<_>>();
+    for name in moved {
+        let inode = tree.remove(&name).unwrap();
+        tree.insert(format!("{}{}", newpath, &name[oldpath.len()..]), inode);
+    }
+
+    Ok(())
+}
+
+impl MemoryFs {
+    fn tree(&self) -> std::sync::MutexGuard<'_, Tree> {
+        self.tree.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl Vfs for MemoryFs {
+    type File = MemoryFile;
+    type Dir = MemoryDir;
+
+    async fn open(
+        &self,
+        path: &str,
+        flags: OpenFlags,
+        attrs: &FileAttributes,
+    ) -> io::Result<Self::File> {
+        let mut tree = self.tree();
+        let path = match resolve(&tree, path) {
+            Ok(path) => path,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && flags.create() => normalize(path),
+            Err(e) => return Err(e),
+        };
+
+        let inode = match tree.get(&path) {
+            Some(_) if flags.exclude() => return Err(io::ErrorKind::AlreadyExists.into()),
+            Some(inode) => inode.clone(),
+            None if flags.create() => {
+                let inode = Inode::new(Kind::File(Vec::new()), attrs.permissions.unwrap_or(0o644));
+                insert(&mut tree, &path, inode.clone())?;
+                inode
+            }
+            None => return Err(not_found()),
+        };
+
+        match &mut inode.lock().unwrap().kind {
+            Kind::File(data) if flags.truncate() => data.clear(),
+            Kind::File(_) => (),
+            _ => return Err(io::ErrorKind::IsADirectory.into()),
+        }
+
+        Ok(MemoryFile {
+            inode,
+            read: flags.read(),
+            write: flags.write() || flags.append(),
+            append: flags.append(),
+        })
+    }
+
+    async fn read_at(&self, file: &Self::File, offset: u64, len: u32) -> io::Result<Vec<u8>> {
+        if !file.read {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+
+        let mut inode = file.inode.lock().unwrap();
+        inode.atime = utils::unix(SystemTime::now()).0;
+        let Kind::File(data) = &inode.kind else {
+            return Err(not_found());
+        };
+
+        let start = (offset as usize).min(data.len());
+        let end = start.saturating_add(len as usize).min(data.len());
+        Ok(data[start..end].to_vec())
+    }
+
+    async fn write_at(&self, file: &Self::File, offset: u64, buf: &[u8]) -> io::Result<()> {
+        if !file.write {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+
+        let mut inode = file.inode.lock().unwrap();
+        inode.mtime = utils::unix(SystemTime::now()).0;
+        let Kind::File(data) = &mut inode.kind else {
+            return Err(not_found());
+        };
+
+        let start = match file.append {
+            true => data.len() as u64,
+            false => offset,
+        };
+        let end = start
+            .checked_add(buf.len() as u64)
+            .ok_or(io::ErrorKind::StorageFull)?;
+        if (data.len() as u64) < end {
+            resize(data, end)?;
+        }
+        data[start as usize..end as usize].copy_from_slice(buf);
+
+        Ok(())
+    }
+
+    async fn fstat(&self, file: &Self::File) -> io::Result<FileAttributes> {
+        Ok(file.inode.lock().unwrap().attrs())
+    }
+
+    async fn fsetstat(&self, file: &Self::File, attrs: &FileAttributes) -> io::Result<()> {
+        file.inode.lock().unwrap().apply(attrs)
+    }
+
+    async fn stat(&self, path: &str) -> io::Result<FileAttributes> {
+        let tree = self.tree();
+        let inode = get(&tree, &resolve(&tree, path)?)?;
+        let attrs = inode.lock().unwrap().attrs();
+        Ok(attrs)
+    }
+
+    async fn lstat(&self, path: &str) -> io::Result<FileAttributes> {
+        let inode = get(&self.tree(), &normalize(path))?;
+        let attrs = inode.lock().unwrap().attrs();
+        Ok(attrs)
+    }
+
+    async fn setstat(&self, path: &str, attrs: &FileAttributes) -> io::Result<()> {
+        let tree = self.tree();
+        get(&tree, &resolve(&tree, path)?)?
+            .lock()
+            .unwrap()
+            .apply(attrs)
+    }
+
+    async fn opendir(&self, path: &str) -> io::Result<Self::Dir> {
+        let tree = self.tree();
+        let path = resolve(&tree, path)?;
+        if !matches!(get(&tree, &path)?.lock().unwrap().kind, Kind::Dir) {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+
+        let entries = tree
+            .iter()
+            .filter(|(name, _)| **name != path && parent(name) == path)
+            .map(|(name, inode)| {
+                (
+                    name[name.rfind('/').unwrap() + 1..].to_string(),
+                    inode.clone(),
+                )
+            })
+            .collect();
+        Ok(MemoryDir {
+            entries: Mutex::new(entries),
+        })
+    }
+
+    async fn readdir(&self, dir: &Self::Dir) -> io::Result<Vec<File>> {
+        let entries = std::mem::take(&mut *dir.entries.lock().unwrap());
+        Ok(entries
+            .into_iter()
+            .map(|(filename, inode)| File {
+                filename,
+                longname: String::new(),
+                attrs: inode.lock().unwrap().attrs(),
+            })
+            .collect())
+    }
+
+    async fn remove(&self, path: &str) -> io::Result<()> {
+        let mut tree = self.tree();
+        let path = normalize(path);
+        if matches!(get(&tree, &path)?.lock().unwrap().kind, Kind::Dir) {
+            return Err(io::ErrorKind::IsADirectory.into());
+        }
+
+        tree.remove(&path);
+        Ok(())
+    }
+
+    async fn mkdir(&self, path: &str, attrs: &FileAttributes) -> io::Result<()> {
+        let inode = Inode::new(Kind::Dir, attrs.permissions.unwrap_or(0o755));
+        inode.lock().unwrap().apply(attrs)?;
+        insert(&mut self.tree(), &normalize(path), inode)
+    }
+
+    async fn rmdir(&self, path: &str) -> io::Result<()> {
+        let mut tree = self.tree();
+        let path = normalize(path);
+        if !matches!(get(&tree, &path)?.lock().unwrap().kind, Kind::Dir) {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+        if path == "/" || tree.keys().any(|name| parent(name) == path && name != "/") {
+            return Err(io::ErrorKind::DirectoryNotEmpty.into());
+        }
+
+        tree.remove(&path);
+        Ok(())
+    }
+
+    async fn realpath(&self, path: &str) -> io::Result<String> {
+        let tree = self.tree();
+        let path = resolve(&tree, path)?;
+        get(&tree, &path)?;
+        Ok(path)
+    }
+
+    async fn rename(&self, oldpath: &str, newpath: &str) -> io::Result<()> {
+        rename(&mut self.tree(), &normalize(oldpath), &normalize(newpath))
+    }
+
+    async fn readlink(&self, path: &str) -> io::Result<String> {
+        match &get(&self.tree(), &normalize(path))?.lock().unwrap().kind {
+            Kind::Symlink(target) => Ok(target.clone()),
+            _ => Err(io::ErrorKind::InvalidInput.into()),
+        }
+    }
+
+    async fn symlink(&self, linkpath: &str, targetpath: &str) -> io::Result<()> {
+        let inode = Inode::new(Kind::Symlink(targetpath.to_string()), 0o777);
+        insert(&mut self.tree(), &normalize(linkpath), inode)
+    }
+
+    async fn posix_rename(&self, oldpath: &str, newpath: &str) -> io::Result<()> {
+        let mut tree = self.tree();
+        let (oldpath, newpath) = (normalize(oldpath), normalize(newpath));
+        get(&tree, &oldpath)?;
+        if oldpath == newpath {
+            return Ok(());
+        }
+
+        if let Some(existing) = tree.get(&newpath) {
+            if matches!(existing.lock().unwrap().kind, Kind::Dir) {
+                return Err(io::ErrorKind::IsADirectory.into());
+            }
+            let existing = tree.remove(&newpath).unwrap();
+            if let Err(e) = rename(&mut tree, &oldpath, &newpath) {
+                tree.insert(newpath, existing);
+                return Err(e);
+            }
+            return Ok(());
+        }
+
+        rename(&mut tree, &oldpath, &newpath)
+    }
+
+    async fn hardlink(&self, oldpath: &str, newpath: &str) -> io::Result<()> {
+        let mut tree = self.tree();
+        let inode = get(&tree, &normalize(oldpath))?;
+        if matches!(inode.lock().unwrap().kind, Kind::Dir) {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+
+        insert(&mut tree, &normalize(newpath), inode)
+    }
+
+    async fn lsetstat(&self, path: &str, attrs: &FileAttributes) -> io::Result<()> {
+        get(&self.tree(), &normalize(path))?
+            .lock()
+            .unwrap()
+            .apply(attrs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io;
+    use tokio_stream::StreamExt;
+
+    use super::*;
+    use crate::{
+        client::SftpSession,
+        protocol::{types::*, StatusCode},
+        server::{self, vfs::VfsHandler},
+    };
+
+    async fn session() -> SftpSession {
+        let (client, server) = io::duplex(4096);
+        let handler = VfsHandler::new(MemoryFs::default());
+        tokio::spawn(server::run_concurrent(server, handler, Default::default()));
+        SftpSession::new(client).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_read_write() {
+        let sftp = session().await;
+        let handle = sftp
+            .open(
+                "/file",
+                OpenFlags::CREATE | OpenFlags::WRITE | OpenFlags::READ,
+                FileAttributes::default(),
+            )
+            .await
+            .unwrap();
+
+        sftp.write(handle.clone(), 0, b"hello world".to_vec())
+            .await
+            .unwrap();
+        sftp.write(handle.clone(), 6, b"there".to_vec())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            sftp.read(handle.clone(), 0, 64).await.unwrap(),
+            &b"hello there"[..]
+        );
+        assert_eq!(sftp.read(handle.clone(), 6, 3).await.unwrap(), &b"the"[..]);
+        assert_eq!(
+            sftp.read(handle.clone(), 11, 64).await,
+            Err(StatusCode::Eof)
+        );
+        assert_eq!(sftp.fstat(handle.clone()).await.unwrap().size, Some(11));
+
+        //sizes sent by the client are bounded
+        for offset in [MAX_FILE_SIZE, u64::MAX] {
+            assert_eq!(
+                sftp.write(handle.clone(), offset, b"x".to_vec()).await,
+                Err(StatusCode::NoSpaceOnFilesystem)
+            );
+        }
+        let attrs = FileAttributes {
+            size: Some(u64::MAX),
+            ..FileAttributes::empty()
+        };
+        assert_eq!(
+            sftp.fsetstat(handle.clone(), attrs).await,
+            Err(StatusCode::NoSpaceOnFilesystem)
+        );
+        assert_eq!(sftp.fstat(handle.clone()).await.unwrap().size, Some(11));
+
+        sftp.close(handle.clone()).await.unwrap();
+        assert_eq!(sftp.close(handle).await, Err(StatusCode::InvalidHandle));
+    }
+
+    #[tokio::test]
+    async fn test_open_flags() {
+        let sftp = session().await;
+        assert_eq!(
+            sftp.open("/missing", OpenFlags::READ, FileAttributes::default())
+                .await,
+            Err(StatusCode::NoSuchFile)
+        );
+
+        let handle = sftp
+            .open(
+                "/file",
+                OpenFlags::CREATE | OpenFlags::WRITE,
+                FileAttributes::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            sftp.read(handle, 0, 1).await,
+            Err(StatusCode::PermissionDenied)
+        );
+        assert_eq!(
+            sftp.open(
+                "/file",
+                OpenFlags::CREATE | OpenFlags::EXCLUDE | OpenFlags::WRITE,
+                FileAttributes::default()
+            )
+            .await,
+            Err(StatusCode::FileAlreadyExists)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_directories() {
+        let sftp = session().await;
+        sftp.mkdir("/dir", FileAttributes::default()).await.unwrap();
+        sftp.mkdir("/dir/sub", FileAttributes::default())
+            .await
+            .unwrap();
+        let handle = sftp
+            .open(
+                "dir/./file",
+                OpenFlags::CREATE | OpenFlags::WRITE,
+                FileAttributes::default(),
+            )
+            .await
+            .unwrap();
+        sftp.close(handle).await.unwrap();
+
+        let mut names = sftp
+            .read_dir("/dir")
+            .await
+            .unwrap()
+            .map(|f| f.unwrap().filename)
+            .collect::<Vec<_>>()
+            .await;
+        names.sort();
+        assert_eq!(names, ["file", "sub"]);
+
+        let handle = sftp.opendir("/dir").await.unwrap();
+        assert_eq!(sftp.readdir(handle.clone()).await.unwrap().len(), 2);
+        assert_eq!(sftp.readdir(handle).await.unwrap_err(), StatusCode::Eof);
+
+        assert_eq!(sftp.rmdir("/dir").await, Err(StatusCode::DirNotEmpty));
+        sftp.rename("/dir", "/moved").await.unwrap();
+        assert!(sftp.stat("/moved/sub").await.unwrap().is_dir());
+        assert_eq!(
+            sftp.stat("/dir/file").await.unwrap_err(),
+            StatusCode::NoSuchFile
+        );
+
+        sftp.remove("/moved/file").await.unwrap();
+        sftp.rmdir("/moved/sub").await.unwrap();
+        sftp.rmdir("/moved").await.unwrap();
+        assert_eq!(
+            sftp.read_dir("/")
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await
+                .len(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_readdir_batches() {
+        let sftp = session().await;
+        let names = (0..3000)
+            .map(|i| format!("{:0>200}", i))
+            .collect::<Vec<_>>();
+        for name in &names {
+            let handle = sftp
+                .open(
+                    name.as_str(),
+                    OpenFlags::CREATE | OpenFlags::WRITE,
+                    FileAttributes::default(),
+                )
+                .await
+                .unwrap();
+            sftp.close(handle).await.unwrap();
+        }
+
+        let handle = sftp.opendir("/").await.unwrap();
+        let mut batches = 0;
+        let mut listed = Vec::new();
+        loop {
+            match sftp.readdir(handle.as_str()).await {
+                Ok(files) => {
+                    assert!(!files.is_empty());
+                    batches += 1;
+                    listed.extend(files.into_iter().map(|f| f.filename));
+                }
+                Err(StatusCode::Eof) => break,
+                Err(err) => panic!("{}", err),
+            }
+        }
+        sftp.close(handle).await.unwrap();
+
+        assert!(batches > 1);
+        listed.sort();
+        assert_eq!(listed, names);
+
+        let mut stream = sftp.read_dir("/").await.unwrap();
+        assert!(stream.next().await.unwrap().is_ok());
+        drop(stream);
+        assert_eq!(
+            sftp.read_dir("/")
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await
+                .len(),
+            3000
+        );
+    }
+
+    #[tokio::test]
+    async fn test_symlinks() {
+        let sftp = session().await;
+        sftp.mkdir("/dir", FileAttributes::default()).await.unwrap();
+        sftp.symlink("/link", "dir").await.unwrap();
+
+        assert_eq!(sftp.readlink("/link").await.unwrap(), "dir");
+        assert!(sftp.stat("/link").await.unwrap().is_dir());
+        assert!(sftp.lstat("/link").await.unwrap().is_symlink());
+        assert_eq!(sftp.realpath("/link/../link").await.unwrap(), "/dir");
+
+        sftp.link("/link2", "dir", true).await.unwrap();
+        assert_eq!(sftp.readlink("/link2").await.unwrap(), "dir");
+        assert_eq!(
+            sftp.link("/hard", "/dir", false).await.unwrap_err(),
+            StatusCode::PermissionDenied
+        );
+
+        let handle = sftp
+            .open(
+                "/file",
+                OpenFlags::CREATE | OpenFlags::WRITE,
+                FileAttributes::default(),
+            )
+            .await
+            .unwrap();
+        sftp.link("/hard", "/file", false).await.unwrap();
+        sftp.write(handle.as_str(), 0, &b"data"[..]).await.unwrap();
+        sftp.close(handle).await.unwrap();
+        let attrs = sftp.lstat("/hard").await.unwrap();
+        assert!(attrs.is_regular());
+        assert_eq!(attrs.size, Some(4));
+    }
+
+    #[tokio::test]
+    async fn test_extensions() {
+        let sftp = session().await;
+        assert!(sftp.extensions().contains_key(PosixRename::NAME));
+
+        let handle = sftp
+            .open(
+                "/a",
+                OpenFlags::WRITE | OpenFlags::CREATE,
+                FileAttributes::default(),
+            )
+            .await
+            .unwrap();
+        sftp.write(handle.as_str(), 0, b"hello".to_vec())
+            .await
+            .unwrap();
+        sftp.fsync(handle.as_str()).await.unwrap();
+        sftp.close(handle).await.unwrap();
+
+        sftp.hardlink("/a", "/b").await.unwrap();
+        sftp.symlink("/c", "/b").await.unwrap();
+        sftp.posix_rename("/b", "/c").await.unwrap();
+        assert!(!sftp.lstat("/c").await.unwrap().is_symlink());
+
+        let source = sftp
+            .open("/c", OpenFlags::READ, FileAttributes::default())
+            .await
+            .unwrap();
+        let destination = sftp
+            .open(
+                "/d",
+                OpenFlags::WRITE | OpenFlags::CREATE,
+                FileAttributes::default(),
+            )
+            .await
+            .unwrap();
+        sftp.copy_data(source.as_str(), 1, 0, destination.as_str(), 0)
+            .await
+            .unwrap();
+        assert_eq!(sftp.stat("/d").await.unwrap().size, Some(4));
+
+        let limits = sftp.limits().await.unwrap();
+        assert!(limits.max_read_length > 0);
+        assert_eq!(sftp.expand_path("~/d").await.unwrap(), "/d");
+        assert_eq!(
+            sftp.statvfs("/").await.unwrap_err(),
+            StatusCode::OpUnsupported
+        );
+    }
+}
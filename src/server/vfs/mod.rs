@@ -0,0 +1,437 @@
+mod handler;
+#[cfg(feature = "impls")]
+mod local;
+#[cfg(feature = "impls")]
+mod memory;
+mod users;
+
+use std::io;
+
+use crate::protocol::types::{File, FileAttributes, OpenFlags, StatvfsReply};
+
+#[cfg(unix)]
+pub use self::users::SystemUsers;
+pub use self::{
+    handler::VfsHandler,
+    users::{NumericIds, UserLookup},
+};
+#[cfg(feature = "impls")]
+pub use self::{
+    local::{LocalDir, LocalFile, LocalFs},
+    memory::{MemoryDir, MemoryFile, MemoryFs},
+};
+
+/// Storage behind [`VfsHandler`]. This is `async_trait`
+///
+/// Paths are passed exactly as received from the client. Failures are
+/// reported as [`io::Error`] and converted to a status code by the handler,
+/// so for example [`io::ErrorKind::NotFound`] becomes `SSH_FX_NO_SUCH_FILE`.
+#[async_trait]
+pub trait Vfs: Send + Sync + 'static {
+    /// An open file. It may be used by several requests
+    /// at the same time, so it is only borrowed immutably
+    type File: Send + Sync + 'static;
+
+    /// An open directory, listed by [`Vfs::readdir`]
+    type Dir: Send + Sync + 'static;
+
+    /// Opens or creates a file
+    async fn open(
+        &self,
+        path: &str,
+        flags: OpenFlags,
+        attrs: &FileAttributes,
+    ) -> io::Result<Self::File>;
+
+    /// Called when the handle of the file is closed
+    #[allow(unused_variables)]
+    async fn close(&self, file: &Self::File) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Reads up to `len` bytes at `offset`, fewer only at the end of the file.
+    /// An empty result means the end of the file. Concurrent reads and
+    /// writes on one file must not share a cursor
+    async fn read_at(&self, file: &Self::File, offset: u64, len: u32) -> io::Result<Vec<u8>>;
+
+    /// Writes all of `data` at `offset`, leaving a hole of zeros when it is
+    /// past the end of the file. Files opened with `APPEND` are written
+    /// at their end whatever the offset
+    async fn write_at(&self, file: &Self::File, offset: u64, data: &[u8]) -> io::Result<()>;
+
+    /// Attributes of an open file
+    async fn fstat(&self, file: &Self::File) -> io::Result<FileAttributes>;
+
+    /// Applies attributes to an open file
+    async fn fsetstat(&self, file: &Self::File, attrs: &FileAttributes) -> io::Result<()>;
+
+    /// Attributes of a path, following symbolic links
+    async fn stat(&self, path: &str) -> io::Result<FileAttributes>;
+
+    /// Attributes of a path, without following symbolic links
+    async fn lstat(&self, path: &str) -> io::Result<FileAttributes>;
+
+    /// Applies attributes to a path
+    async fn setstat(&self, path: &str, attrs: &FileAttributes) -> io::Result<()>;
+
+    /// Opens a directory to list its entries
+    async fn opendir(&self, path: &str) -> io::Result<Self::Dir>;
+
+    /// Next entries of an open directory with their attributes, as many as
+    /// the backend likes at a time. An empty result means the end of the
+    /// directory, so large directories are neither read nor stat-ed at once
+    async fn readdir(&self, dir: &Self::Dir) -> io::Result<Vec<File>>;
+
+    /// Removes a file
+    async fn remove(&self, path: &str) -> io::Result<()>;
+
+    /// Creates a directory
+    async fn mkdir(&self, path: &str, attrs: &FileAttributes) -> io::Result<()>;
+
+    /// Removes an empty directory
+    async fn rmdir(&self, path: &str) -> io::Result<()>;
+
+    /// Canonical absolute form of a path, which must exist
+    async fn realpath(&self, path: &str) -> io::Result<String>;
+
+    /// Renames a file or directory
+    async fn rename(&self, oldpath: &str, newpath: &str) -> io::Result<()>;
+
+    /// Target of a symbolic link
+    async fn readlink(&self, path: &str) -> io::Result<String>;
+
+    /// Creates a symbolic link at `linkpath` pointing to `targetpath`
+    async fn symlink(&self, linkpath: &str, targetpath: &str) -> io::Result<()>;
+
+    /// Renames a file or directory, replacing `newpath` if it exists
+    #[allow(unused_variables)]
+    async fn posix_rename(&self, oldpath: &str, newpath: &str) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Creates a hard link at `newpath` to `oldpath`
+    #[allow(unused_variables)]
+    async fn hardlink(&self, oldpath: &str, newpath: &str) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Flushes an open file to storage
+    #[allow(unused_variables)]
+    async fn fsync(&self, file: &Self::File) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Applies attributes to a path, without following symbolic links
+    #[allow(unused_variables)]
+    async fn lsetstat(&self, path: &str, attrs: &FileAttributes) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Statistics of the file system holding a path.
+    /// The id of the reply is set by the handler
+    #[allow(unused_variables)]
+    async fn statvfs(&self, path: &str) -> io::Result<StatvfsReply> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Statistics of the file system holding an open file.
+    /// The id of the reply is set by the handler
+    #[allow(unused_variables)]
+    async fn fstatvfs(&self, file: &Self::File) -> io::Result<StatvfsReply> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Like [`Vfs::realpath`] with a leading `~` standing for the home
+    /// directory. The default takes the current directory as home
+    async fn expand_path(&self, path: &str) -> io::Result<String> {
+        match path.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                self.realpath(&format!(".{}", rest)).await
+            }
+            _ => self.realpath(path).await,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "impls"))]
+mod test {
+    use tokio::io;
+
+    use super::*;
+    use crate::{
+        client::SftpSession,
+        protocol::{StatusCode, MAX_PACKET_LEN},
+        server,
+        test_util::TempDir,
+    };
+
+    async fn session<V: Vfs>(handler: VfsHandler<V>) -> SftpSession {
+        let (client, server) = io::duplex(4096);
+        tokio::spawn(server::run(server, handler));
+        SftpSession::new(client).await.unwrap()
+    }
+
+    /// Offsets of reads and writes as OpenSSH handles them
+    async fn check_offsets(sftp: SftpSession) {
+        let flags = OpenFlags::READ | OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        let handle = sftp
+            .open("/file", flags, FileAttributes::empty())
+            .await
+            .unwrap();
+        let read = |offset, len| sftp.read(handle.clone(), offset, len);
+
+        //past the end leaves a hole of zeros
+        sftp.write(handle.clone(), 4, &b"data"[..]).await.unwrap();
+        assert_eq!(read(0, 64).await.unwrap(), &b"\0\0\0\0data"[..]);
+        sftp.write(handle.clone(), 2, &b"xy"[..]).await.unwrap();
+        assert_eq!(read(0, 64).await.unwrap(), &b"\0\0xydata"[..]);
+
+        //short at the end, then Eof only once nothing is left
+        assert_eq!(read(6, 64).await.unwrap(), &b"ta"[..]);
+        assert_eq!(read(8, 64).await, Err(StatusCode::Eof));
+        assert_eq!(read(1 << 40, 1).await, Err(StatusCode::Eof));
+
+        //no shared cursor between requests on one handle
+        let (a, b, c) = tokio::join!(read(4, 2), read(0, 4), read(6, 2));
+        assert_eq!(a.unwrap(), &b"da"[..]);
+        assert_eq!(b.unwrap(), &b"\0\0xy"[..]);
+        assert_eq!(c.unwrap(), &b"ta"[..]);
+        assert_eq!(sftp.fstat(handle.clone()).await.unwrap().size, Some(8));
+
+        //appends ignore the offset
+        let append = sftp
+            .open(
+                "/file",
+                OpenFlags::WRITE | OpenFlags::APPEND,
+                FileAttributes::empty(),
+            )
+            .await
+            .unwrap();
+        sftp.write(append.clone(), 0, &b"tail"[..]).await.unwrap();
+        sftp.write(append.clone(), 2, &b"!"[..]).await.unwrap();
+        sftp.close(append).await.unwrap();
+        assert_eq!(read(0, 64).await.unwrap(), &b"\0\0xydatatail!"[..]);
+
+        sftp.close(handle).await.unwrap();
+    }
+
+    /// Canonical paths, which only exist for existing files
+    async fn check_realpath(sftp: SftpSession) {
+        sftp.mkdir("/dir", FileAttributes::empty()).await.unwrap();
+        sftp.symlink("/link", "dir").await.unwrap();
+
+        assert_eq!(sftp.realpath(".").await.unwrap(), "/");
+        assert_eq!(sftp.realpath("/dir/./../dir").await.unwrap(), "/dir");
+        assert_eq!(sftp.realpath("/link").await.unwrap(), "/dir");
+        for path in ["/missing", "/dir/missing", "/link/missing"] {
+            assert_eq!(sftp.realpath(path).await, Err(StatusCode::NoSuchFile));
+        }
+    }
+
+    /// Runs `check` on a [`LocalFs`] confined to an empty directory
+    async fn with_local<F, Fut>(name: &str, check: F)
+    where
+        F: FnOnce(SftpSession) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        let dir = TempDir::new(name);
+        let handler = VfsHandler::new(LocalFs::with_root(&dir).unwrap());
+        check(session(handler).await).await;
+    }
+
+    #[tokio::test]
+    async fn test_offsets_memory() {
+        check_offsets(session(VfsHandler::new(MemoryFs::default())).await).await;
+    }
+
+    #[tokio::test]
+    async fn test_offsets_local() {
+        with_local("offsets", check_offsets).await;
+    }
+
+    #[tokio::test]
+    async fn test_realpath_memory() {
+        check_realpath(session(VfsHandler::new(MemoryFs::default())).await).await;
+    }
+
+    #[tokio::test]
+    async fn test_realpath_local() {
+        with_local("realpath-shared", check_realpath).await;
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let sftp = session(VfsHandler::new(MemoryFs::default())).await;
+        let limits = sftp.limits().await.unwrap();
+        assert_eq!(limits.max_packet_length, u64::from(MAX_PACKET_LEN));
+
+        let (client, server) = io::duplex(4096);
+        let handler = VfsHandler::new(MemoryFs::default()).with_max_packet_len(8192);
+        let config = server::Config {
+            max_packet_len: 8192,
+            ..Default::default()
+        };
+        tokio::spawn(server::run_with_config(server, handler, config));
+        let sftp = SftpSession::new(client).await.unwrap();
+        let limits = sftp.limits().await.unwrap();
+        assert_eq!(limits.max_packet_length, 8192);
+        assert_eq!(limits.max_read_length, 8192 - 1024);
+        assert_eq!(limits.max_write_length, 8192 - 1024);
+
+        //a write of the announced length fits in a packet
+        let flags = OpenFlags::READ | OpenFlags::WRITE | OpenFlags::CREATE;
+        let handle = sftp
+            .open("/file", flags, FileAttributes::empty())
+            .await
+            .unwrap();
+        let data = vec![7; limits.max_write_length as usize];
+        sftp.write(handle.clone(), 0, data.clone()).await.unwrap();
+        let read = sftp.read(handle.clone(), 0, 8192).await.unwrap();
+        assert_eq!(read, data);
+        sftp.close(handle).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handles() {
+        let sftp = session(VfsHandler::new(MemoryFs::default()).with_max_handles(2)).await;
+        assert_eq!(sftp.limits().await.unwrap().max_open_handles, 2);
+
+        let flags = || OpenFlags::READ | OpenFlags::CREATE;
+        let file = sftp
+            .open("/file", flags(), FileAttributes::empty())
+            .await
+            .unwrap();
+        let dir = sftp.opendir("/").await.unwrap();
+        assert!(!file.contains("file") && file != dir);
+
+        //files and directories are kept apart
+        let readdir = sftp.readdir(file.as_str()).await;
+        assert_eq!(readdir.err(), Some(StatusCode::InvalidHandle));
+        let read = sftp.read(dir.as_str(), 0, 1).await;
+        assert_eq!(read, Err(StatusCode::InvalidHandle));
+
+        assert_eq!(
+            sftp.open("/other", flags(), FileAttributes::empty()).await,
+            Err(StatusCode::Failure)
+        );
+        //refused before the backend is reached
+        let stat = sftp.stat("/other").await;
+        assert_eq!(stat.err(), Some(StatusCode::NoSuchFile));
+        assert_eq!(sftp.opendir("/").await, Err(StatusCode::Failure));
+
+        sftp.close(dir.as_str()).await.unwrap();
+        let again = sftp.opendir("/").await.unwrap();
+        assert_ne!(again, dir);
+        let readdir = sftp.readdir(dir).await;
+        assert_eq!(readdir.err(), Some(StatusCode::InvalidHandle));
+    }
+
+    #[tokio::test]
+    async fn test_hash_extensions() {
+        use md5::Md5;
+        use sha2::{Digest, Sha256};
+
+        let sftp = session(VfsHandler::new(MemoryFs::default())).await;
+        let data = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+        let flags = OpenFlags::READ | OpenFlags::WRITE | OpenFlags::CREATE;
+        let handle = sftp
+            .open("/file", flags, FileAttributes::empty())
+            .await
+            .unwrap();
+        sftp.write(handle.as_str(), 0, data.clone()).await.unwrap();
+
+        let reply = sftp
+            .check_file_name("/file", "crc32,sha256,md5", 0, 0, 0)
+            .await
+            .unwrap();
+        assert_eq!(reply.hash_algorithm, "sha256");
+        assert_eq!(reply.hashes, Sha256::digest(&data).to_vec());
+
+        //blocks of 256 bytes, the last one shorter
+        let reply = sftp
+            .check_file_handle(handle.as_str(), "md5", 100, 800, 256)
+            .await
+            .unwrap();
+        let blocks = data[100..900]
+            .chunks(256)
+            .flat_map(|block| Md5::digest(block).to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(reply.hashes, blocks);
+
+        let small = sftp.check_file_name("/file", "md5", 0, 0, 100).await;
+        assert_eq!(small.err(), Some(StatusCode::Failure));
+        let unknown = sftp.check_file_name("/file", "crc32", 0, 0, 0).await;
+        assert_eq!(unknown.err(), Some(StatusCode::OpUnsupported));
+
+        //the range is shorter than the 2048 bytes of the quick check
+        let md5 = Md5::digest(&data[10..]).to_vec();
+        assert_eq!(
+            sftp.md5_hash("/file", 10, 0, md5.clone()).await.unwrap(),
+            md5
+        );
+        assert_eq!(
+            sftp.md5_hash_handle(handle.as_str(), 10, 0, vec![0; 16])
+                .await
+                .unwrap(),
+            Vec::<u8>::new()
+        );
+        let missing = sftp.md5_hash("/missing", 0, 0, Vec::new()).await;
+        assert_eq!(missing.err(), Some(StatusCode::NoSuchFile));
+
+        //the hashes of every block must fit in the reply
+        let attrs = FileAttributes {
+            size: Some(8 << 20),
+            ..FileAttributes::empty()
+        };
+        sftp.setstat("/file", attrs).await.unwrap();
+        let many = sftp.check_file_name("/file", "sha256", 0, 0, 256).await;
+        assert_eq!(many.err(), Some(StatusCode::Failure));
+        let reply = sftp
+            .check_file_name("/file", "sha256", 0, 0, 1 << 20)
+            .await
+            .unwrap();
+        assert_eq!(reply.hashes.len(), 8 * 32);
+    }
+
+    #[tokio::test]
+    async fn test_read_only() {
+        let fs = MemoryFs::default();
+        let sftp = session(VfsHandler::new(fs.clone())).await;
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE;
+        let handle = sftp
+            .open("/file", flags, FileAttributes::empty())
+            .await
+            .unwrap();
+        sftp.write(handle.as_str(), 0, &b"data"[..]).await.unwrap();
+        sftp.close(handle).await.unwrap();
+
+        let sftp = session(VfsHandler::new(fs).with_read_only(true)).await;
+        let handle = sftp
+            .open("/file", OpenFlags::READ, FileAttributes::empty())
+            .await
+            .unwrap();
+        assert_eq!(
+            sftp.read(handle.as_str(), 0, 64).await.unwrap(),
+            &b"data"[..]
+        );
+
+        let denied = Err(StatusCode::PermissionDenied);
+        let write = sftp.open(
+            "/file",
+            OpenFlags::READ | OpenFlags::WRITE,
+            FileAttributes::empty(),
+        );
+        assert_eq!(write.await.map(|_| ()), denied);
+        let attrs = FileAttributes {
+            permissions: Some(0o600),
+            ..FileAttributes::empty()
+        };
+        assert_eq!(sftp.fsetstat(handle.as_str(), attrs.clone()).await, denied);
+        assert_eq!(sftp.setstat("/file", attrs).await, denied);
+        assert_eq!(sftp.mkdir("/dir", FileAttributes::empty()).await, denied);
+        assert_eq!(sftp.rename("/file", "/other").await, denied);
+        assert_eq!(sftp.symlink("/link", "/file").await, denied);
+        assert_eq!(sftp.remove("/file").await, denied);
+        assert_eq!(sftp.stat("/file").await.unwrap().size, Some(4));
+    }
+}
@@ -0,0 +1,112 @@
+#[cfg(unix)]
+use std::{collections::HashMap, ffi::CStr, sync::Mutex};
+
+use crate::protocol::types::FileAttributes;
+
+/// Names of users and groups by id. They are shown in `longname`
+/// and sent as owner and group since version 4
+pub trait UserLookup: Send + Sync + 'static {
+    /// Name of the user `uid`, if known
+    fn user(&self, uid: u32) -> Option<String>;
+
+    /// Name of the group `gid`, if known
+    fn group(&self, gid: u32) -> Option<String>;
+
+    /// Fills in `user` and `group` of `attrs` when they are missing
+    fn fill(&self, attrs: &mut FileAttributes) {
+        if attrs.user.is_none() {
+            attrs.user = attrs.uid.and_then(|uid| self.user(uid));
+        }
+
+        if attrs.group.is_none() {
+            attrs.group = attrs.gid.and_then(|gid| self.group(gid));
+        }
+    }
+}
+
+/// Leaves users and groups as numbers
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NumericIds;
+
+impl UserLookup for NumericIds {
+    fn user(&self, _uid: u32) -> Option<String> {
+        None
+    }
+
+    fn group(&self, _gid: u32) -> Option<String> {
+        None
+    }
+}
+
+/// Names from the user and group databases of the system,
+/// remembered after the first lookup
+#[cfg(unix)]
+#[derive(Debug, Default)]
+pub struct SystemUsers {
+    users: Mutex<HashMap<u32, Option<String>>>,
+    groups: Mutex<HashMap<u32, Option<String>>>,
+}
+
+/// Calls a reentrant lookup such as `getpwuid_r` with a buffer
+/// grown until the entry fits, and reads the name from the entry
+#[cfg(unix)]
+fn lookup<T>(
+    call: impl Fn(*mut T, *mut libc::c_char, usize, *mut *mut T) -> libc::c_int,
+    name: impl Fn(&T) -> *const libc::c_char,
+) -> Option<String> {
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut entry = std::mem::MaybeUninit::<T>::uninit();
+        let mut result = std::ptr::null_mut();
+        match call(
+            entry.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        ) {
+            libc::ERANGE if buffer.len() < 1024 * 1024 => buffer.resize(buffer.len() * 2, 0),
+            0 if !result.is_null() => {
+                // SAFETY: on success `result` points to `entry`, whose
+                // strings live in `buffer` and are nul-terminated
+                let name = unsafe { CStr::from_ptr(name(&*result)) };
+                return Some(name.to_string_lossy().into_owned());
+            }
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl UserLookup for SystemUsers {
+    fn user(&self, uid: u32) -> Option<String> {
+        let mut users = self.users.lock().unwrap_or_else(|e| e.into_inner());
+        users
+            .entry(uid)
+            .or_insert_with(|| {
+                lookup(
+                    // SAFETY: the pointers are valid for the lengths given
+                    |entry, buffer, len, result| unsafe {
+                        libc::getpwuid_r(uid, entry, buffer, len, result)
+                    },
+                    |entry: &libc::passwd| entry.pw_name,
+                )
+            })
+            .clone()
+    }
+
+    fn group(&self, gid: u32) -> Option<String> {
+        let mut groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        groups
+            .entry(gid)
+            .or_insert_with(|| {
+                lookup(
+                    // SAFETY: the pointers are valid for the lengths given
+                    |entry, buffer, len, result| unsafe {
+                        libc::getgrgid_r(gid, entry, buffer, len, result)
+                    },
+                    |entry: &libc::group| entry.gr_name,
+                )
+            })
+            .clone()
+    }
+}
@@ -1 +0,0 @@
-pub mod file;
\ No newline at end of file
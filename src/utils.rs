@@ -1,6 +1,13 @@
-use chrono::{DateTime, Utc};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub fn unix(time: SystemTime) -> u32 {
-    DateTime::<Utc>::from(time).timestamp() as u32
+/// Seconds and nanoseconds since the unix epoch, zero for earlier times
+pub fn unix(time: SystemTime) -> (u64, u32) {
+    time.duration_since(UNIX_EPOCH)
+        .map_or((0, 0), |time| (time.as_secs(), time.subsec_nanos()))
+}
+
+/// Time from seconds and nanoseconds since the unix epoch,
+/// `None` if it cannot be represented
+pub fn system_time(seconds: u64, nanoseconds: u32) -> Option<SystemTime> {
+    UNIX_EPOCH.checked_add(Duration::new(seconds, nanoseconds.min(999_999_999)))
 }
@@ -0,0 +1,131 @@
+//! Client sessions over child processes serving SFTP on their standard
+//! input and output
+
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use russh_sftp::{
+    client::SftpSession,
+    protocol::{
+        types::{FileAttributes, OpenFlags},
+        StatusCode,
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::Command,
+};
+
+#[path = "../src/test_util.rs"]
+mod test_util;
+
+use test_util::TempDir;
+
+/// Locations of OpenSSH's sftp-server, after the `SFTP_SERVER` variable
+const OPENSSH_SFTP_SERVER: &[&str] = &[
+    "/usr/lib/openssh/sftp-server",
+    "/usr/libexec/openssh/sftp-server",
+    "/usr/libexec/sftp-server",
+    "/usr/lib/ssh/sftp-server",
+];
+
+fn command(program: impl AsRef<Path>, dir: &Path) -> Command {
+    let mut command = Command::new(program.as_ref());
+    command.current_dir(dir).stderr(Stdio::null());
+    command
+}
+
+/// Requests whose results do not depend on the server, relative to its directory
+async fn exercise(sftp: &SftpSession, dir: &Path) {
+    let dir = std::fs::canonicalize(dir).unwrap();
+    assert_eq!(sftp.realpath(".").await.unwrap(), dir.to_string_lossy());
+
+    let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+    let mut file = sftp
+        .open_file("file", flags, FileAttributes::empty())
+        .await
+        .unwrap();
+    let data = (0..100_000).map(|i| i as u8).collect::<Vec<_>>();
+    file.write_all(&data).await.unwrap();
+    file.shutdown().await.unwrap();
+    assert_eq!(std::fs::read(dir.join("file")).unwrap(), data);
+
+    let mut file = sftp
+        .open_file("file", OpenFlags::READ, FileAttributes::empty())
+        .await
+        .unwrap();
+    let mut read = Vec::new();
+    file.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, data);
+
+    sftp.mkdir("dir", FileAttributes::empty()).await.unwrap();
+    sftp.rename("file", "dir/moved").await.unwrap();
+    let attrs = sftp.stat("dir/moved").await.unwrap();
+    assert_eq!(attrs.size, Some(data.len() as u64));
+    assert!(attrs.is_regular());
+
+    let handle = sftp.opendir("dir").await.unwrap();
+    let names = sftp
+        .readdir(handle.as_str())
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|file| file.filename)
+        .filter(|name| name != "." && name != "..")
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["moved"]);
+    sftp.close(handle).await.unwrap();
+
+    assert_eq!(sftp.stat("file").await.err(), Some(StatusCode::NoSuchFile));
+    sftp.remove("dir/moved").await.unwrap();
+    sftp.rmdir("dir").await.unwrap();
+}
+
+#[tokio::test]
+async fn test_own_server() {
+    let dir = TempDir::new("process-own");
+    let server = env!("CARGO_BIN_EXE_russh-sftp-server");
+
+    let (sftp, mut child) = SftpSession::spawn(&mut command(server, &dir), Default::default())
+        .await
+        .unwrap();
+    exercise(&sftp, &dir).await;
+
+    //EOF on its input ends the server
+    drop(sftp);
+    assert!(child.wait().await.unwrap().success());
+
+    let (sftp, mut child) = SftpSession::spawn(command(server, &dir).arg("-R"), Default::default())
+        .await
+        .unwrap();
+    let denied = sftp.mkdir("dir", FileAttributes::empty()).await;
+    assert_eq!(denied, Err(StatusCode::PermissionDenied));
+    drop(sftp);
+    assert!(child.wait().await.unwrap().success());
+}
+
+#[tokio::test]
+#[ignore = "needs OpenSSH's sftp-server, found at SFTP_SERVER or a usual location"]
+async fn test_openssh_server() {
+    let server = std::env::var_os("SFTP_SERVER")
+        .map(PathBuf::from)
+        .or_else(|| {
+            OPENSSH_SFTP_SERVER
+                .iter()
+                .map(PathBuf::from)
+                .find(|path| path.exists())
+        });
+    let server = server.expect("OpenSSH sftp-server not found, set SFTP_SERVER");
+
+    let dir = TempDir::new("process-openssh");
+    let (sftp, mut child) = SftpSession::spawn(&mut command(server, &dir), Default::default())
+        .await
+        .unwrap();
+    assert_eq!(sftp.version(), 3);
+    exercise(&sftp, &dir).await;
+
+    drop(sftp);
+    assert!(child.wait().await.unwrap().success());
+}